@@ -1,48 +1,102 @@
-use std::time::SystemTime;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 
 use tauri_plugin_dialog::DialogExt;
-use xlab_core::{options::RecordingState, record::SaveProgress, PreviousRecording};
+use xlab_core::{
+    audio::{AudioCodec, AudioDevice, AudioInput, AudioMix},
+    capture::{CaptureRegion, MonitorInfo, WindowInfo},
+    edit::{AudioOverlay, Crop, ScaleFilter, SpeedChange, TimeRange, Transition},
+    export::OutputFormat,
+    options::{EncodeMode, RecordingState},
+    record::SaveProgress,
+    video::{Container, QualityPreset, QualitySettings, VideoCodec},
+    zoom::ZoomSettings,
+    EditJob, PreviousRecording, XlabError,
+};
+
+/// Edits of previous recordings that are running, by the id the frontend gave them
+static EDIT_JOBS: Mutex<BTreeMap<u32, EditJob>> = Mutex::new(BTreeMap::new());
 
 #[tauri::command]
-pub fn recording_state() -> RecordingState {
-    xlab_core::record::get_options()
-        .lock()
-        .unwrap()
-        .recording_state()
+pub fn recording_state() -> Result<RecordingState, XlabError> {
+    let recorder = xlab_core::record::default_recorder();
+    if let Some(err) = recorder.take_error() {
+        return Err(err);
+    }
+    Ok(recorder.recording_state())
+}
+
+#[tauri::command]
+pub fn start_recording() -> Result<(), XlabError> {
+    xlab_core::record::record()
+}
+
+/// Stops the recording. Streamed recordings wait for the encoder to flush, which can
+/// take seconds, so this runs off the main thread.
+#[tauri::command(async)]
+pub fn stop_recording() -> Result<(), XlabError> {
+    xlab_core::record::stop()
 }
 
 #[tauri::command]
-pub fn start_recording() {
-    xlab_core::record::record();
+pub fn pause_recording() -> Result<(), XlabError> {
+    xlab_core::record::pause()
 }
 
 #[tauri::command]
-pub fn stop_recording() {
-    xlab_core::record::stop();
+pub fn resume_recording() -> Result<(), XlabError> {
+    xlab_core::record::resume()
 }
 
+/// Mutes or unmutes an audio input, also while recording
 #[tauri::command]
-pub fn save_recording() {
-    let save_at_chosen_loc = |save_fn: Box<dyn FnOnce(Option<std::path::PathBuf>) + Send>| {
-        let temp_filename = format! {"rec_{}_xlab.mp4", SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()};
-        let dialog = DialogExt::dialog(super::APP_HANDLE.get().unwrap());
+pub fn set_audio_muted(input: AudioInput, muted: bool) {
+    xlab_core::record::set_muted(input, muted)
+}
+
+#[tauri::command]
+pub fn is_audio_muted(input: AudioInput) -> bool {
+    xlab_core::record::is_muted(input)
+}
+
+/// Saves the finished recording, as a video unless another `format` is given
+#[tauri::command]
+pub fn save_recording(format: Option<OutputFormat>) -> Result<(), XlabError> {
+    let format = format.unwrap_or_default();
+    let container = xlab_core::record::get_options()
+        .lock()
+        .unwrap()
+        .get_container();
+    let save_at_chosen_loc = move |save_fn: Box<dyn FnOnce(Option<std::path::PathBuf>) + Send>| {
+        let extension = format.extension(container);
+        let temp_filename = format! {"rec_{}_xlab.{extension}", SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()};
+        let Some(app_handle) = super::APP_HANDLE.get() else {
+            // Without a dialog the recording stays in the app cache
+            return save_fn(None);
+        };
+        let dialog = DialogExt::dialog(app_handle);
         dialog
             .file()
             .set_file_name(&temp_filename)
-            .add_filter("MP4 Files", &["mp4"])
+            .add_filter(
+                format!("{} Files", format.file_type(container)),
+                &[extension],
+            )
             .save_file(move |filepath| save_fn(filepath.map(|v| v.into_path().ok()).flatten()));
     };
 
-    xlab_core::record::save_video(save_at_chosen_loc);
+    xlab_core::record::save_video(format, save_at_chosen_loc)
 }
 
 #[tauri::command]
-pub fn discard_recording() {
-    xlab_core::record::discard_video();
+pub fn discard_recording() -> Result<(), XlabError> {
+    xlab_core::record::discard_video()
 }
 
 #[tauri::command]
-pub fn available_resolutions() -> [[u32; 2]; 8] {
+pub fn available_resolutions() -> Result<[[u32; 2]; 8], XlabError> {
     xlab_core::valid_resolutions()
 }
 
@@ -52,20 +106,52 @@ pub fn available_frame_rates() -> [u32; 4] {
 }
 
 #[tauri::command]
-pub fn update_resolution(index: usize) {
-    let resolutions = xlab_core::valid_resolutions();
-    let resolution = resolutions[index];
-    xlab_core::user::update_resolution(resolution[0], resolution[1]);
+pub fn update_resolution(index: usize) -> Result<(), XlabError> {
+    let resolutions = xlab_core::valid_resolutions()?;
+    let resolution = resolutions
+        .get(index)
+        .ok_or_else(|| XlabError::Config(format!("no resolution at index {index}")))?;
+    xlab_core::user::update_resolution(resolution[0], resolution[1])
 }
 
 #[tauri::command]
-pub fn update_pointer(index: usize) {
-    xlab_core::user::update_pointer(index);
+pub fn update_pointer(index: usize) -> Result<(), XlabError> {
+    xlab_core::user::update_pointer(index)
 }
 
 #[tauri::command]
-pub fn update_frame_rate(frame_rate: u32) {
-    xlab_core::user::update_frame_rate(frame_rate);
+pub fn update_frame_rate(frame_rate: u32) -> Result<(), XlabError> {
+    xlab_core::user::update_frame_rate(frame_rate)
+}
+
+/// Records a timelapse that captures a frame every `interval_ms`, or in real time when
+/// `None`
+#[tauri::command]
+pub fn update_timelapse_interval(interval_ms: Option<u64>) -> Result<(), XlabError> {
+    xlab_core::user::update_timelapse_interval(interval_ms.map(Duration::from_millis))
+}
+
+#[tauri::command]
+pub fn get_timelapse_interval() -> Option<u64> {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options
+        .timelapse_interval
+        .map(|interval| interval.as_millis() as u64)
+}
+
+/// Zooms in on the cursor while recording as `settings` say, or turns zooming off
+/// when `None`
+#[tauri::command]
+pub fn update_auto_zoom(settings: Option<ZoomSettings>) -> Result<(), XlabError> {
+    xlab_core::user::update_auto_zoom(settings)
+}
+
+#[tauri::command]
+pub fn get_auto_zoom() -> Option<ZoomSettings> {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.auto_zoom
 }
 
 #[tauri::command]
@@ -83,6 +169,211 @@ pub fn get_current_frame_rate() -> u32 {
     options.frame_rate
 }
 
+#[tauri::command]
+pub fn available_monitors() -> Result<Vec<MonitorInfo>, XlabError> {
+    xlab_core::capture::monitors()
+}
+
+#[tauri::command]
+pub fn update_monitor(id: Option<u32>) -> Result<(), XlabError> {
+    xlab_core::user::update_monitor(id)
+}
+
+#[tauri::command]
+pub fn get_current_monitor() -> Option<u32> {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.monitor
+}
+
+#[tauri::command]
+pub fn available_windows() -> Result<Vec<WindowInfo>, XlabError> {
+    xlab_core::capture::windows()
+}
+
+#[tauri::command]
+pub fn update_window(id: Option<u32>) -> Result<(), XlabError> {
+    xlab_core::user::update_window(id)
+}
+
+#[tauri::command]
+pub fn get_current_window() -> Option<u32> {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.window
+}
+
+#[tauri::command]
+pub fn update_region(region: Option<CaptureRegion>) -> Result<(), XlabError> {
+    xlab_core::user::update_region(region)
+}
+
+#[tauri::command]
+pub fn get_current_region() -> Option<CaptureRegion> {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.region
+}
+
+#[tauri::command]
+pub fn update_encode_mode(encode_mode: EncodeMode) {
+    xlab_core::user::update_encode_mode(encode_mode);
+}
+
+#[tauri::command]
+pub fn get_current_encode_mode() -> EncodeMode {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.encode_mode
+}
+
+#[tauri::command]
+pub fn available_codecs() -> Vec<VideoCodec> {
+    xlab_core::video::available_codecs()
+}
+
+#[tauri::command]
+pub fn update_codec(codec: VideoCodec) -> Result<(), XlabError> {
+    xlab_core::user::update_codec(codec)
+}
+
+#[tauri::command]
+pub fn get_current_codec() -> VideoCodec {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.codec
+}
+
+#[tauri::command]
+pub fn available_containers() -> Vec<Container> {
+    Container::ALL.to_vec()
+}
+
+#[tauri::command]
+pub fn update_container(container: Container) -> Result<(), XlabError> {
+    xlab_core::user::update_container(container)
+}
+
+#[tauri::command]
+pub fn get_current_container() -> Container {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.container
+}
+
+#[tauri::command]
+pub fn quality_presets() -> Vec<QualityPreset> {
+    QualityPreset::ALL.to_vec()
+}
+
+#[tauri::command]
+pub fn update_quality_preset(preset: QualityPreset) -> Result<(), XlabError> {
+    xlab_core::user::update_quality_preset(preset)
+}
+
+#[tauri::command]
+pub fn update_quality(quality: QualitySettings) -> Result<(), XlabError> {
+    xlab_core::user::update_quality(quality)
+}
+
+#[tauri::command]
+pub fn get_current_quality() -> QualitySettings {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.quality.clone()
+}
+
+/// Preset the current quality settings match, `None` once they have been customized
+#[tauri::command]
+pub fn get_current_quality_preset() -> Option<QualityPreset> {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.quality.preset()
+}
+
+#[tauri::command]
+pub fn available_audio_codecs() -> Vec<AudioCodec> {
+    xlab_core::audio::available_audio_codecs()
+}
+
+#[tauri::command]
+pub fn update_system_audio(enabled: bool) -> Result<(), XlabError> {
+    xlab_core::user::update_system_audio(enabled)
+}
+
+#[tauri::command]
+pub fn get_system_audio() -> bool {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.system_audio
+}
+
+#[tauri::command]
+pub fn available_microphones() -> Result<Vec<AudioDevice>, XlabError> {
+    xlab_core::audio::available_microphones()
+}
+
+#[tauri::command]
+pub fn update_microphone(enabled: bool) -> Result<(), XlabError> {
+    xlab_core::user::update_microphone(enabled)
+}
+
+#[tauri::command]
+pub fn get_microphone() -> bool {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.microphone
+}
+
+/// Selects the microphone by its device name, `None` selects the default input
+#[tauri::command]
+pub fn update_microphone_device(device: Option<String>) -> Result<(), XlabError> {
+    xlab_core::user::update_microphone_device(device)
+}
+
+#[tauri::command]
+pub fn get_current_microphone_device() -> Option<String> {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.microphone_device.clone()
+}
+
+#[tauri::command]
+pub fn update_audio_gain(input: AudioInput, gain: f32) -> Result<(), XlabError> {
+    xlab_core::user::update_audio_gain(input, gain)
+}
+
+#[tauri::command]
+pub fn get_audio_gain(input: AudioInput) -> f32 {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.audio_gain(input)
+}
+
+#[tauri::command]
+pub fn update_audio_mix(mix: AudioMix) -> Result<(), XlabError> {
+    xlab_core::user::update_audio_mix(mix)
+}
+
+#[tauri::command]
+pub fn get_current_audio_mix() -> AudioMix {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.audio_mix
+}
+
+#[tauri::command]
+pub fn update_audio_codec(codec: AudioCodec) -> Result<(), XlabError> {
+    xlab_core::user::update_audio_codec(codec)
+}
+
+#[tauri::command]
+pub fn get_current_audio_codec() -> AudioCodec {
+    let options = xlab_core::user::get_user_options();
+    let options = options.lock().unwrap();
+    options.audio_codec
+}
+
 #[tauri::command]
 pub fn get_current_pointer() -> usize {
     let options = xlab_core::user::get_user_options();
@@ -96,18 +387,328 @@ pub fn get_current_pointer() -> usize {
 }
 
 #[tauri::command]
-pub fn saving_progress() -> Option<SaveProgress> {
-    *xlab_core::record::get_save_progress().lock().unwrap()
+pub fn saving_progress() -> Result<Option<SaveProgress>, XlabError> {
+    let recorder = xlab_core::record::default_recorder();
+    if let Some(err) = recorder.take_error() {
+        return Err(err);
+    }
+    Ok(recorder.save_progress())
 }
 
 #[tauri::command]
-pub fn past_videos() -> Vec<PreviousRecording> {
+pub fn past_videos() -> Result<Vec<PreviousRecording>, XlabError> {
     xlab_core::previous_recordings()
 }
 
 #[tauri::command]
-pub fn remove_previous_recording_by_index(index: usize) {
-    xlab_core::delete_previous_recording(index);
+pub fn remove_previous_recording_by_index(index: usize) -> Result<(), XlabError> {
+    xlab_core::delete_previous_recording(index)
+}
+
+/// Asks where to export the previous recording at `index` as `format` and exports it,
+/// reporting progress under `job_id`. Runs off the main thread since the file dialog
+/// blocks until it is closed.
+#[tauri::command(async)]
+pub fn export_previous_recording(
+    index: usize,
+    format: OutputFormat,
+    job_id: u32,
+) -> Result<(), XlabError> {
+    let app_handle = super::APP_HANDLE
+        .get()
+        .ok_or_else(|| XlabError::State("the app is not initialized".into()))?;
+    let container = xlab_core::record::get_options()
+        .lock()
+        .unwrap()
+        .get_container();
+    let extension = format.extension(container);
+    let temp_filename = format! {"rec_{}_xlab.{extension}", SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()};
+    let output_path = DialogExt::dialog(app_handle)
+        .file()
+        .set_file_name(&temp_filename)
+        .add_filter(
+            format!("{} Files", format.file_type(container)),
+            &[extension],
+        )
+        .blocking_save_file()
+        .and_then(|filepath| filepath.into_path().ok());
+    match output_path {
+        Some(output_path) => run_edit_job(job_id, |job| {
+            xlab_core::export_previous_recording(index, format, output_path, job)
+        }),
+        // The dialog was cancelled
+        None => Ok(()),
+    }
+}
+
+/// Asks for an audio file and where to save the result, then adds the audio to the
+/// previous recording at `index` as a new track, starting `offset_ms` into it.
+/// Progress is reported under `job_id`.
+#[tauri::command(async)]
+pub fn add_audio_to_previous_recording(
+    index: usize,
+    offset_ms: u64,
+    gain: f32,
+    job_id: u32,
+) -> Result<(), XlabError> {
+    let app_handle = super::APP_HANDLE
+        .get()
+        .ok_or_else(|| XlabError::State("the app is not initialized".into()))?;
+    let recording = xlab_core::previous_recordings()?
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| XlabError::Config(format!("no previous recording at index {index}")))?;
+    let audio_path = DialogExt::dialog(app_handle)
+        .file()
+        .add_filter(
+            "Audio Files",
+            &["wav", "mp3", "opus", "ogg", "aac", "m4a", "flac"],
+        )
+        .blocking_pick_file()
+        .and_then(|filepath| filepath.into_path().ok());
+    let Some(audio_path) = audio_path else {
+        // The dialog was cancelled
+        return Ok(());
+    };
+    let output_path = pick_edited_recording_path(app_handle, &recording);
+    let overlay = AudioOverlay {
+        offset: Duration::from_millis(offset_ms),
+        gain,
+        ..AudioOverlay::new(audio_path)
+    };
+    match output_path {
+        Some(output_path) => run_edit_job(job_id, |job| {
+            xlab_core::add_audio_to_previous_recording(index, &overlay, output_path, job)
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Asks where to save the previous recording at `index` from `start_ms` up to
+/// `end_ms`, then writes that part of it, reporting progress under `job_id`
+#[tauri::command(async)]
+pub fn trim_previous_recording(
+    index: usize,
+    start_ms: u64,
+    end_ms: u64,
+    job_id: u32,
+) -> Result<(), XlabError> {
+    let range = TimeRange::new(
+        Duration::from_millis(start_ms),
+        Duration::from_millis(end_ms),
+    );
+    range.validate()?;
+    match ask_edit_output_path(index)? {
+        Some(output_path) => run_edit_job(job_id, |job| {
+            xlab_core::trim_previous_recording(index, range, output_path, job)
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Asks where to save the previous recording at `index` without the part from
+/// `start_ms` up to `end_ms`, then writes what is left of it, reporting progress under
+/// `job_id`
+#[tauri::command(async)]
+pub fn cut_previous_recording(
+    index: usize,
+    start_ms: u64,
+    end_ms: u64,
+    job_id: u32,
+) -> Result<(), XlabError> {
+    let range = TimeRange::new(
+        Duration::from_millis(start_ms),
+        Duration::from_millis(end_ms),
+    );
+    range.validate()?;
+    match ask_edit_output_path(index)? {
+        Some(output_path) => run_edit_job(job_id, |job| {
+            xlab_core::cut_previous_recording(index, range, output_path, job)
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Asks where to save the previous recording at `index`, then writes it with its
+/// speed multiplied by `speed`, only from `start_ms` up to `end_ms` when given.
+/// `blend` blends the frames that speeding up skips into those that are shown.
+/// Progress is reported under `job_id`.
+#[tauri::command(async)]
+pub fn change_previous_recording_speed(
+    index: usize,
+    speed: f64,
+    start_ms: Option<u64>,
+    end_ms: Option<u64>,
+    blend: bool,
+    job_id: u32,
+) -> Result<(), XlabError> {
+    let range = match (start_ms, end_ms) {
+        (None, None) => None,
+        (start_ms, end_ms) => Some(TimeRange::new(
+            Duration::from_millis(start_ms.unwrap_or(0)),
+            end_ms.map_or(Duration::MAX, Duration::from_millis),
+        )),
+    };
+    let change = SpeedChange {
+        speed,
+        range,
+        blend,
+    };
+    change.validate()?;
+    match ask_edit_output_path(index)? {
+        Some(output_path) => run_edit_job(job_id, |job| {
+            xlab_core::change_previous_recording_speed(index, &change, output_path, job)
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Asks where to save the previous recording at `index`, then writes the part of it
+/// within `region`, scaled with `filter` to `resolution` when given. The resolution
+/// may be one of [`available_resolutions`] or any other size. Progress is reported
+/// under `job_id`.
+#[tauri::command(async)]
+pub fn crop_previous_recording(
+    index: usize,
+    region: CaptureRegion,
+    resolution: Option<[u32; 2]>,
+    filter: ScaleFilter,
+    job_id: u32,
+) -> Result<(), XlabError> {
+    let crop = Crop {
+        region,
+        resolution: resolution.map(|[width, height]| (width, height)),
+        filter,
+    };
+    match ask_edit_output_path(index)? {
+        Some(output_path) => run_edit_job(job_id, |job| {
+            xlab_core::crop_previous_recording(index, &crop, output_path, job)
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Asks where to save the previous recording at `index`, then writes it zoomed in on
+/// the cursor as `settings` say, following the cursor track recorded with it.
+/// Progress is reported under `job_id`.
+#[tauri::command(async)]
+pub fn auto_zoom_previous_recording(
+    index: usize,
+    settings: ZoomSettings,
+    job_id: u32,
+) -> Result<(), XlabError> {
+    settings.validate()?;
+    match ask_edit_output_path(index)? {
+        Some(output_path) => run_edit_job(job_id, |job| {
+            xlab_core::auto_zoom_previous_recording(index, &settings, output_path, job)
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Asks where to save the result, then joins the previous recordings at `indices` in
+/// that order. A nonzero `crossfade_ms` fades each recording into the next one; a
+/// nonzero `title_card_ms` first asks for an image to show between them for that long.
+/// Progress is reported under `job_id`.
+#[tauri::command(async)]
+pub fn concat_previous_recordings(
+    indices: Vec<usize>,
+    crossfade_ms: u64,
+    title_card_ms: u64,
+    job_id: u32,
+) -> Result<(), XlabError> {
+    let transition = match (crossfade_ms, title_card_ms) {
+        (0, 0) => Transition::Cut,
+        (crossfade_ms, 0) => Transition::Crossfade(Duration::from_millis(crossfade_ms)),
+        (0, title_card_ms) => {
+            let app_handle = super::APP_HANDLE
+                .get()
+                .ok_or_else(|| XlabError::State("the app is not initialized".into()))?;
+            let image = DialogExt::dialog(app_handle)
+                .file()
+                .add_filter("Images", &["png", "jpg", "jpeg", "webp"])
+                .blocking_pick_file()
+                .and_then(|filepath| filepath.into_path().ok());
+            let Some(image) = image else {
+                // The dialog was cancelled
+                return Ok(());
+            };
+            Transition::TitleCard {
+                image,
+                duration: Duration::from_millis(title_card_ms),
+            }
+        }
+        _ => {
+            return Err(XlabError::Config(
+                "recordings are joined with either a crossfade or a title card".into(),
+            ))
+        }
+    };
+    let first = *indices
+        .first()
+        .ok_or_else(|| XlabError::Config("no recordings to join".into()))?;
+    match ask_edit_output_path(first)? {
+        Some(output_path) => run_edit_job(job_id, |job| {
+            xlab_core::concat_previous_recordings(&indices, &transition, output_path, job)
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Progress of the edit started with `job_id`, `None` before it started and once it
+/// ended
+#[tauri::command]
+pub fn editing_progress(job_id: u32) -> Option<SaveProgress> {
+    EDIT_JOBS
+        .lock()
+        .unwrap()
+        .get(&job_id)
+        .and_then(EditJob::progress)
+}
+
+/// Runs `edit` with a job that [`editing_progress`] reports under `job_id` meanwhile
+fn run_edit_job(
+    job_id: u32,
+    edit: impl FnOnce(&EditJob) -> Result<(), XlabError>,
+) -> Result<(), XlabError> {
+    let job = EditJob::new();
+    EDIT_JOBS.lock().unwrap().insert(job_id, job.clone());
+    let result = edit(&job);
+    EDIT_JOBS.lock().unwrap().remove(&job_id);
+    result
+}
+
+/// Asks where to save an edit of the previous recording at `index`, `None` if the
+/// dialog was cancelled
+fn ask_edit_output_path(index: usize) -> Result<Option<PathBuf>, XlabError> {
+    let app_handle = super::APP_HANDLE
+        .get()
+        .ok_or_else(|| XlabError::State("the app is not initialized".into()))?;
+    let recording = xlab_core::previous_recordings()?
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| XlabError::Config(format!("no previous recording at index {index}")))?;
+    Ok(pick_edited_recording_path(app_handle, &recording))
+}
+
+/// Asks where to save an edit of `recording`, in the container of the recording
+fn pick_edited_recording_path(
+    app_handle: &tauri::AppHandle,
+    recording: &PreviousRecording,
+) -> Option<PathBuf> {
+    let extension = recording
+        .file_path()
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("mp4")
+        .to_owned();
+    let temp_filename = format! {"rec_{}_xlab.{extension}", SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()};
+    DialogExt::dialog(app_handle)
+        .file()
+        .set_file_name(&temp_filename)
+        .add_filter("Video Files", &[extension.as_str()])
+        .blocking_save_file()
+        .and_then(|filepath| filepath.into_path().ok())
 }
 
 #[tauri::command]
@@ -31,7 +31,7 @@ pub fn run() {
             APP_HANDLE
                 .set(app.handle().clone())
                 .expect("failed to set app handle");
-            xlab_core::set_app_cache_dir(app_cache_dir);
+            xlab_core::set_app_cache_dir(app_cache_dir)?;
             xlab_core::init();
             Ok(())
         })
@@ -39,6 +39,10 @@ pub fn run() {
             start_recording,
             recording_state,
             stop_recording,
+            pause_recording,
+            resume_recording,
+            set_audio_muted,
+            is_audio_muted,
             save_recording,
             discard_recording,
             available_resolutions,
@@ -46,12 +50,60 @@ pub fn run() {
             update_resolution,
             update_pointer,
             update_frame_rate,
+            update_timelapse_interval,
+            get_timelapse_interval,
+            update_auto_zoom,
+            get_auto_zoom,
             get_current_resolution,
             get_current_frame_rate,
             get_current_pointer,
+            available_monitors,
+            update_monitor,
+            get_current_monitor,
+            available_windows,
+            update_window,
+            get_current_window,
+            update_region,
+            get_current_region,
+            update_encode_mode,
+            get_current_encode_mode,
+            available_codecs,
+            update_codec,
+            get_current_codec,
+            available_containers,
+            update_container,
+            get_current_container,
+            quality_presets,
+            update_quality_preset,
+            update_quality,
+            get_current_quality,
+            get_current_quality_preset,
+            available_audio_codecs,
+            update_system_audio,
+            get_system_audio,
+            available_microphones,
+            update_microphone,
+            get_microphone,
+            update_microphone_device,
+            get_current_microphone_device,
+            update_audio_gain,
+            get_audio_gain,
+            update_audio_mix,
+            get_current_audio_mix,
+            update_audio_codec,
+            get_current_audio_codec,
             saving_progress,
             past_videos,
             remove_previous_recording_by_index,
+            export_previous_recording,
+            add_audio_to_previous_recording,
+            trim_previous_recording,
+            cut_previous_recording,
+            change_previous_recording_speed,
+            crop_previous_recording,
+            auto_zoom_previous_recording,
+            concat_previous_recordings,
+            editing_progress,
             open_file_location
         ])
         .run(tauri::generate_context!())
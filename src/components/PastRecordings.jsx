@@ -1,6 +1,7 @@
 import React from "react";
-import { TrashIcon, FolderOpenIcon, PlayIcon } from "@heroicons/react/24/outline";
+import { TrashIcon, FolderOpenIcon, PlayIcon, GifIcon } from "@heroicons/react/24/outline";
 import { invoke } from "@tauri-apps/api/core";
+import { OutputFormat } from "../utils/api";
 import "./PastRecordings.css";
 
 const formatDuration = (seconds) => {
@@ -41,7 +42,7 @@ const formatDate = (secsSinceEpoch) => {
 
 const getFilename = (filePath) => filePath.split("/").pop().split("\\").pop();
 
-export function PastRecordings({ pastVideos, removeRecording }) {
+export function PastRecordings({ pastVideos, removeRecording, exportRecording }) {
   const handleCardClick = async (videoPath) => {
     try {
       await invoke("open_file_location", { path: videoPath });
@@ -57,6 +58,11 @@ export function PastRecordings({ pastVideos, removeRecording }) {
     }
   };
 
+  const handleExportGif = (e, index) => {
+    e.stopPropagation(); // Prevent card click
+    exportRecording(index, OutputFormat.GIF);
+  };
+
   const handleOpenLocation = async (e, videoPath) => {
     e.stopPropagation(); // Prevent card click
     try {
@@ -111,6 +117,13 @@ export function PastRecordings({ pastVideos, removeRecording }) {
                 >
                   <FolderOpenIcon className="action-icon" />
                 </button>
+                <button
+                  className="action-button"
+                  onClick={(e) => handleExportGif(e, index)}
+                  title="Export as GIF"
+                >
+                  <GifIcon className="action-icon" />
+                </button>
                 <button
                   className="action-button delete"
                   onClick={(e) => handleDelete(e, index)}
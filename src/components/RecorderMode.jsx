@@ -9,7 +9,9 @@ import {
   discardRecording,
   getRecordingState,
   getSavingStateAsRecordingState,
+  pauseRecording,
   releaseRecording,
+  resumeRecording,
   startRecording,
   stopRecording,
 } from "../utils/api";
@@ -30,6 +32,7 @@ export function RecorderMode() {
     pastVideos,
     refreshPastVideos,
     removePastVideo,
+    exportPastVideo,
   } = useRecorder();
 
   const disabled = useMemo(
@@ -66,12 +69,20 @@ export function RecorderMode() {
     stopRecording().then(() => pollRecordingState());
   };
 
+  const onPauseRecording = () => {
+    pauseRecording().then(() => pollRecordingState());
+  };
+
+  const onResumeRecording = () => {
+    resumeRecording().then(() => pollRecordingState());
+  };
+
   const onDiscardRecording = () => {
     discardRecording().then(() => pollRecordingState());
   };
 
-  const onReleaseRecording = () => {
-    releaseRecording().then(() => pollSavingState());
+  const onReleaseRecording = (format) => {
+    releaseRecording(format).then(() => pollSavingState());
   };
 
   useEffect(() => {
@@ -122,6 +133,8 @@ export function RecorderMode() {
           recordingState={recordingState}
           onStartRecording={onStartRecording}
           onStopRecording={onStopRecording}
+          onPauseRecording={onPauseRecording}
+          onResumeRecording={onResumeRecording}
           onDiscardRecording={onDiscardRecording}
           onReleaseRecording={onReleaseRecording}
         />
@@ -130,6 +143,7 @@ export function RecorderMode() {
         <PastRecordings
           pastVideos={pastVideos}
           removeRecording={removePastVideo}
+          exportRecording={exportPastVideo}
         />
       </div>
     </div>
@@ -1,12 +1,14 @@
 import React from "react";
 import { formatTime } from "../utils/formatters";
 import ProgressBar from "./ProgressBar";
-import { RecordingState } from "../utils/api";
+import { OutputFormat, RecordingState } from "../utils/api";
 
 function RecordingControls({
   recordingState,
   onStartRecording,
   onStopRecording,
+  onPauseRecording,
+  onResumeRecording,
   onDiscardRecording,
   onReleaseRecording,
 }) {
@@ -21,15 +23,42 @@ function RecordingControls({
         </button>
       )}
       {recordingState?.state === RecordingState.RECORDING && (
-        <button
-          className="glass px-6 py-3 rounded-lg bg-red-500 text-white hover:bg-red-600 transition flex gap-2"
-          onClick={onStopRecording}
-          title="Stop recording"
-        >
-          <span className="w-full h-full flex items-center justify-center text-lg text-white">
-            {formatTime(Date.now() - new Date(recordingState.instant))}
-          </span>
-        </button>
+        <>
+          <button
+            className="glass px-6 py-3 rounded-lg bg-red-500 text-white hover:bg-red-600 transition flex gap-2"
+            onClick={onStopRecording}
+            title="Stop recording"
+          >
+            <span className="w-full h-full flex items-center justify-center text-lg text-white">
+              {formatTime(Date.now() - new Date(recordingState.instant))}
+            </span>
+          </button>
+          <button
+            className="glass px-6 py-3 rounded-lg bg-yellow-500 text-white hover:bg-yellow-600 transition"
+            onClick={onPauseRecording}
+          >
+            Pause
+          </button>
+        </>
+      )}
+      {recordingState?.state === RecordingState.PAUSED && (
+        <>
+          <button
+            className="glass px-6 py-3 rounded-lg bg-red-500 text-white hover:bg-red-600 transition flex gap-2"
+            onClick={onStopRecording}
+            title="Stop recording"
+          >
+            <span className="w-full h-full flex items-center justify-center text-lg text-white">
+              {formatTime(recordingState.duration)}
+            </span>
+          </button>
+          <button
+            className="glass px-6 py-3 rounded-lg bg-green-500 text-white hover:bg-green-600 transition"
+            onClick={onResumeRecording}
+          >
+            Resume
+          </button>
+        </>
       )}
       {recordingState?.state === RecordingState.DONE && (
         <>
@@ -41,10 +70,22 @@ function RecordingControls({
           </button>
           <button
             className="glass px-6 py-3 rounded-lg bg-green-500 text-white hover:bg-green-600 transition"
-            onClick={onReleaseRecording}
+            onClick={() => onReleaseRecording(OutputFormat.VIDEO)}
           >
             Release Video
           </button>
+          <select
+            className="glass px-4 py-3 rounded-lg bg-blue-500 text-white hover:bg-blue-600 transition"
+            value=""
+            onChange={(e) => onReleaseRecording(OutputFormat[e.target.value])}
+          >
+            <option value="" disabled>
+              Release as...
+            </option>
+            <option value="GIF">GIF</option>
+            <option value="WEBP">WebP</option>
+            <option value="APNG">APNG</option>
+          </select>
         </>
       )}
       {recordingState?.state === RecordingState.SAVING && (
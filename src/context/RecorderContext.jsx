@@ -41,6 +41,12 @@ export const RecorderProvider = ({ children }) => {
     );
   };
 
+  const exportPastVideo = async (index, format) => {
+    await invoke("export_previous_recording", { index, format })
+      .then(refreshPastVideos)
+      .catch(console.error);
+  };
+
   useEffect(() => {
     Promise.all([
       invoke("available_resolutions")
@@ -76,6 +82,7 @@ export const RecorderProvider = ({ children }) => {
         pastVideos,
         refreshPastVideos,
         removePastVideo,
+        exportPastVideo,
       }}
     >
       {children}
@@ -3,6 +3,7 @@ import { invoke } from "@tauri-apps/api/core";
 export const RecordingState = Object.freeze({
   IDLE: "Idle",
   RECORDING: "Recording",
+  PAUSED: "Paused",
   DONE: "Done",
   SAVING: "Saving",
 });
@@ -14,6 +15,15 @@ export const SavingState = Object.freeze({
   DONE: "Done",
 });
 
+// Formats a recording can be saved as, in the shape the backend deserializes them.
+// Options left out fall back to the backend defaults.
+export const OutputFormat = Object.freeze({
+  VIDEO: "Video",
+  GIF: { Gif: {} },
+  WEBP: { WebP: {} },
+  APNG: { Apng: {} },
+});
+
 export async function getRecordingState() {
   const state = await invoke("recording_state");
 
@@ -25,6 +35,10 @@ export async function getRecordingState() {
     return { state: RecordingState.RECORDING, instant: state.Recording };
   }
 
+  if (typeof state === "object" && RecordingState.PAUSED in state) {
+    return { state: RecordingState.PAUSED, duration: state.Paused };
+  }
+
   if (typeof state === "object" && RecordingState.DONE in state) {
     return { state: RecordingState.DONE, duration: state.Done };
   }
@@ -81,10 +95,18 @@ export async function stopRecording() {
   await invoke("stop_recording");
 }
 
+export async function pauseRecording() {
+  await invoke("pause_recording");
+}
+
+export async function resumeRecording() {
+  await invoke("resume_recording");
+}
+
 export async function discardRecording() {
   await invoke("discard_recording");
 }
 
-export async function releaseRecording() {
-  await invoke("save_recording");
+export async function releaseRecording(format = OutputFormat.VIDEO) {
+  await invoke("save_recording", { format });
 }
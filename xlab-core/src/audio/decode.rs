@@ -0,0 +1,227 @@
+use std::path::Path;
+use std::ptr;
+use std::time::Duration;
+
+use ffmpeg_sys_next::AVSampleFormat::AV_SAMPLE_FMT_FLT;
+use ffmpeg_sys_next::*;
+
+use super::{position_timestamp, CHANNELS, SAMPLE_RATE};
+use crate::{video::path_to_cstring, Result, XlabError};
+
+/// Reads the audio stream of a file as interleaved samples at [`SAMPLE_RATE`] with
+/// [`CHANNELS`] channels, timestamped from the first sample
+pub(crate) struct AudioDecoder {
+    fmt_ctx: *mut AVFormatContext,
+    codec_ctx: *mut AVCodecContext,
+    swr_ctx: *mut SwrContext,
+    frame: *mut AVFrame,
+    packet: *mut AVPacket,
+    stream_index: i32,
+    /// Samples per channel returned so far
+    position: i64,
+    draining: bool,
+    finished: bool,
+}
+
+impl AudioDecoder {
+    pub(crate) fn open(input_path: &Path) -> Result<Self> {
+        let input_path_c = path_to_cstring(input_path)?;
+        // Everything allocated so far is released by `drop` when a step fails
+        let mut decoder = Self {
+            fmt_ctx: ptr::null_mut(),
+            codec_ctx: ptr::null_mut(),
+            swr_ctx: ptr::null_mut(),
+            frame: ptr::null_mut(),
+            packet: ptr::null_mut(),
+            stream_index: 0,
+            position: 0,
+            draining: false,
+            finished: false,
+        };
+        unsafe {
+            // 1. Open the file and probe its streams
+            if avformat_open_input(
+                &mut decoder.fmt_ctx,
+                input_path_c.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+            ) < 0
+            {
+                return Err(XlabError::Encode(format!(
+                    "Failed to open {}",
+                    input_path.display()
+                )));
+            }
+            if avformat_find_stream_info(decoder.fmt_ctx, ptr::null_mut()) < 0 {
+                return Err(XlabError::Encode("Failed to read stream info".into()));
+            }
+
+            // 2. Find the audio stream and its decoder
+            let mut codec = ptr::null();
+            let stream_index = av_find_best_stream(
+                decoder.fmt_ctx,
+                AVMediaType::AVMEDIA_TYPE_AUDIO,
+                -1,
+                -1,
+                &mut codec,
+                0,
+            );
+            if stream_index < 0 || codec.is_null() {
+                return Err(XlabError::Encode(format!(
+                    "{} has no decodable audio stream",
+                    input_path.display()
+                )));
+            }
+            decoder.stream_index = stream_index;
+
+            // 3. Open the decoder
+            decoder.codec_ctx = avcodec_alloc_context3(codec);
+            if decoder.codec_ctx.is_null() {
+                return Err(XlabError::Encode("Failed to allocate codec context".into()));
+            }
+            if avcodec_parameters_to_context(decoder.codec_ctx, (*decoder.stream()).codecpar) < 0 {
+                return Err(XlabError::Encode("Failed to copy codec parameters".into()));
+            }
+            if avcodec_open2(decoder.codec_ctx, codec, ptr::null_mut()) < 0 {
+                return Err(XlabError::Encode("Failed to open codec".into()));
+            }
+
+            // 4. Allocate frame and packet
+            decoder.frame = av_frame_alloc();
+            decoder.packet = av_packet_alloc();
+            if decoder.frame.is_null() || decoder.packet.is_null() {
+                return Err(XlabError::Encode("Failed to allocate frame".into()));
+            }
+        }
+        Ok(decoder)
+    }
+
+    /// The decoded stream, whose packets can also be copied as they are
+    pub(crate) fn stream(&self) -> *mut AVStream {
+        unsafe { *(*self.fmt_ctx).streams.add(self.stream_index as usize) }
+    }
+
+    /// Reads the next packet of the decoded stream without decoding it, returning
+    /// `false` at the end of the file. The packet must be unreferenced by the caller.
+    pub(crate) unsafe fn read_packet(&mut self, packet: *mut AVPacket) -> bool {
+        while av_read_frame(self.fmt_ctx, packet) >= 0 {
+            if (*packet).stream_index == self.stream_index {
+                return true;
+            }
+            av_packet_unref(packet);
+        }
+        false
+    }
+
+    /// Decodes the next samples, returning `None` once the stream is exhausted
+    fn next_samples(&mut self) -> Result<Option<Vec<f32>>> {
+        unsafe {
+            loop {
+                let received = avcodec_receive_frame(self.codec_ctx, self.frame);
+                if received >= 0 {
+                    let samples = self.convert_frame();
+                    av_frame_unref(self.frame);
+                    return samples.map(Some);
+                }
+                if received == AVERROR_EOF || self.draining {
+                    // The resampler holds back a few samples until it is flushed
+                    let samples = self.resample(ptr::null(), 0)?;
+                    return Ok((!samples.is_empty()).then_some(samples));
+                }
+                if received != AVERROR(EAGAIN) {
+                    return Err(XlabError::Encode("Failed to decode audio".into()));
+                }
+
+                // The decoder needs more input
+                if !self.read_packet(self.packet) {
+                    // End of file, flush the samples the decoder still holds
+                    self.draining = true;
+                    avcodec_send_packet(self.codec_ctx, ptr::null());
+                    continue;
+                }
+                let sent = avcodec_send_packet(self.codec_ctx, self.packet);
+                av_packet_unref(self.packet);
+                if sent < 0 {
+                    return Err(XlabError::Encode("Failed to send packet to decoder".into()));
+                }
+            }
+        }
+    }
+
+    /// Converts the decoded frame to interleaved float samples
+    unsafe fn convert_frame(&mut self) -> Result<Vec<f32>> {
+        if self.swr_ctx.is_null() {
+            let mut output_layout = std::mem::zeroed();
+            av_channel_layout_default(&mut output_layout, CHANNELS as i32);
+            let allocated = swr_alloc_set_opts2(
+                &mut self.swr_ctx,
+                &output_layout,
+                AV_SAMPLE_FMT_FLT,
+                SAMPLE_RATE as i32,
+                &(*self.frame).ch_layout,
+                (*self.codec_ctx).sample_fmt,
+                (*self.frame).sample_rate,
+                0,
+                ptr::null_mut(),
+            );
+            if allocated < 0 || swr_init(self.swr_ctx) < 0 {
+                return Err(XlabError::Encode("Failed to create resampler".into()));
+            }
+        }
+        self.resample(
+            (*self.frame).extended_data as *const *const u8,
+            (*self.frame).nb_samples,
+        )
+    }
+
+    /// Resamples `count` samples per channel from `input`, or flushes the resampler
+    /// if `input` is null
+    unsafe fn resample(&mut self, input: *const *const u8, count: i32) -> Result<Vec<f32>> {
+        if self.swr_ctx.is_null() {
+            return Ok(Vec::new());
+        }
+        let capacity = swr_get_out_samples(self.swr_ctx, count).max(0) as usize;
+        let mut samples = vec![0.0f32; capacity * CHANNELS];
+        let output = [samples.as_mut_ptr() as *mut u8];
+        let converted = swr_convert(self.swr_ctx, output.as_ptr(), capacity as i32, input, count);
+        if converted < 0 {
+            return Err(XlabError::Encode("Failed to resample audio".into()));
+        }
+        samples.truncate(converted as usize * CHANNELS);
+        Ok(samples)
+    }
+}
+
+impl Iterator for AudioDecoder {
+    type Item = Result<(Duration, Vec<f32>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let samples = self.next_samples().transpose();
+        self.finished = !matches!(samples, Some(Ok(_)));
+        samples.map(|samples| {
+            samples.map(|samples| {
+                // Audio files play without gaps, so samples follow each other
+                let timestamp = position_timestamp(self.position);
+                self.position += (samples.len() / CHANNELS) as i64;
+                (timestamp, samples)
+            })
+        })
+    }
+}
+
+impl Drop for AudioDecoder {
+    fn drop(&mut self) {
+        unsafe {
+            av_packet_free(&mut self.packet);
+            av_frame_free(&mut self.frame);
+            swr_free(&mut self.swr_ctx);
+            avcodec_free_context(&mut self.codec_ctx);
+            if !self.fmt_ctx.is_null() {
+                avformat_close_input(&mut self.fmt_ctx);
+            }
+        }
+    }
+}
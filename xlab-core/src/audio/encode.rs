@@ -0,0 +1,229 @@
+use std::ffi::CString;
+use std::ptr;
+use std::time::Duration;
+
+use ffmpeg_sys_next::AVSampleFormat::{AV_SAMPLE_FMT_FLT, AV_SAMPLE_FMT_FLTP, AV_SAMPLE_FMT_S16};
+use ffmpeg_sys_next::*;
+
+use super::{sample_position, AudioTrack, CHANNELS, SAMPLE_RATE};
+use crate::{Result, XlabError};
+
+/// Samples per channel of every frame when the encoder accepts any frame size
+const DEFAULT_FRAME_SIZE: usize = 1024;
+
+/// Gaps in the captured audio shorter than this are closed rather than filled with
+/// silence, so that capture jitter doesn't add up to clicks
+pub(super) const MAX_DRIFT: Duration = Duration::from_millis(100);
+
+/// Encodes audio into a stream of a muxer, next to the video stream of
+/// [`crate::video::VideoEncoder`]
+pub(crate) struct AudioEncoder {
+    codec_ctx: *mut AVCodecContext,
+    stream: *mut AVStream,
+    frame: *mut AVFrame,
+    packet: *mut AVPacket,
+    frame_size: usize,
+    /// Interleaved samples waiting for a full frame
+    pending: Vec<f32>,
+    /// Position of the first pending sample, in samples per channel
+    next_pts: i64,
+}
+
+impl AudioEncoder {
+    /// Adds an audio stream to `fmt_ctx`, which must not have written its header yet
+    pub(crate) unsafe fn new(fmt_ctx: *mut AVFormatContext, track: &AudioTrack) -> Result<Self> {
+        let codec = track.codec;
+        let encoder = codec.find_encoder()?;
+        // Everything allocated so far is released by `drop` when a step fails
+        let mut audio = Self {
+            codec_ctx: ptr::null_mut(),
+            stream: ptr::null_mut(),
+            frame: ptr::null_mut(),
+            packet: ptr::null_mut(),
+            frame_size: DEFAULT_FRAME_SIZE,
+            pending: Vec::new(),
+            next_pts: 0,
+        };
+
+        audio.stream = avformat_new_stream(fmt_ctx, ptr::null());
+        if audio.stream.is_null() {
+            return Err(XlabError::Encode("Failed to create audio stream".into()));
+        }
+        if let Some(title) = &track.title {
+            let title = CString::new(title.as_str())
+                .map_err(|_| XlabError::Config(format!("invalid track title {title}")))?;
+            av_dict_set(
+                &mut (*audio.stream).metadata,
+                c"title".as_ptr(),
+                title.as_ptr(),
+                0,
+            );
+        }
+        audio.codec_ctx = avcodec_alloc_context3(encoder);
+        if audio.codec_ctx.is_null() {
+            return Err(XlabError::Encode("Failed to allocate codec context".into()));
+        }
+        let codec_ctx = audio.codec_ctx;
+        (*codec_ctx).sample_fmt = sample_format(encoder).ok_or_else(|| {
+            XlabError::Encode(format!("the {codec} encoder has no usable sample format"))
+        })?;
+        (*codec_ctx).sample_rate = SAMPLE_RATE as i32;
+        av_channel_layout_default(&mut (*codec_ctx).ch_layout, CHANNELS as i32);
+        (*codec_ctx).bit_rate = codec.bit_rate();
+        (*codec_ctx).time_base = AVRational {
+            num: 1,
+            den: SAMPLE_RATE as i32,
+        };
+        if (*(*fmt_ctx).oformat).flags & AVFMT_GLOBALHEADER != 0 {
+            (*codec_ctx).flags |= AV_CODEC_FLAG_GLOBAL_HEADER as i32;
+        }
+        if avcodec_open2(codec_ctx, encoder, ptr::null_mut()) < 0 {
+            return Err(XlabError::Encode("Failed to open audio codec".into()));
+        }
+
+        (*audio.stream).time_base = (*codec_ctx).time_base;
+        if avcodec_parameters_from_context((*audio.stream).codecpar, codec_ctx) < 0 {
+            return Err(XlabError::Encode("Failed to copy codec parameters".into()));
+        }
+
+        let variable_frame_size =
+            (*encoder).capabilities as u32 & AV_CODEC_CAP_VARIABLE_FRAME_SIZE != 0;
+        if !variable_frame_size && (*codec_ctx).frame_size > 0 {
+            audio.frame_size = (*codec_ctx).frame_size as usize;
+        }
+        audio.frame = av_frame_alloc();
+        audio.packet = av_packet_alloc();
+        if audio.frame.is_null() || audio.packet.is_null() {
+            return Err(XlabError::Encode("Failed to allocate frame".into()));
+        }
+        (*audio.frame).format = (*codec_ctx).sample_fmt as i32;
+        (*audio.frame).sample_rate = (*codec_ctx).sample_rate;
+        (*audio.frame).nb_samples = audio.frame_size as i32;
+        av_channel_layout_copy(&mut (*audio.frame).ch_layout, &(*codec_ctx).ch_layout);
+        if av_frame_get_buffer(audio.frame, 0) < 0 {
+            return Err(XlabError::Encode("Failed to allocate frame buffers".into()));
+        }
+        Ok(audio)
+    }
+
+    /// Appends interleaved samples captured `timestamp` after the recording started.
+    /// Samples are laid end to end; only gaps longer than [`MAX_DRIFT`], such as
+    /// dropped audio, are filled with silence to keep the audio in sync with the video.
+    pub(crate) unsafe fn append(
+        &mut self,
+        fmt_ctx: *mut AVFormatContext,
+        timestamp: Duration,
+        samples: &[f32],
+    ) -> Result<()> {
+        let queued_end = self.next_pts + (self.pending.len() / CHANNELS) as i64;
+        let position = sample_position(timestamp);
+        let max_drift = sample_position(MAX_DRIFT);
+        if position - queued_end > max_drift {
+            let silence = (position - queued_end) as usize * CHANNELS;
+            self.pending.resize(self.pending.len() + silence, 0.0);
+        }
+        self.pending.extend_from_slice(samples);
+        while self.pending.len() >= self.frame_size * CHANNELS {
+            self.encode_frame(fmt_ctx, self.frame_size)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes the pending samples and flushes the encoder
+    pub(crate) unsafe fn finish(&mut self, fmt_ctx: *mut AVFormatContext) -> Result<()> {
+        // Encoders accept a shorter frame at the end of the stream
+        let remaining = self.pending.len() / CHANNELS;
+        if remaining > 0 {
+            self.encode_frame(fmt_ctx, remaining)?;
+        }
+        avcodec_send_frame(self.codec_ctx, ptr::null());
+        self.write_packets(fmt_ctx)
+    }
+
+    /// Encodes the first `frame_samples` pending samples per channel
+    unsafe fn encode_frame(
+        &mut self,
+        fmt_ctx: *mut AVFormatContext,
+        frame_samples: usize,
+    ) -> Result<()> {
+        if av_frame_make_writable(self.frame) < 0 {
+            return Err(XlabError::Encode("Failed to allocate frame buffers".into()));
+        }
+        let samples = &self.pending[..frame_samples * CHANNELS];
+        let frame = &mut *self.frame;
+        match (*self.codec_ctx).sample_fmt {
+            AV_SAMPLE_FMT_FLTP => {
+                for channel in 0..CHANNELS {
+                    let plane = std::slice::from_raw_parts_mut(
+                        frame.data[channel] as *mut f32,
+                        frame_samples,
+                    );
+                    for (output, input) in plane.iter_mut().zip(samples.chunks_exact(CHANNELS)) {
+                        *output = input[channel];
+                    }
+                }
+            }
+            AV_SAMPLE_FMT_FLT => {
+                let data = std::slice::from_raw_parts_mut(frame.data[0] as *mut f32, samples.len());
+                data.copy_from_slice(samples);
+            }
+            _ => {
+                let data = std::slice::from_raw_parts_mut(frame.data[0] as *mut i16, samples.len());
+                for (output, input) in data.iter_mut().zip(samples) {
+                    *output = (input.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                }
+            }
+        }
+        frame.nb_samples = frame_samples as i32;
+        frame.pts = self.next_pts;
+        if avcodec_send_frame(self.codec_ctx, self.frame) < 0 {
+            return Err(XlabError::Encode("Failed to send audio to encoder".into()));
+        }
+        self.pending.drain(..frame_samples * CHANNELS);
+        self.next_pts += frame_samples as i64;
+        self.write_packets(fmt_ctx)
+    }
+
+    unsafe fn write_packets(&mut self, fmt_ctx: *mut AVFormatContext) -> Result<()> {
+        while avcodec_receive_packet(self.codec_ctx, self.packet) >= 0 {
+            av_packet_rescale_ts(
+                self.packet,
+                (*self.codec_ctx).time_base,
+                (*self.stream).time_base,
+            );
+            (*self.packet).stream_index = (*self.stream).index;
+            if av_interleaved_write_frame(fmt_ctx, self.packet) < 0 {
+                return Err(XlabError::Encode("Failed to write audio".into()));
+            }
+            av_packet_unref(self.packet);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AudioEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            av_packet_free(&mut self.packet);
+            av_frame_free(&mut self.frame);
+            avcodec_free_context(&mut self.codec_ctx);
+        }
+    }
+}
+
+/// Picks the sample format of `encoder` that interleaved float samples are converted to
+unsafe fn sample_format(encoder: *const AVCodec) -> Option<AVSampleFormat> {
+    let supported = (*encoder).sample_fmts;
+    if supported.is_null() {
+        return None;
+    }
+    let mut formats = Vec::new();
+    let mut index = 0;
+    while *supported.add(index) != AVSampleFormat::AV_SAMPLE_FMT_NONE {
+        formats.push(*supported.add(index));
+        index += 1;
+    }
+    [AV_SAMPLE_FMT_FLTP, AV_SAMPLE_FMT_FLT, AV_SAMPLE_FMT_S16]
+        .into_iter()
+        .find(|format| formats.contains(format))
+}
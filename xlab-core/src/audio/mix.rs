@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use super::{encode::MAX_DRIFT, position_timestamp, sample_position, CHANNELS};
+
+/// How far the mix may run ahead of an input that stopped delivering samples. Later
+/// samples of the input are mixed in where they still fit and dropped otherwise.
+const MAX_LATENCY: Duration = Duration::from_millis(250);
+
+/// Mixes the audio of several inputs, captured independently of each other, into a
+/// single stream
+pub(crate) struct AudioMixer {
+    /// Position after the last samples of each input, `None` before its first samples
+    ends: Vec<Option<i64>>,
+    /// Position of the first sample of `buffer`, in samples per channel
+    start: i64,
+    /// Sum of the inputs from `start` on, interleaved
+    buffer: Vec<f32>,
+}
+
+impl AudioMixer {
+    pub(crate) fn new(inputs: usize) -> Self {
+        Self {
+            ends: vec![None; inputs],
+            start: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Adds interleaved samples of `input` captured `timestamp` after the recording
+    /// started. Returns the mix that no input can add to anymore, with its timestamp.
+    pub(crate) fn push(
+        &mut self,
+        input: usize,
+        timestamp: Duration,
+        samples: &[f32],
+    ) -> Option<(Duration, Vec<f32>)> {
+        // Samples are laid end to end like the encoder does, so an input keeps its
+        // timing relative to the others
+        let captured_at = sample_position(timestamp);
+        let position = match self.ends[input] {
+            Some(end) if captured_at - end <= sample_position(MAX_DRIFT) => end,
+            _ => captured_at,
+        };
+        self.ends[input] = Some(position + (samples.len() / CHANNELS) as i64);
+
+        let skipped = ((self.start - position).max(0) as usize * CHANNELS).min(samples.len());
+        let offset = (position - self.start).max(0) as usize * CHANNELS;
+        let samples = &samples[skipped..];
+        if self.buffer.len() < offset + samples.len() {
+            self.buffer.resize(offset + samples.len(), 0.0);
+        }
+        for (mixed, sample) in self.buffer[offset..].iter_mut().zip(samples) {
+            *mixed += sample;
+        }
+
+        let latest = self.ends.iter().flatten().copied().max()?;
+        let lagging = self
+            .ends
+            .iter()
+            .map(|end| end.unwrap_or(self.start))
+            .min()?;
+        self.take(lagging.max(latest - sample_position(MAX_LATENCY)))
+    }
+
+    /// Returns the rest of the mix
+    pub(crate) fn finish(&mut self) -> Option<(Duration, Vec<f32>)> {
+        self.take(i64::MAX)
+    }
+
+    /// Removes the mix before `until` from the buffer
+    fn take(&mut self, until: i64) -> Option<(Duration, Vec<f32>)> {
+        let frames = (until - self.start).min((self.buffer.len() / CHANNELS) as i64);
+        if frames <= 0 {
+            return None;
+        }
+        let mixed = self.buffer.drain(..frames as usize * CHANNELS).collect();
+        let timestamp = position_timestamp(self.start);
+        self.start += frames;
+        Some((timestamp, mixed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::SAMPLE_RATE;
+
+    use super::*;
+
+    #[test]
+    fn audio_mixer_sums_inputs() {
+        let mut mixer = AudioMixer::new(2);
+        let chunk = Duration::from_millis(10);
+        let frames = SAMPLE_RATE as usize / 100;
+        // Nothing is mixed until both inputs delivered samples
+        assert!(mixer
+            .push(0, Duration::ZERO, &vec![0.25; frames * CHANNELS])
+            .is_none());
+        let (timestamp, mixed) = mixer
+            .push(1, Duration::ZERO, &vec![0.5; frames * CHANNELS])
+            .unwrap();
+        assert_eq!(timestamp, Duration::ZERO);
+        assert_eq!(mixed, vec![0.75; frames * CHANNELS]);
+
+        // A silent input holds the mix back only for a while
+        let mut mixed = Vec::new();
+        for index in 1..=50 {
+            mixed.extend(mixer.push(0, chunk * index, &vec![0.25; frames * CHANNELS]));
+        }
+        assert!(!mixed.is_empty());
+        assert_eq!(mixed[0].0, chunk);
+        let (_, rest) = mixer.finish().unwrap();
+        assert!(rest.iter().all(|sample| *sample == 0.25));
+    }
+}
@@ -0,0 +1,179 @@
+use std::ffi::CStr;
+use std::time::Duration;
+
+use ffmpeg_sys_next::*;
+
+use crate::{user::UserOptions, Result, XlabError};
+
+mod decode;
+mod encode;
+mod mix;
+mod pulse;
+mod store;
+mod tone;
+
+pub(crate) use pulse::ensure_capture_available;
+pub use pulse::{available_microphones, AudioDevice, PulseSource};
+pub use tone::ToneSource;
+
+pub(crate) use decode::AudioDecoder;
+pub(crate) use encode::AudioEncoder;
+pub(crate) use mix::AudioMixer;
+pub(crate) use store::{AudioStoreReader, AudioStoreWriter};
+
+/// Sample rate of all captured and encoded audio
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Channels of all captured and encoded audio
+pub const CHANNELS: usize = 2;
+
+/// Loudest gain an audio input can be amplified with
+pub const MAX_GAIN: f32 = 4.0;
+
+/// Something the recorder can capture audio from
+pub trait AudioSource {
+    /// Blocks until the next samples are captured and returns them, interleaved,
+    /// at [`SAMPLE_RATE`] with [`CHANNELS`] channels
+    fn next_samples(&mut self) -> Result<Vec<f32>>;
+}
+
+/// Creates the audio source of a recording from the options it was started with.
+/// Sources are created on their own capture thread, so they don't need to be `Send`.
+pub type AudioSourceFactory = dyn Fn(&UserOptions) -> Result<Box<dyn AudioSource>> + Send + Sync;
+
+pub(crate) fn default_audio_source(_: &UserOptions) -> Result<Box<dyn AudioSource>> {
+    Ok(Box::new(PulseSource::new(PulseSource::DEFAULT_MONITOR)?))
+}
+
+pub(crate) fn default_microphone_source(options: &UserOptions) -> Result<Box<dyn AudioSource>> {
+    let device = options
+        .microphone_device
+        .as_deref()
+        .unwrap_or(PulseSource::DEFAULT_SOURCE);
+    Ok(Box::new(PulseSource::new(device)?))
+}
+
+/// Duration of `samples` interleaved samples
+pub(crate) fn samples_duration(samples: usize) -> Duration {
+    let frames = (samples / CHANNELS) as u64;
+    Duration::from_micros(frames * 1_000_000 / SAMPLE_RATE as u64)
+}
+
+/// Position of the sample played `timestamp` after the recording started, in samples
+/// per channel
+pub(crate) fn sample_position(timestamp: Duration) -> i64 {
+    (timestamp.as_micros() * SAMPLE_RATE as u128 / 1_000_000) as i64
+}
+
+/// Inverse of [`sample_position`]
+pub(crate) fn position_timestamp(position: i64) -> Duration {
+    Duration::from_micros(position.max(0) as u64 * 1_000_000 / SAMPLE_RATE as u64)
+}
+
+/// What the audio of a recording is captured from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum AudioInput {
+    /// Everything the system plays
+    System,
+    /// Narration from a microphone
+    Microphone,
+}
+
+impl AudioInput {
+    pub const ALL: [AudioInput; 2] = [Self::System, Self::Microphone];
+}
+
+impl std::fmt::Display for AudioInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::System => "System audio",
+            Self::Microphone => "Microphone",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How several audio inputs are laid out in a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum AudioMix {
+    /// All inputs are mixed into a single track
+    #[default]
+    Mixed,
+    /// Every input gets its own track, to be balanced when editing
+    Separate,
+}
+
+/// An audio stream of a video
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioTrack {
+    pub codec: AudioCodec,
+    /// Title players show when choosing between tracks
+    pub title: Option<String>,
+}
+
+/// Audio codecs recordings can be encoded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum AudioCodec {
+    #[default]
+    Aac,
+    Opus,
+}
+
+impl AudioCodec {
+    pub const ALL: [AudioCodec; 2] = [Self::Aac, Self::Opus];
+
+    /// Name of the ffmpeg encoder of the codec
+    fn encoder_name(self) -> &'static CStr {
+        match self {
+            Self::Aac => c"aac",
+            // The native Opus encoder of ffmpeg is experimental
+            Self::Opus => c"libopus",
+        }
+    }
+
+    /// Bitrate in bit/s, enough for both codecs to be transparent on stereo audio
+    fn bit_rate(self) -> i64 {
+        match self {
+            Self::Aac => 192_000,
+            Self::Opus => 128_000,
+        }
+    }
+
+    pub(crate) fn codec_id(self) -> AVCodecID {
+        match self {
+            Self::Aac => AVCodecID::AV_CODEC_ID_AAC,
+            Self::Opus => AVCodecID::AV_CODEC_ID_OPUS,
+        }
+    }
+
+    pub(crate) fn find_encoder(self) -> Result<*const AVCodec> {
+        let codec = unsafe { avcodec_find_encoder_by_name(self.encoder_name().as_ptr()) };
+        if codec.is_null() {
+            return Err(XlabError::Encode(format!("no {self} encoder is available")));
+        }
+        Ok(codec)
+    }
+
+    /// Returns whether ffmpeg provides an encoder for the codec
+    pub fn is_available(self) -> bool {
+        self.find_encoder().is_ok()
+    }
+}
+
+impl std::fmt::Display for AudioCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Aac => "AAC",
+            Self::Opus => "Opus",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Lists the audio codecs that can be encoded with the ffmpeg build in use
+pub fn available_audio_codecs() -> Vec<AudioCodec> {
+    AudioCodec::ALL
+        .into_iter()
+        .filter(|codec| codec.is_available())
+        .collect()
+}
@@ -0,0 +1,178 @@
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::sync::Once;
+
+use ffmpeg_sys_next::*;
+
+use super::{AudioSource, CHANNELS, SAMPLE_RATE};
+use crate::{Result, XlabError};
+
+/// An audio device that can be recorded from
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct AudioDevice {
+    /// Name the device is opened by
+    pub name: String,
+    /// Human readable name of the device
+    pub description: String,
+}
+
+fn register_devices() {
+    static REGISTER_DEVICES: Once = Once::new();
+    REGISTER_DEVICES.call_once(|| unsafe { avdevice_register_all() });
+}
+
+/// The pulse input device of ffmpeg
+fn pulse_format() -> Result<*const AVInputFormat> {
+    register_devices();
+    let format = unsafe { av_find_input_format(c"pulse".as_ptr()) };
+    if format.is_null() {
+        return Err(XlabError::Capture(
+            "audio capture needs ffmpeg built with PulseAudio support".into(),
+        ));
+    }
+    Ok(format)
+}
+
+/// Fails unless audio can be captured here. PulseAudio is the only backend, so other
+/// platforms and ffmpeg builds without it can't record audio at all.
+pub(crate) fn ensure_capture_available() -> Result<()> {
+    match pulse_format() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(XlabError::Config(
+            "recording audio needs PulseAudio, which isn't available".into(),
+        )),
+    }
+}
+
+/// Lists the PulseAudio sources that capture from an input device, leaving out the
+/// monitors of outputs
+pub fn available_microphones() -> Result<Vec<AudioDevice>> {
+    let format = pulse_format()?;
+    let mut devices = Vec::new();
+    unsafe {
+        let mut list = ptr::null_mut();
+        if avdevice_list_input_sources(format, ptr::null(), ptr::null_mut(), &mut list) < 0 {
+            return Err(XlabError::Capture("Failed to list audio devices".into()));
+        }
+        for index in 0..(*list).nb_devices as usize {
+            let device = &**(*list).devices.add(index);
+            let name = CStr::from_ptr(device.device_name).to_string_lossy();
+            if name.ends_with(".monitor") {
+                continue;
+            }
+            let description = match device.device_description.is_null() {
+                true => name.to_string(),
+                false => CStr::from_ptr(device.device_description)
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            devices.push(AudioDevice {
+                name: name.into_owned(),
+                description,
+            });
+        }
+        avdevice_free_list_devices(&mut list);
+    }
+    Ok(devices)
+}
+
+/// Captures audio from a PulseAudio source through the pulse input device of ffmpeg.
+/// PipeWire provides the same sources through its PulseAudio server.
+pub struct PulseSource {
+    fmt_ctx: *mut AVFormatContext,
+    packet: *mut AVPacket,
+}
+
+impl PulseSource {
+    /// Monitor of the default output, which plays back everything the system plays
+    pub const DEFAULT_MONITOR: &'static str = "@DEFAULT_MONITOR@";
+
+    /// The default input, usually a microphone
+    pub const DEFAULT_SOURCE: &'static str = "@DEFAULT_SOURCE@";
+
+    /// Opens the PulseAudio source named `device`, such as [`Self::DEFAULT_MONITOR`],
+    /// the monitor of a sink, `<sink name>.monitor`, or one of
+    /// [`available_microphones`]
+    pub fn new(device: &str) -> Result<Self> {
+        let format = pulse_format()?;
+        let device_c = CString::new(device)
+            .map_err(|_| XlabError::Config(format!("invalid audio device {device}")))?;
+        // Everything allocated so far is released by `drop` when a step fails
+        let mut source = Self {
+            fmt_ctx: ptr::null_mut(),
+            packet: ptr::null_mut(),
+        };
+        unsafe {
+            let mut options = ptr::null_mut();
+            let sample_rate = CString::new(SAMPLE_RATE.to_string()).unwrap();
+            let channels = CString::new(CHANNELS.to_string()).unwrap();
+            av_dict_set(
+                &mut options,
+                c"sample_rate".as_ptr(),
+                sample_rate.as_ptr(),
+                0,
+            );
+            av_dict_set(&mut options, c"channels".as_ptr(), channels.as_ptr(), 0);
+            av_dict_set(&mut options, c"name".as_ptr(), c"xlab".as_ptr(), 0);
+            let opened =
+                avformat_open_input(&mut source.fmt_ctx, device_c.as_ptr(), format, &mut options);
+            av_dict_free(&mut options);
+            if opened < 0 {
+                return Err(XlabError::Capture(format!(
+                    "Failed to open audio device {device}"
+                )));
+            }
+
+            // The pulse device always delivers native endian 16 bit samples
+            let codecpar = (**(*source.fmt_ctx).streams).codecpar;
+            let expected_codec = if cfg!(target_endian = "little") {
+                AVCodecID::AV_CODEC_ID_PCM_S16LE
+            } else {
+                AVCodecID::AV_CODEC_ID_PCM_S16BE
+            };
+            if (*codecpar).codec_id != expected_codec
+                || (*codecpar).sample_rate != SAMPLE_RATE as i32
+                || (*codecpar).ch_layout.nb_channels != CHANNELS as i32
+            {
+                return Err(XlabError::Capture(format!(
+                    "audio device {device} has an unexpected sample format"
+                )));
+            }
+
+            source.packet = av_packet_alloc();
+            if source.packet.is_null() {
+                return Err(XlabError::Capture("Failed to allocate packet".into()));
+            }
+        }
+        Ok(source)
+    }
+}
+
+impl AudioSource for PulseSource {
+    fn next_samples(&mut self) -> Result<Vec<f32>> {
+        unsafe {
+            if av_read_frame(self.fmt_ctx, self.packet) < 0 {
+                return Err(XlabError::Capture("Failed to read audio".into()));
+            }
+            let bytes =
+                std::slice::from_raw_parts((*self.packet).data, (*self.packet).size as usize);
+            let samples = bytes
+                .chunks_exact(2)
+                .map(|sample| i16::from_ne_bytes([sample[0], sample[1]]) as f32 / 32768.0)
+                .collect();
+            av_packet_unref(self.packet);
+            Ok(samples)
+        }
+    }
+}
+
+impl Drop for PulseSource {
+    fn drop(&mut self) {
+        unsafe {
+            av_packet_free(&mut self.packet);
+            if !self.fmt_ctx.is_null() {
+                avformat_close_input(&mut self.fmt_ctx);
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Append-only store for the audio of a recording, kept next to its frame store.
+//!
+//! ```text
+//! header:  MAGIC (8 bytes) | version (u32)
+//! chunk:   track (u32) | timestamp in µs (u64) | sample count (u32) | samples (f32 each)
+//! ```
+//!
+//! All numbers are little endian and samples are interleaved. Timestamps count from
+//! the start of the recording and chunks of different tracks may alternate. A
+//! truncated last chunk is dropped when reading.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{Result, XlabError};
+
+const MAGIC: &[u8; 8] = b"XLABAUDO";
+const VERSION: u32 = 2;
+
+pub(crate) struct AudioStoreWriter {
+    file: BufWriter<File>,
+}
+
+impl AudioStoreWriter {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self { file })
+    }
+
+    /// Appends samples of `track` captured `timestamp` after the recording started
+    pub(crate) fn append(
+        &mut self,
+        track: usize,
+        timestamp: Duration,
+        samples: &[f32],
+    ) -> Result<()> {
+        self.file.write_all(&(track as u32).to_le_bytes())?;
+        self.file
+            .write_all(&(timestamp.as_micros() as u64).to_le_bytes())?;
+        self.file.write_all(&(samples.len() as u32).to_le_bytes())?;
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+/// Reads the chunks of an audio store in order, with the track they belong to
+pub(crate) struct AudioStoreReader {
+    file: BufReader<File>,
+}
+
+impl AudioStoreReader {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0; 12];
+        file.read_exact(&mut header)?;
+        if &header[..8] != MAGIC || header[8..] != VERSION.to_le_bytes() {
+            return Err(XlabError::Config(format!(
+                "{} is not an audio store",
+                path.display()
+            )));
+        }
+        Ok(Self { file })
+    }
+
+    fn read_chunk(&mut self) -> std::io::Result<(usize, Duration, Vec<f32>)> {
+        let mut header = [0; 16];
+        self.file.read_exact(&mut header)?;
+        let track = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let timestamp = u64::from_le_bytes(header[4..12].try_into().unwrap());
+        let count = u32::from_le_bytes(header[12..].try_into().unwrap()) as usize;
+        let mut bytes = vec![0; count * 4];
+        self.file.read_exact(&mut bytes)?;
+        let samples = bytes
+            .chunks_exact(4)
+            .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+            .collect();
+        Ok((track, Duration::from_micros(timestamp), samples))
+    }
+}
+
+impl Iterator for AudioStoreReader {
+    type Item = Result<(usize, Duration, Vec<f32>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_chunk() {
+            Ok(chunk) => Some(Ok(chunk)),
+            // The end of the store, or a chunk cut short by a crash
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => None,
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant};
+
+use super::{AudioSource, CHANNELS, SAMPLE_RATE};
+use crate::Result;
+
+/// Samples per channel returned at once, 10 ms like a typical capture device
+const CHUNK_FRAMES: u64 = SAMPLE_RATE as u64 / 100;
+
+/// Deterministic audio source for recording without a sound server. It plays a sine
+/// tone on every channel, returning samples at the pace a capture device would.
+pub struct ToneSource {
+    frequency: f32,
+    amplitude: f32,
+    position: u64,
+    started: Option<Instant>,
+}
+
+impl ToneSource {
+    pub fn new(frequency: f32) -> Self {
+        Self {
+            frequency,
+            amplitude: 0.5,
+            position: 0,
+            started: None,
+        }
+    }
+
+    /// Renders the sample at `position`, which is always the same for the same position
+    pub fn sample(&self, position: u64) -> f32 {
+        let phase = (position as f64 * self.frequency as f64 / SAMPLE_RATE as f64).fract();
+        (phase as f32 * TAU).sin() * self.amplitude
+    }
+}
+
+impl AudioSource for ToneSource {
+    fn next_samples(&mut self) -> Result<Vec<f32>> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let end = self.position + CHUNK_FRAMES;
+        let due = started + Duration::from_micros(end * 1_000_000 / SAMPLE_RATE as u64);
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+
+        let samples = (self.position..end)
+            .flat_map(|position| std::iter::repeat_n(self.sample(position), CHANNELS))
+            .collect();
+        self.position = end;
+        Ok(samples)
+    }
+}
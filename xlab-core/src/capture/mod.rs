@@ -0,0 +1,286 @@
+use mouse_position::mouse_position;
+use xcap::image::{imageops, RgbaImage};
+
+use crate::{user::UserOptions, Result, XlabError};
+
+mod synthetic;
+mod window;
+
+pub use synthetic::SyntheticSource;
+pub use window::{windows, WindowInfo, WindowSource};
+
+pub(crate) use window::find_window;
+
+/// Something the capture loop can record frames and cursor positions from
+pub trait CaptureSource {
+    /// Captures the next frame
+    fn next_frame(&mut self) -> Result<RgbaImage>;
+
+    /// Returns the cursor position in frame coordinates, or `None` when the cursor
+    /// is outside of the captured area
+    fn cursor_position(&mut self) -> Option<(u32, u32)>;
+
+    /// Returns whether a mouse button is held down. Sources that can't tell report
+    /// `false`, so that only a resting cursor zooms in.
+    fn cursor_pressed(&mut self) -> bool {
+        false
+    }
+
+    /// Returns the dimensions of the captured frames
+    fn dimensions(&self) -> (u32, u32);
+}
+
+/// Creates the capture source of a recording from the options it was started with.
+/// Sources are created on the capture thread, so they don't need to be `Send`.
+pub type SourceFactory = dyn Fn(&UserOptions) -> Result<Box<dyn CaptureSource>> + Send + Sync;
+
+pub(crate) fn default_source(user_options: &UserOptions) -> Result<Box<dyn CaptureSource>> {
+    if let Some(id) = user_options.window {
+        return Ok(Box::new(WindowSource::new(find_window(id)?)?));
+    }
+    let monitor = find_monitor(user_options.monitor)?;
+    Ok(Box::new(MonitorSource::new(monitor)?))
+}
+
+/// Rectangle of a monitor to record, in monitor coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CaptureRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CaptureRegion {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Fails unless the region is non-empty and fits into an area of the given dimensions
+    pub fn validate(&self, (width, height): (u32, u32)) -> Result<()> {
+        let fits = self.width > 0
+            && self.height > 0
+            && self.x.saturating_add(self.width) <= width
+            && self.y.saturating_add(self.height) <= height;
+        if !fits {
+            return Err(XlabError::Config(format!(
+                "region {}x{} at ({}, {}) does not fit into {width}x{height}",
+                self.width, self.height, self.x, self.y
+            )));
+        }
+        Ok(())
+    }
+
+    /// Crops a captured frame to the region
+    pub fn crop(&self, frame: &RgbaImage) -> RgbaImage {
+        imageops::crop_imm(frame, self.x, self.y, self.width, self.height).to_image()
+    }
+
+    /// Translates a position in frame coordinates into the region,
+    /// returning `None` when it falls outside of the region
+    pub fn translate(&self, (x, y): (u32, u32)) -> Option<(u32, u32)> {
+        translate_position(
+            (x as i32, y as i32),
+            (self.x as i32, self.y as i32),
+            self.dimensions(),
+        )
+    }
+}
+
+/// Description of a monitor, as shown to users choosing what to record
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MonitorInfo {
+    pub id: u32,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+    pub is_primary: bool,
+}
+
+impl MonitorInfo {
+    fn new(monitor: &xcap::Monitor) -> Result<Self> {
+        Ok(Self {
+            id: monitor.id()?,
+            name: monitor.name()?,
+            x: monitor.x()?,
+            y: monitor.y()?,
+            width: monitor.width()?,
+            height: monitor.height()?,
+            scale_factor: monitor.scale_factor()?,
+            is_primary: monitor.is_primary()?,
+        })
+    }
+}
+
+/// Lists the monitors that can be recorded
+pub fn monitors() -> Result<Vec<MonitorInfo>> {
+    xcap::Monitor::all()?.iter().map(MonitorInfo::new).collect()
+}
+
+/// Finds the monitor with the given id, or the primary monitor when `id` is `None`
+pub(crate) fn find_monitor(id: Option<u32>) -> Result<xcap::Monitor> {
+    let monitor = pick_monitor(
+        xcap::Monitor::all()?,
+        id,
+        |m| m.id().ok(),
+        |m| m.is_primary().unwrap_or(false),
+    );
+    monitor.ok_or_else(|| match id {
+        Some(id) => XlabError::Capture(format!("monitor {id} not found")),
+        None => XlabError::Capture("no monitor found".into()),
+    })
+}
+
+/// Picks the monitor with the given id, or the primary monitor when `id` is `None`
+fn pick_monitor<M>(
+    monitors: Vec<M>,
+    id: Option<u32>,
+    id_of: impl Fn(&M) -> Option<u32>,
+    is_primary: impl Fn(&M) -> bool,
+) -> Option<M> {
+    match id {
+        Some(id) => monitors.into_iter().find(|m| id_of(m) == Some(id)),
+        None => {
+            // Fall back to the first monitor when none is reported as primary
+            let primary = monitors.iter().position(is_primary).unwrap_or(0);
+            monitors.into_iter().nth(primary)
+        }
+    }
+}
+
+/// Captures a whole monitor through xcap
+pub struct MonitorSource {
+    monitor: xcap::Monitor,
+    origin: (i32, i32),
+    dimensions: (u32, u32),
+}
+
+impl MonitorSource {
+    pub fn new(monitor: xcap::Monitor) -> Result<Self> {
+        let origin = (monitor.x()?, monitor.y()?);
+        let dimensions = (monitor.width()?, monitor.height()?);
+        Ok(Self {
+            monitor,
+            origin,
+            dimensions,
+        })
+    }
+
+    /// Captures the primary monitor
+    pub fn primary() -> Result<Self> {
+        Self::new(find_monitor(None)?)
+    }
+}
+
+impl CaptureSource for MonitorSource {
+    fn next_frame(&mut self) -> Result<RgbaImage> {
+        Ok(self.monitor.capture_image()?)
+    }
+
+    fn cursor_position(&mut self) -> Option<(u32, u32)> {
+        let (x, y) = global_mouse_position()?;
+        translate_position((x, y), self.origin, self.dimensions)
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+}
+
+/// Returns the cursor position in global desktop coordinates
+pub(crate) fn global_mouse_position() -> Option<(i32, i32)> {
+    match mouse_position::Mouse::get_mouse_position() {
+        mouse_position::Mouse::Position { x, y } => Some((x, y)),
+        mouse_position::Mouse::Error => None,
+    }
+}
+
+/// Translates a global position into an area starting at `origin`,
+/// returning `None` when it falls outside of the area
+pub(crate) fn translate_position(
+    (x, y): (i32, i32),
+    origin: (i32, i32),
+    (width, height): (u32, u32),
+) -> Option<(u32, u32)> {
+    let (dx, dy) = (x - origin.0, y - origin.1);
+    if dx < 0 || dy < 0 || dx as u32 >= width || dy as u32 >= height {
+        return None;
+    }
+    Some((dx as u32, dy as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monitor_falls_back_to_primary() {
+        // Monitors as their id and whether they are the primary one
+        let pick = |monitors: &[(u32, bool)], id| {
+            pick_monitor(monitors.to_vec(), id, |m| Some(m.0), |m| m.1).map(|m| m.0)
+        };
+        let monitors = [(1, false), (2, true), (3, false)];
+        assert_eq!(pick(&monitors, Some(3)), Some(3));
+        assert_eq!(pick(&monitors, Some(4)), None);
+        assert_eq!(pick(&monitors, None), Some(2));
+        assert_eq!(pick(&[(1, false), (2, false)], None), Some(1));
+        assert_eq!(pick(&[], None), None);
+    }
+
+    #[test]
+    fn region_must_fit() {
+        let monitor = (320, 180);
+        CaptureRegion::new(0, 0, 320, 180)
+            .validate(monitor)
+            .unwrap();
+        CaptureRegion::new(40, 30, 160, 90)
+            .validate(monitor)
+            .unwrap();
+        assert!(CaptureRegion::new(200, 0, 160, 90)
+            .validate(monitor)
+            .is_err());
+        assert!(CaptureRegion::new(0, 100, 160, 90)
+            .validate(monitor)
+            .is_err());
+        assert!(CaptureRegion::new(0, 0, 0, 90).validate(monitor).is_err());
+        assert!(CaptureRegion::new(u32::MAX, 0, 2, 2)
+            .validate(monitor)
+            .is_err());
+    }
+
+    #[test]
+    fn region_crops_frames_and_pointer() {
+        let region = CaptureRegion::new(40, 30, 160, 90);
+        // The cursor alternates between a point inside the region and one outside of it
+        let mut source =
+            SyntheticSource::new(320, 180).with_cursor_path(vec![(50, 40), (300, 170)]);
+        region.validate(source.dimensions()).unwrap();
+
+        let frame = source.next_frame().unwrap();
+        let cropped = region.crop(&frame);
+        assert_eq!(cropped.dimensions(), (160, 90));
+        assert_eq!(cropped.get_pixel(0, 0), frame.get_pixel(40, 30));
+        assert_eq!(cropped.get_pixel(159, 89), frame.get_pixel(199, 119));
+        let position = source.cursor_position().unwrap();
+        assert_eq!(region.translate(position), Some((10, 10)));
+
+        source.next_frame().unwrap();
+        let position = source.cursor_position().unwrap();
+        assert_eq!(region.translate(position), None);
+        // The edges of the region are inside it up to its last pixel
+        assert_eq!(region.translate((199, 119)), Some((159, 89)));
+        assert_eq!(region.translate((200, 119)), None);
+    }
+}
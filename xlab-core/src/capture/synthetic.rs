@@ -0,0 +1,101 @@
+use xcap::image::{Rgba, RgbaImage};
+
+use super::CaptureSource;
+use crate::Result;
+
+const BAR_COLORS: [[u8; 3]; 8] = [
+    [192, 192, 192],
+    [192, 192, 0],
+    [0, 192, 192],
+    [0, 192, 0],
+    [192, 0, 192],
+    [192, 0, 0],
+    [0, 0, 192],
+    [16, 16, 16],
+];
+
+/// Deterministic capture source for recording without a display.
+/// Frames are color bars that scroll by a few pixels each frame above a gray ramp,
+/// and the cursor follows a scripted path, one point per frame, looping at the end.
+pub struct SyntheticSource {
+    dimensions: (u32, u32),
+    frame_index: u64,
+    cursor_path: Vec<(u32, u32)>,
+    /// Indices of the frames during which a mouse button is held down
+    presses: Vec<u64>,
+}
+
+impl SyntheticSource {
+    /// Creates a source whose cursor walks the diagonal of the frame
+    pub fn new(width: u32, height: u32) -> Self {
+        const STEPS: u32 = 60;
+        let cursor_path = (0..STEPS)
+            .map(|i| {
+                (
+                    i * width.saturating_sub(1) / (STEPS - 1),
+                    i * height.saturating_sub(1) / (STEPS - 1),
+                )
+            })
+            .collect();
+        Self {
+            dimensions: (width, height),
+            frame_index: 0,
+            cursor_path,
+            presses: Vec::new(),
+        }
+    }
+
+    /// Replaces the cursor path. An empty path keeps the cursor hidden.
+    pub fn with_cursor_path(mut self, cursor_path: Vec<(u32, u32)>) -> Self {
+        self.cursor_path = cursor_path;
+        self
+    }
+
+    /// Holds a mouse button down during the frames at `presses`
+    pub fn with_presses(mut self, presses: Vec<u64>) -> Self {
+        self.presses = presses;
+        self
+    }
+
+    /// Renders the frame at `index`, which is always the same for the same index
+    pub fn frame(&self, index: u64) -> RgbaImage {
+        let (width, height) = self.dimensions;
+        let bar_width = (width / BAR_COLORS.len() as u32).max(1);
+        let offset = (index * 4 % width.max(1) as u64) as u32;
+        RgbaImage::from_fn(width, height, |x, y| {
+            if y < height * 3 / 4 {
+                let bar = ((x + offset) % width / bar_width) as usize % BAR_COLORS.len();
+                let [r, g, b] = BAR_COLORS[bar];
+                Rgba([r, g, b, 255])
+            } else {
+                let level = (x * 255 / width.max(1)) as u8;
+                Rgba([level, level, level, 255])
+            }
+        })
+    }
+}
+
+impl CaptureSource for SyntheticSource {
+    fn next_frame(&mut self) -> Result<RgbaImage> {
+        let frame = self.frame(self.frame_index);
+        self.frame_index += 1;
+        Ok(frame)
+    }
+
+    fn cursor_position(&mut self) -> Option<(u32, u32)> {
+        if self.cursor_path.is_empty() {
+            return None;
+        }
+        // The cursor belongs to the frame that was captured last
+        let index = self.frame_index.saturating_sub(1) as usize % self.cursor_path.len();
+        Some(self.cursor_path[index])
+    }
+
+    fn cursor_pressed(&mut self) -> bool {
+        self.presses.contains(&self.frame_index.saturating_sub(1))
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+}
@@ -0,0 +1,140 @@
+use xcap::image::{Rgba, RgbaImage};
+
+use super::{global_mouse_position, translate_position, CaptureSource};
+use crate::{Letterbox, Result, XlabError};
+
+/// Description of a window, as shown to users choosing what to record
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WindowInfo {
+    pub id: u32,
+    pub title: String,
+    pub app_name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WindowInfo {
+    fn new(window: &xcap::Window) -> Result<Self> {
+        Ok(Self {
+            id: window.id()?,
+            title: window.title()?,
+            app_name: window.app_name()?,
+            x: window.x()?,
+            y: window.y()?,
+            width: window.width()?,
+            height: window.height()?,
+        })
+    }
+}
+
+/// Lists the windows that can be recorded, skipping minimized ones
+pub fn windows() -> Result<Vec<WindowInfo>> {
+    xcap::Window::all()?
+        .iter()
+        .filter(|window| !window.is_minimized().unwrap_or(false))
+        .map(WindowInfo::new)
+        .collect()
+}
+
+pub(crate) fn find_window(id: u32) -> Result<xcap::Window> {
+    xcap::Window::all()?
+        .into_iter()
+        .find(|window| window.id().ok() == Some(id))
+        .ok_or_else(|| XlabError::Capture(format!("window {id} not found")))
+}
+
+/// Captures a single window, following it when it moves.
+/// Frames keep the size the window had when recording started; when the window is
+/// resized, its content is scaled to fit and letterboxed.
+pub struct WindowSource {
+    window: xcap::Window,
+    dimensions: (u32, u32),
+    window_dimensions: (u32, u32),
+    letterbox: Letterbox,
+}
+
+impl WindowSource {
+    pub fn new(window: xcap::Window) -> Result<Self> {
+        let dimensions = (window.width()?, window.height()?);
+        if dimensions.0 == 0 || dimensions.1 == 0 {
+            return Err(XlabError::Capture("the window has no visible area".into()));
+        }
+        Ok(Self {
+            window,
+            dimensions,
+            window_dimensions: dimensions,
+            letterbox: Letterbox::IDENTITY,
+        })
+    }
+}
+
+/// Fits the capture of a window into a frame of `dimensions`. Windows that couldn't be
+/// captured show up as black frames.
+fn window_frame(
+    capture: Option<RgbaImage>,
+    (width, height): (u32, u32),
+) -> Result<(RgbaImage, Letterbox)> {
+    match capture {
+        Some(capture) => crate::letterbox(capture, (width, height)),
+        None => Ok((
+            RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255])),
+            Letterbox::IDENTITY,
+        )),
+    }
+}
+
+impl CaptureSource for WindowSource {
+    fn next_frame(&mut self) -> Result<RgbaImage> {
+        // Minimized windows can't be captured and closed ones are gone
+        let capture = match self.window.is_minimized() {
+            Ok(false) => Some(self.window.capture_image()?),
+            _ => None,
+        };
+        if let Some(capture) = &capture {
+            self.window_dimensions = capture.dimensions();
+        }
+        let (frame, letterbox) = window_frame(capture, self.dimensions)?;
+        self.letterbox = letterbox;
+        Ok(frame)
+    }
+
+    fn cursor_position(&mut self) -> Option<(u32, u32)> {
+        // The window may have moved since the last frame
+        let origin = (self.window.x().ok()?, self.window.y().ok()?);
+        let position =
+            translate_position(global_mouse_position()?, origin, self.window_dimensions)?;
+        Some(self.letterbox.map(position))
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_frames_keep_their_size() {
+        let black = Rgba([0, 0, 0, 255]);
+        let (frame, _) = window_frame(None, (320, 180)).unwrap();
+        assert_eq!(frame.dimensions(), (320, 180));
+        assert!(frame.pixels().all(|pixel| *pixel == black));
+
+        let red = Rgba([255, 0, 0, 255]);
+        let capture = RgbaImage::from_pixel(320, 180, red);
+        let (frame, _) = window_frame(Some(capture.clone()), (320, 180)).unwrap();
+        assert_eq!(frame, capture);
+
+        // A window that got wider is scaled down between black bars
+        let capture = RgbaImage::from_pixel(640, 180, red);
+        let (frame, letterbox) = window_frame(Some(capture), (320, 180)).unwrap();
+        assert_eq!(frame.dimensions(), (320, 180));
+        assert_eq!(*frame.get_pixel(160, 90), red);
+        assert_eq!(*frame.get_pixel(160, 0), black);
+        assert_eq!(letterbox.map((640, 180)), (320, 135));
+    }
+}
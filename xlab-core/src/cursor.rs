@@ -0,0 +1,264 @@
+//! Where the cursor was in every frame of a recording. The track is kept next to the
+//! frames, so that the pointer is drawn when saving and effects that follow the cursor
+//! can be applied after the recording ended.
+//!
+//! Tracks are written one sample per frame as the frames are captured:
+//!
+//! ```text
+//! header:  MAGIC (8 bytes) | version (u32) | width (u32) | height (u32)
+//! sample:  flags (u8) | time since the previous sample in µs (varint) |
+//!          x and y change since the last visible position (zigzag varints, if visible)
+//! ```
+//!
+//! Fixed size integers are little endian, varints are LEB128. The cursor mostly
+//! rests or moves a little between frames, so most samples take a few bytes. A track
+//! that ends in the middle of a sample is read up to the last complete one.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{Result, XlabError};
+
+const MAGIC: &[u8; 8] = b"XLABCRSR";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 20;
+
+const VISIBLE: u8 = 1;
+const PRESSED: u8 = 2;
+
+/// The cursor at the moment a frame was captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorSample {
+    /// Timestamp of the frame
+    pub timestamp: Duration,
+    /// Position in pixels of the frame, `None` while the cursor is outside of it
+    pub position: Option<(u32, u32)>,
+    /// Whether a mouse button is held down
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CursorTrack {
+    /// Dimensions of the frames, which the positions are relative to
+    pub dimensions: (u32, u32),
+    /// Samples in the order of their timestamps
+    pub samples: Vec<CursorSample>,
+}
+
+impl CursorTrack {
+    pub fn new(dimensions: (u32, u32)) -> Self {
+        Self {
+            dimensions,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        if data.len() < HEADER_LEN || &data[..8] != MAGIC {
+            return Err(XlabError::Encode(format!(
+                "{} is not a cursor track",
+                path.display()
+            )));
+        }
+        let field = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        if field(8) != VERSION {
+            return Err(XlabError::Encode(format!(
+                "cursor track version {} is not supported",
+                field(8)
+            )));
+        }
+        let mut track = Self::new((field(12), field(16)));
+        let mut reader = SampleReader {
+            data: &data[HEADER_LEN..],
+            timestamp: 0,
+            position: (0, 0),
+        };
+        while let Some(sample) = reader.next_sample() {
+            track.samples.push(sample);
+        }
+        Ok(track)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut writer = CursorTrackWriter::create(path, self.dimensions)?;
+        for sample in &self.samples {
+            writer.append(sample)?;
+        }
+        writer.finish()
+    }
+
+    /// The last sample taken at or before `timestamp`
+    pub fn sample_at(&self, timestamp: Duration) -> Option<&CursorSample> {
+        let index = self
+            .samples
+            .partition_point(|sample| sample.timestamp <= timestamp);
+        self.samples.get(index.checked_sub(1)?)
+    }
+}
+
+/// Appends the samples of a track to its file as they are taken
+pub struct CursorTrackWriter {
+    file: BufWriter<File>,
+    /// Timestamp of the last sample in µs
+    timestamp: u64,
+    /// Last visible position
+    position: (i64, i64),
+}
+
+impl CursorTrackWriter {
+    pub fn create(path: &Path, (width, height): (u32, u32)) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        file.write_all(&width.to_le_bytes())?;
+        file.write_all(&height.to_le_bytes())?;
+        Ok(Self {
+            file,
+            timestamp: 0,
+            position: (0, 0),
+        })
+    }
+
+    pub fn append(&mut self, sample: &CursorSample) -> Result<()> {
+        let mut flags = 0;
+        if sample.position.is_some() {
+            flags |= VISIBLE;
+        }
+        if sample.pressed {
+            flags |= PRESSED;
+        }
+        let mut buffer = vec![flags];
+        let timestamp = sample.timestamp.as_micros() as u64;
+        write_varint(&mut buffer, timestamp.saturating_sub(self.timestamp));
+        self.timestamp = timestamp.max(self.timestamp);
+        if let Some((x, y)) = sample.position {
+            let position = (x as i64, y as i64);
+            write_varint(&mut buffer, zigzag(position.0 - self.position.0));
+            write_varint(&mut buffer, zigzag(position.1 - self.position.1));
+            self.position = position;
+        }
+        self.file.write_all(&buffer)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Decodes the samples of a track one after the other
+struct SampleReader<'a> {
+    data: &'a [u8],
+    timestamp: u64,
+    position: (i64, i64),
+}
+
+impl SampleReader<'_> {
+    fn next_sample(&mut self) -> Option<CursorSample> {
+        let (&flags, rest) = self.data.split_first()?;
+        self.data = rest;
+        self.timestamp += self.varint()?;
+        let position = match flags & VISIBLE != 0 {
+            true => {
+                self.position.0 += unzigzag(self.varint()?);
+                self.position.1 += unzigzag(self.varint()?);
+                Some((self.position.0 as u32, self.position.1 as u32))
+            }
+            false => None,
+        };
+        Some(CursorSample {
+            timestamp: Duration::from_micros(self.timestamp),
+            position,
+            pressed: flags & PRESSED != 0,
+        })
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for (index, &byte) in self.data.iter().enumerate().take(10) {
+            value |= ((byte & 0x7f) as u64) << (7 * index);
+            if byte & 0x80 == 0 {
+                self.data = &self.data[index + 1..];
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Maps signed integers to unsigned ones so that small changes stay small
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Path of the cursor track that belongs to the recording or frame store at `path`.
+/// The extension of `path` is kept, so that recordings that only differ in it don't
+/// share a track.
+pub fn track_path(path: &Path) -> PathBuf {
+    let mut track_path = path.as_os_str().to_owned();
+    track_path.push(".cursor");
+    PathBuf::from(track_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_track_round_trip() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("round-trip.cursor");
+        let mut track = CursorTrack::new((1920, 1080));
+        for frame in 0..300u32 {
+            track.samples.push(CursorSample {
+                timestamp: Duration::from_micros(frame as u64 * 33_333),
+                position: (frame % 50 != 0).then_some((960 + frame % 7, 540 - frame % 5)),
+                pressed: frame % 30 < 3,
+            });
+        }
+        track.samples.push(CursorSample {
+            timestamp: Duration::from_secs(3600),
+            position: Some((0, 1079)),
+            pressed: false,
+        });
+        track.write(&path).unwrap();
+        assert_eq!(CursorTrack::read(&path).unwrap(), track);
+        // Small moves take a few bytes per frame
+        let size = std::fs::metadata(&path).unwrap().len();
+        assert!(size < 20 + 6 * track.samples.len() as u64);
+
+        // A track cut off in the middle of a sample keeps the samples before it
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        let truncated = CursorTrack::read(&path).unwrap();
+        assert_eq!(truncated.samples, track.samples[..track.samples.len() - 1]);
+        std::fs::write(&path, b"not a cursor track").unwrap();
+        assert!(CursorTrack::read(&path).is_err());
+        std::fs::remove_file(&path).ok();
+
+        // Recordings that only differ in their extension have tracks of their own
+        assert_ne!(
+            track_path(Path::new("demo.mp4")),
+            track_path(Path::new("demo.gif"))
+        );
+    }
+}
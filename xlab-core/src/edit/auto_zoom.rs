@@ -0,0 +1,65 @@
+use std::path::Path;
+use std::time::Duration;
+
+use super::transform::transform_frames;
+use super::InputFile;
+use crate::{
+    cursor::{self, CursorTrack},
+    resize_image,
+    zoom::{AutoZoom, ZoomSettings},
+    Result, XlabError,
+};
+
+/// Writes the recording at `video_path` zoomed in on the cursor as `settings` say to
+/// `output_path`, following the cursor track that was recorded with it. Returns the
+/// duration and resolution of the result; `on_progress` is called with the
+/// milliseconds of the recording done and its duration.
+pub(crate) fn auto_zoom(
+    video_path: &Path,
+    settings: &ZoomSettings,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<(Duration, (u32, u32))> {
+    settings.validate()?;
+    let track_path = cursor::track_path(video_path);
+    if !track_path.exists() {
+        return Err(XlabError::Config(format!(
+            "{} has no cursor track to follow",
+            video_path.display()
+        )));
+    }
+    let track = CursorTrack::read(&track_path)?;
+    let dimensions = InputFile::open(video_path)?
+        .video_dimensions()
+        .ok_or_else(|| {
+            XlabError::Encode(format!("{} has no video stream", video_path.display()))
+        })?;
+    // Positions are in pixels of the recorded frames, which exports may have scaled
+    let scale = |(x, y): (u32, u32)| {
+        let (width, height) = track.dimensions;
+        (
+            (x as u64 * dimensions.0 as u64 / width.max(1) as u64) as u32,
+            (y as u64 * dimensions.1 as u64 / height.max(1) as u64) as u32,
+        )
+    };
+    let mut zoom = AutoZoom::new(*settings, dimensions);
+    let duration = transform_frames(
+        video_path,
+        output_path,
+        dimensions,
+        on_progress,
+        |timestamp, image| {
+            let sample = track.sample_at(timestamp);
+            let position = sample.and_then(|sample| sample.position).map(scale);
+            let pressed = sample.is_some_and(|sample| sample.pressed);
+            let view = zoom.update(timestamp, position, pressed);
+            if view.dimensions() == dimensions {
+                return Ok(image);
+            }
+            let mut image = view.crop(&image);
+            resize_image(&mut image, dimensions)?;
+            Ok(image)
+        },
+    )?;
+    Ok((duration, dimensions))
+}
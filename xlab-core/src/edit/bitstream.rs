@@ -0,0 +1,183 @@
+//! Conversions between the packets of encoders and those of the streams they are
+//! spliced into
+
+use ffmpeg_sys_next::*;
+
+use crate::{Result, XlabError};
+
+/// How the packets of a stream carry their data, and the codec headers that come with
+/// the stream rather than its packets
+pub(crate) struct StreamFormat {
+    /// Size of the length before each NAL unit of length prefixed H.264 and H.265, as
+    /// stored in MP4 and Matroska. `None` for start code prefixed or other codecs.
+    nal_length_size: Option<usize>,
+    /// Codec headers, in the form of the packets of the stream
+    headers: Vec<u8>,
+}
+
+impl StreamFormat {
+    pub(crate) unsafe fn of(codecpar: *const AVCodecParameters) -> Self {
+        let extradata = extradata(codecpar);
+        let parsed = match (*codecpar).codec_id {
+            AVCodecID::AV_CODEC_ID_H264 => parse_avcc(extradata),
+            AVCodecID::AV_CODEC_ID_HEVC => parse_hvcc(extradata),
+            // The configuration OBUs, such as the sequence header, follow the AV1
+            // configuration record
+            AVCodecID::AV_CODEC_ID_AV1 if extradata.len() > 4 => {
+                Some((None, extradata[4..].to_vec()))
+            }
+            _ => None,
+        };
+        let (nal_length_size, headers) = parsed.unwrap_or_default();
+        Self {
+            nal_length_size,
+            headers,
+        }
+    }
+
+    /// Converts the data of a packet of an encoder without global headers, which
+    /// writes H.264 and H.265 with start codes, to the format of the stream
+    pub(crate) fn convert(&self, data: &[u8]) -> Vec<u8> {
+        match self.nal_length_size {
+            Some(length_size) => {
+                let mut converted = Vec::with_capacity(data.len() + 16);
+                for nal in split_annexb(data) {
+                    let length = (nal.len() as u32).to_be_bytes();
+                    converted.extend_from_slice(&length[4 - length_size..]);
+                    converted.extend_from_slice(nal);
+                }
+                converted
+            }
+            None => data.to_vec(),
+        }
+    }
+
+    /// Codec headers of the stream, to be put in front of the first packet copied
+    /// after packets of another encoder, whose headers replaced them in decoders
+    pub(crate) fn headers(&self) -> &[u8] {
+        &self.headers
+    }
+}
+
+/// Codec specific data of a stream, such as a decoder configuration record
+pub(crate) unsafe fn extradata<'a>(codecpar: *const AVCodecParameters) -> &'a [u8] {
+    match (*codecpar).extradata.is_null() {
+        true => &[],
+        false => std::slice::from_raw_parts(
+            (*codecpar).extradata,
+            (*codecpar).extradata_size.max(0) as usize,
+        ),
+    }
+}
+
+/// Reads a big endian number of `size` bytes at `offset`
+fn read_be(data: &[u8], offset: usize, size: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + size)?;
+    Some(
+        bytes
+            .iter()
+            .fold(0, |number, byte| number << 8 | *byte as usize),
+    )
+}
+
+/// Appends the NAL unit of `length` bytes at `offset` with a length prefix
+fn push_nal(
+    headers: &mut Vec<u8>,
+    data: &[u8],
+    offset: usize,
+    length: usize,
+    length_size: usize,
+) -> Option<()> {
+    let nal = data.get(offset..offset + length)?;
+    headers.extend_from_slice(&(length as u32).to_be_bytes()[4 - length_size..]);
+    headers.extend_from_slice(nal);
+    Some(())
+}
+
+/// Parses an H.264 decoder configuration record into its NAL length size and its
+/// parameter sets. Start code prefixed headers are kept as they are.
+fn parse_avcc(extradata: &[u8]) -> Option<(Option<usize>, Vec<u8>)> {
+    if extradata.first() != Some(&1) {
+        return Some((None, extradata.to_vec()));
+    }
+    let length_size = (*extradata.get(4)? as usize & 3) + 1;
+    let mut headers = Vec::new();
+    let mut offset = 5;
+    // Sequence parameter sets, then picture parameter sets
+    for count_mask in [0x1f, 0xff] {
+        let count = *extradata.get(offset)? as usize & count_mask;
+        offset += 1;
+        for _ in 0..count {
+            let length = read_be(extradata, offset, 2)?;
+            push_nal(&mut headers, extradata, offset + 2, length, length_size)?;
+            offset += 2 + length;
+        }
+    }
+    Some((Some(length_size), headers))
+}
+
+/// Parses an H.265 decoder configuration record into its NAL length size and its
+/// parameter sets. Start code prefixed headers are kept as they are.
+fn parse_hvcc(extradata: &[u8]) -> Option<(Option<usize>, Vec<u8>)> {
+    if extradata.first() != Some(&1) {
+        return Some((None, extradata.to_vec()));
+    }
+    let length_size = (*extradata.get(21)? as usize & 3) + 1;
+    let arrays = *extradata.get(22)? as usize;
+    let mut headers = Vec::new();
+    let mut offset = 23;
+    for _ in 0..arrays {
+        let count = read_be(extradata, offset + 1, 2)?;
+        offset += 3;
+        for _ in 0..count {
+            let length = read_be(extradata, offset, 2)?;
+            push_nal(&mut headers, extradata, offset + 2, length, length_size)?;
+            offset += 2 + length;
+        }
+    }
+    Some((Some(length_size), headers))
+}
+
+/// Splits start code prefixed data into its NAL units
+fn split_annexb(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut index = 0;
+    while index + 3 <= data.len() {
+        if data[index..index + 3] == [0, 0, 1] {
+            starts.push((index, index + 3));
+            index += 3;
+        } else {
+            index += 1;
+        }
+    }
+    starts
+        .iter()
+        .enumerate()
+        .map(|(position, &(_, nal_start))| {
+            let mut nal_end = starts
+                .get(position + 1)
+                .map_or(data.len(), |&(next_code, _)| next_code);
+            // The zero of a four byte start code belongs to the next one
+            while nal_end > nal_start && data[nal_end - 1] == 0 {
+                nal_end -= 1;
+            }
+            &data[nal_start..nal_end]
+        })
+        .filter(|nal| !nal.is_empty())
+        .collect()
+}
+
+/// Creates a packet with the properties of `source` that holds `data`
+pub(crate) unsafe fn packet_with_data(
+    source: *const AVPacket,
+    data: &[u8],
+) -> Result<*mut AVPacket> {
+    let mut packet = av_packet_alloc();
+    if packet.is_null() || av_new_packet(packet, data.len() as i32) < 0 {
+        av_packet_free(&mut packet);
+        return Err(XlabError::Encode("Failed to allocate packet".into()));
+    }
+    std::ptr::copy_nonoverlapping(data.as_ptr(), (*packet).data, data.len());
+    av_packet_copy_props(packet, source);
+    Ok(packet)
+}
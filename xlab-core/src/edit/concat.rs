@@ -0,0 +1,425 @@
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ffmpeg_sys_next::*;
+use xcap::image::{Rgba, RgbaImage};
+
+use super::bitstream::extradata;
+use super::{
+    encoding_audio, encoding_codec, output_container, packet_time, InputFile, OutputFile, Timeline,
+    DEFAULT_FPS,
+};
+use crate::{
+    audio::{position_timestamp, sample_position, AudioDecoder, AudioMixer, CHANNELS},
+    letterbox,
+    video::{Container, EncoderConfig, VideoDecoder, VideoEncoder},
+    Result, XlabError,
+};
+
+/// What is shown between two recordings that are joined
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Transition {
+    /// Each recording starts right where the previous one ends
+    #[default]
+    Cut,
+    /// The end of each recording fades into the start of the next one, both video and
+    /// audio. Fades last at most half of either recording.
+    Crossfade(Duration),
+    /// An image, such as a title rendered by the frontend, is shown in silence between
+    /// the recordings
+    TitleCard { image: PathBuf, duration: Duration },
+}
+
+impl Transition {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Self::Crossfade(duration) | Self::TitleCard { duration, .. } if duration.is_zero() => {
+                Err(XlabError::Config(
+                    "transitions must last longer than zero".into(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Joins the recordings at `video_paths` in order into `output_path`, returning the
+/// duration and the resolution of the result. Recordings that simply follow each other
+/// are copied as they are when their streams match; otherwise all of them are encoded
+/// again at the largest resolution and highest frame rate among them, letterboxed
+/// where their aspect ratios differ. `on_progress` is called with the progress and
+/// its total.
+pub(crate) fn concat(
+    video_paths: &[PathBuf],
+    transition: &Transition,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<(Duration, (u32, u32))> {
+    if video_paths.len() < 2 {
+        return Err(XlabError::Config(
+            "at least two recordings are needed to join them".into(),
+        ));
+    }
+    transition.validate()?;
+    let container = output_container(output_path)?;
+    let inputs = video_paths
+        .iter()
+        .map(|video_path| InputFile::open(video_path))
+        .collect::<Result<Vec<_>>>()?;
+    let copyable = inputs
+        .windows(2)
+        .all(|pair| same_streams(&pair[0], &pair[1]));
+    if *transition == Transition::Cut && copyable {
+        copy_joined(inputs, container, output_path, on_progress)
+    } else {
+        encode_joined(
+            video_paths,
+            &inputs,
+            transition,
+            container,
+            output_path,
+            on_progress,
+        )
+    }
+}
+
+/// Whether the packets of both files can be mixed in the same streams
+fn same_streams(first: &InputFile, second: &InputFile) -> bool {
+    first.streams().len() == second.streams().len()
+        && first
+            .streams()
+            .iter()
+            .zip(second.streams())
+            .all(|(&first, &second)| unsafe {
+                let (first, second) = ((*first).codecpar, (*second).codecpar);
+                (*first).codec_type == (*second).codec_type
+                    && (*first).codec_id == (*second).codec_id
+                    && (*first).format == (*second).format
+                    && (*first).width == (*second).width
+                    && (*first).height == (*second).height
+                    && (*first).sample_rate == (*second).sample_rate
+                    && (*first).ch_layout.nb_channels == (*second).ch_layout.nb_channels
+                    && extradata(first) == extradata(second)
+            })
+}
+
+/// Copies the packets of `inputs` one after the other
+fn copy_joined(
+    mut inputs: Vec<InputFile>,
+    container: Container,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<(Duration, (u32, u32))> {
+    let mut output = OutputFile::create(output_path, container)?;
+    let total = inputs.iter().map(InputFile::duration).sum::<Duration>();
+    let total_millis = total.as_millis() as u64;
+    unsafe {
+        let mut streams = Vec::with_capacity(inputs[0].streams().len());
+        for &stream in inputs[0].streams() {
+            streams.push(output.copy_stream(stream)?);
+        }
+        output.write_header()?;
+        let mut timeline = Timeline::new(streams);
+
+        let mut packet = av_packet_alloc();
+        if packet.is_null() {
+            return Err(XlabError::Encode("Failed to allocate packet".into()));
+        }
+        let mut result = Ok(());
+        let mut offset = Duration::ZERO;
+        'inputs: for input in &mut inputs {
+            timeline.start_part(input.start_time(), offset);
+            while input.read_packet(packet) {
+                let index = (*packet).stream_index as usize;
+                let time_base = (*input.streams()[index]).time_base;
+                let time = packet_time(packet, time_base);
+                result = timeline.write(&mut output, packet, time_base, index);
+                av_packet_unref(packet);
+                if result.is_err() {
+                    break 'inputs;
+                }
+                let written = (offset + time).as_millis() as u64;
+                on_progress(written.min(total_millis), total_millis);
+            }
+            offset += input.duration();
+        }
+        av_packet_free(&mut packet);
+        result?;
+        output.finish()?;
+    }
+    let resolution = inputs[0].video_dimensions().unwrap_or_default();
+    Ok((total, resolution))
+}
+
+/// A recording or title card, placed on the timeline of the joined video
+struct Segment {
+    source: Source,
+    offset: Duration,
+    duration: Duration,
+    fade_in: Duration,
+    fade_out: Duration,
+}
+
+enum Source {
+    Recording { path: PathBuf, has_audio: bool },
+    Card(RgbaImage),
+}
+
+impl Segment {
+    fn end(&self) -> Duration {
+        self.offset + self.duration
+    }
+
+    /// Opacity and volume of the segment `elapsed` into it
+    fn gain(&self, elapsed: Duration) -> f32 {
+        let fade = |elapsed: Duration, length: Duration| match length.is_zero() {
+            true => 1.0,
+            false => (elapsed.as_secs_f32() / length.as_secs_f32()).min(1.0),
+        };
+        fade(elapsed, self.fade_in) * fade(self.duration.saturating_sub(elapsed), self.fade_out)
+    }
+}
+
+/// Decoders of a segment that is being shown. Title cards have none.
+struct SegmentState {
+    frames: Option<Peekable<VideoDecoder>>,
+    /// The last frame shown, letterboxed
+    frame: RgbaImage,
+    audio: Option<Peekable<AudioDecoder>>,
+}
+
+impl SegmentState {
+    fn open(segment: &Segment, dimensions: (u32, u32)) -> Result<Self> {
+        match &segment.source {
+            Source::Recording { path, has_audio } => Ok(Self {
+                frames: Some(VideoDecoder::open(path)?.peekable()),
+                frame: RgbaImage::from_pixel(dimensions.0, dimensions.1, Rgba([0, 0, 0, 255])),
+                audio: match has_audio {
+                    true => Some(AudioDecoder::open(path)?.peekable()),
+                    false => None,
+                },
+            }),
+            Source::Card(image) => Ok(Self {
+                frames: None,
+                frame: image.clone(),
+                audio: None,
+            }),
+        }
+    }
+
+    /// The frame shown `elapsed` into the segment
+    fn frame_at(&mut self, elapsed: Duration, dimensions: (u32, u32)) -> Result<&RgbaImage> {
+        let mut latest = None;
+        if let Some(frames) = &mut self.frames {
+            while let Some(next) =
+                frames.next_if(|next| !matches!(next, Ok((timestamp, _)) if *timestamp > elapsed))
+            {
+                latest = Some(next?.1);
+            }
+        }
+        // Only the frame that is shown is scaled
+        if let Some(latest) = latest {
+            self.frame = letterbox(latest, dimensions)?.0;
+        }
+        Ok(&self.frame)
+    }
+
+    /// Passes the audio of the segment up to `elapsed` into it, faded like its video,
+    /// to input `input` of `mixer`
+    fn write_audio(
+        &mut self,
+        segment: &Segment,
+        elapsed: Duration,
+        mixer: &mut AudioMixer,
+        input: usize,
+        encoder: &mut VideoEncoder,
+    ) -> Result<()> {
+        let Some(audio) = &mut self.audio else {
+            return Ok(());
+        };
+        while let Some(chunk) =
+            audio.next_if(|chunk| !matches!(chunk, Ok((timestamp, _)) if *timestamp > elapsed))
+        {
+            let (timestamp, mut samples) = chunk?;
+            if timestamp >= segment.duration {
+                continue;
+            }
+            let start = sample_position(timestamp);
+            let remaining = (sample_position(segment.duration) - start) as usize;
+            samples.truncate(remaining * CHANNELS);
+            for (position, frame) in samples.chunks_mut(CHANNELS).enumerate() {
+                let gain = segment.gain(position_timestamp(start + position as i64));
+                frame.iter_mut().for_each(|sample| *sample *= gain);
+            }
+            if let Some((timestamp, mixed)) =
+                mixer.push(input, segment.offset + timestamp, &samples)
+            {
+                encoder.append_audio(0, &mixed, timestamp)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Mixes `to` into `from`, `amount` being the share of `to`
+fn blend(from: &mut RgbaImage, to: &RgbaImage, amount: f32) {
+    for (from, to) in from.iter_mut().zip(to.iter()) {
+        *from = (*from as f32 + (*to as f32 - *from as f32) * amount).round() as u8;
+    }
+}
+
+/// Decodes `inputs` and encodes them one after the other, with `transition` between
+/// them
+fn encode_joined(
+    video_paths: &[PathBuf],
+    inputs: &[InputFile],
+    transition: &Transition,
+    container: Container,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<(Duration, (u32, u32))> {
+    // 1. Pick the format of the result
+    let (width, height) = inputs
+        .iter()
+        .filter_map(InputFile::video_dimensions)
+        .max_by_key(|&(width, height)| width as u64 * height as u64)
+        .ok_or_else(|| XlabError::Encode("none of the recordings has a video stream".into()))?;
+    // Chroma subsampling needs even dimensions
+    let dimensions = (width & !1, height & !1);
+    let fps = inputs
+        .iter()
+        .filter_map(InputFile::frame_rate)
+        .fold(0.0, f64::max);
+    let fps = match fps.round() as u32 {
+        0 => DEFAULT_FPS,
+        fps => fps,
+    };
+    let codec = encoding_codec(inputs.iter().filter_map(InputFile::video_codec), container)?;
+    let has_audio: Vec<bool> = inputs
+        .iter()
+        .map(|input| input.find_stream(AVMediaType::AVMEDIA_TYPE_AUDIO).is_some())
+        .collect();
+    let audio = match has_audio.contains(&true) {
+        true => vec![encoding_audio(container)?],
+        false => Vec::new(),
+    };
+
+    // 2. Lay out the recordings and title cards
+    let card = match transition {
+        Transition::TitleCard { image, duration } => {
+            let image = VideoDecoder::open(image)?.next().ok_or_else(|| {
+                XlabError::Encode(format!("{} holds no image", image.display()))
+            })??;
+            Some((letterbox(image.1, dimensions)?.0, *duration))
+        }
+        _ => None,
+    };
+    let crossfade = match transition {
+        Transition::Crossfade(duration) => *duration,
+        _ => Duration::ZERO,
+    };
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut end = Duration::ZERO;
+    for ((path, input), has_audio) in video_paths.iter().zip(inputs).zip(has_audio) {
+        let duration = input.duration();
+        let mut fade = Duration::ZERO;
+        if let Some(previous) = segments.last_mut() {
+            if let Some((image, card_duration)) = &card {
+                segments.push(Segment {
+                    source: Source::Card(image.clone()),
+                    offset: end,
+                    duration: *card_duration,
+                    fade_in: Duration::ZERO,
+                    fade_out: Duration::ZERO,
+                });
+                end += *card_duration;
+            } else {
+                // No more than two recordings overlap
+                fade = crossfade.min(duration / 2).min(previous.duration / 2);
+                previous.fade_out = fade;
+            }
+        }
+        segments.push(Segment {
+            source: Source::Recording {
+                path: path.clone(),
+                has_audio,
+            },
+            offset: end - fade,
+            duration,
+            fade_in: fade,
+            fade_out: Duration::ZERO,
+        });
+        end = end - fade + duration;
+    }
+
+    // 3. Encode frames at the common frame rate, blending overlapping recordings
+    let mut encoder = VideoEncoder::new(
+        output_path.to_path_buf(),
+        fps,
+        dimensions,
+        EncoderConfig {
+            codec,
+            container,
+            audio,
+            ..Default::default()
+        },
+    )?;
+    let mut states: Vec<Option<SegmentState>> = segments.iter().map(|_| None).collect();
+    let mut mixer = AudioMixer::new(2);
+    let frame_count = (end.as_micros() as u64 * fps as u64).div_ceil(1_000_000);
+    for index in 0..frame_count {
+        let time = Duration::from_micros(index * 1_000_000 / fps as u64);
+        let mut frame: Option<RgbaImage> = None;
+        for (position, segment) in segments.iter().enumerate() {
+            if time < segment.offset {
+                break;
+            }
+            if time >= segment.end() {
+                // The rest of the audio of a segment that ended
+                if let Some(mut state) = states[position].take() {
+                    state.write_audio(
+                        segment,
+                        segment.duration,
+                        &mut mixer,
+                        position % 2,
+                        &mut encoder,
+                    )?;
+                }
+                continue;
+            }
+            let state = match &mut states[position] {
+                Some(state) => state,
+                state => state.insert(SegmentState::open(segment, dimensions)?),
+            };
+            let elapsed = time - segment.offset;
+            let image = state.frame_at(elapsed, dimensions)?;
+            match &mut frame {
+                Some(frame) => blend(frame, image, segment.gain(elapsed)),
+                None => frame = Some(image.clone()),
+            }
+            state.write_audio(segment, elapsed, &mut mixer, position % 2, &mut encoder)?;
+        }
+        let frame = frame.unwrap_or_else(|| {
+            RgbaImage::from_pixel(dimensions.0, dimensions.1, Rgba([0, 0, 0, 255]))
+        });
+        encoder.append_image(frame, time)?;
+        on_progress(index + 1, frame_count);
+    }
+    for (position, segment) in segments.iter().enumerate() {
+        if let Some(mut state) = states[position].take() {
+            state.write_audio(
+                segment,
+                segment.duration,
+                &mut mixer,
+                position % 2,
+                &mut encoder,
+            )?;
+        }
+    }
+    if let Some((timestamp, mixed)) = mixer.finish() {
+        encoder.append_audio(0, &mixed, timestamp)?;
+    }
+    encoder.finalize()?;
+    Ok((end, dimensions))
+}
@@ -0,0 +1,105 @@
+use std::path::Path;
+use std::time::Duration;
+
+use fast_image_resize::{FilterType, ResizeAlg, ResizeOptions};
+
+use super::transform::transform_frames;
+use super::InputFile;
+use crate::{capture::CaptureRegion, resize_image_with, Result, XlabError};
+
+/// How frames are scaled to another resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ScaleFilter {
+    /// Repeats or skips pixels, keeping hard edges such as those of pixel art
+    Nearest,
+    Bilinear,
+    Bicubic,
+    /// Sharpest, and the filter recordings are scaled with
+    #[default]
+    Lanczos,
+}
+
+impl ScaleFilter {
+    fn options(self) -> ResizeOptions {
+        let algorithm = match self {
+            Self::Nearest => ResizeAlg::Nearest,
+            Self::Bilinear => ResizeAlg::Convolution(FilterType::Bilinear),
+            Self::Bicubic => ResizeAlg::Convolution(FilterType::CatmullRom),
+            Self::Lanczos => ResizeAlg::Convolution(FilterType::Lanczos3),
+        };
+        ResizeOptions::new().resize_alg(algorithm)
+    }
+}
+
+/// A rectangle to cut a recording down to, and the resolution to scale it to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    /// Part of the frames that is kept, in pixels of the recording
+    pub region: CaptureRegion,
+    /// Resolution of the result, `None` keeps that of `region`
+    pub resolution: Option<(u32, u32)>,
+    pub filter: ScaleFilter,
+}
+
+impl Crop {
+    pub fn new(region: CaptureRegion) -> Self {
+        Self {
+            region,
+            resolution: None,
+            filter: ScaleFilter::default(),
+        }
+    }
+
+    /// Resolution of the result for a recording of `dimensions`, failing unless the
+    /// region fits into it. Chroma subsampling needs even dimensions, so odd ones are
+    /// rounded down.
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> Result<(u32, u32)> {
+        self.region.validate(dimensions)?;
+        let (width, height) = self.resolution.unwrap_or(self.region.dimensions());
+        match (width & !1, height & !1) {
+            (0, _) | (_, 0) => Err(XlabError::Config(format!(
+                "{width}x{height} is too small to encode"
+            ))),
+            dimensions => Ok(dimensions),
+        }
+    }
+}
+
+/// Writes the recording at `video_path` cut down to the region of `crop` and scaled
+/// to its resolution to `output_path`, encoded with the codec and quality the user
+/// chose. The sound of its main audio track is kept. Returns the duration and
+/// resolution of the result; `on_progress` is called with the milliseconds of the
+/// recording done and its duration.
+pub(crate) fn crop(
+    video_path: &Path,
+    crop: &Crop,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<(Duration, (u32, u32))> {
+    let input_dimensions = InputFile::open(video_path)?
+        .video_dimensions()
+        .ok_or_else(|| {
+            XlabError::Encode(format!("{} has no video stream", video_path.display()))
+        })?;
+    let dimensions = crop.output_dimensions(input_dimensions)?;
+    // Odd sizes lose their last row or column rather than being stretched by a pixel
+    let region = match crop.resolution {
+        Some(_) => crop.region,
+        None => CaptureRegion::new(crop.region.x, crop.region.y, dimensions.0, dimensions.1),
+    };
+    let resize_options = crop.filter.options();
+    let duration = transform_frames(
+        video_path,
+        output_path,
+        dimensions,
+        on_progress,
+        |_, image| {
+            let mut image = region.crop(&image);
+            if image.dimensions() != dimensions {
+                resize_image_with(&mut image, dimensions, &resize_options)?;
+            }
+            Ok(image)
+        },
+    )?;
+    Ok((duration, dimensions))
+}
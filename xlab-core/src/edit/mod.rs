@@ -0,0 +1,361 @@
+//! Edits of finished recordings, written to new files. Video is copied as it is where
+//! possible, and encoded again only around cuts that don't fall on keyframes and where
+//! recordings that are joined don't fit together.
+
+use std::path::Path;
+use std::ptr;
+use std::time::Duration;
+
+use ffmpeg_sys_next::*;
+
+use crate::{
+    audio::{AudioCodec, AudioTrack},
+    video::{available_codecs, path_to_cstring, Container, VideoCodec},
+    Result, XlabError,
+};
+
+mod auto_zoom;
+mod bitstream;
+mod concat;
+mod crop;
+mod mux_audio;
+mod reencode;
+mod speed;
+mod transform;
+mod trim;
+
+pub(crate) use auto_zoom::auto_zoom;
+pub(crate) use concat::concat;
+pub use concat::Transition;
+pub(crate) use crop::crop;
+pub use crop::{Crop, ScaleFilter};
+pub(crate) use mux_audio::mux_audio;
+pub use mux_audio::AudioOverlay;
+pub(crate) use speed::change_speed;
+pub use speed::{SpeedChange, MAX_SPEED, MIN_SPEED};
+pub use trim::TimeRange;
+pub(crate) use trim::{cut, trim};
+
+/// Time base of the timestamps of ffmpeg that aren't tied to a stream
+const MICROSECONDS: AVRational = AVRational {
+    num: 1,
+    den: AV_TIME_BASE as i32,
+};
+
+/// Frame rate of edits that are encoded again when the recordings don't tell theirs
+const DEFAULT_FPS: u32 = 30;
+
+/// Returns the container of the file at `path`, from its extension
+fn output_container(path: &Path) -> Result<Container> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(Container::from_extension)
+        .ok_or_else(|| {
+            XlabError::Config(format!(
+                "{} doesn't have the extension of a video container",
+                path.display()
+            ))
+        })
+}
+
+/// Video codec for edits that are encoded again: the first of `recorded` that
+/// `container` supports, or else any codec available for it
+fn encoding_codec(
+    recorded: impl IntoIterator<Item = VideoCodec>,
+    container: Container,
+) -> Result<VideoCodec> {
+    recorded
+        .into_iter()
+        .chain(available_codecs())
+        .find(|codec| container.supports(*codec) && codec.is_available())
+        .ok_or_else(|| {
+            XlabError::Encode(format!(
+                "no video encoder for {container} files is available"
+            ))
+        })
+}
+
+/// Audio track for edits that are encoded again
+fn encoding_audio(container: Container) -> Result<AudioTrack> {
+    let codec = AudioCodec::ALL
+        .into_iter()
+        .find(|codec| container.supports_audio(*codec) && codec.is_available())
+        .ok_or_else(|| {
+            XlabError::Encode(format!(
+                "no audio encoder for {container} files is available"
+            ))
+        })?;
+    Ok(AudioTrack { codec, title: None })
+}
+
+/// A media file whose packets are read without decoding them
+pub(crate) struct InputFile {
+    fmt_ctx: *mut AVFormatContext,
+}
+
+impl InputFile {
+    pub(crate) fn open(input_path: &Path) -> Result<Self> {
+        let input_path_c = path_to_cstring(input_path)?;
+        let mut input = Self {
+            fmt_ctx: ptr::null_mut(),
+        };
+        unsafe {
+            if avformat_open_input(
+                &mut input.fmt_ctx,
+                input_path_c.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+            ) < 0
+            {
+                return Err(XlabError::Encode(format!(
+                    "Failed to open {}",
+                    input_path.display()
+                )));
+            }
+            if avformat_find_stream_info(input.fmt_ctx, ptr::null_mut()) < 0 {
+                return Err(XlabError::Encode("Failed to read stream info".into()));
+            }
+        }
+        Ok(input)
+    }
+
+    pub(crate) fn streams(&self) -> &[*mut AVStream] {
+        unsafe {
+            std::slice::from_raw_parts((*self.fmt_ctx).streams, (*self.fmt_ctx).nb_streams as usize)
+        }
+    }
+
+    pub(crate) fn duration(&self) -> Duration {
+        let duration = unsafe { (*self.fmt_ctx).duration };
+        match duration {
+            AV_NOPTS_VALUE => Duration::ZERO,
+            duration => Duration::from_micros(duration.max(0) as u64),
+        }
+    }
+
+    /// Time of the first frame or sample of the file
+    pub(crate) fn start_time(&self) -> Duration {
+        let start_time = unsafe { (*self.fmt_ctx).start_time };
+        match start_time {
+            AV_NOPTS_VALUE => Duration::ZERO,
+            start_time => Duration::from_micros(start_time.max(0) as u64),
+        }
+    }
+
+    /// Index of the first stream of `media_type`
+    pub(crate) fn find_stream(&self, media_type: AVMediaType) -> Option<usize> {
+        self.streams()
+            .iter()
+            .position(|&stream| unsafe { (*(*stream).codecpar).codec_type == media_type })
+    }
+
+    pub(crate) fn video_stream(&self) -> Option<*mut AVStream> {
+        let index = self.find_stream(AVMediaType::AVMEDIA_TYPE_VIDEO)?;
+        Some(self.streams()[index])
+    }
+
+    pub(crate) fn video_dimensions(&self) -> Option<(u32, u32)> {
+        let codecpar = unsafe { (*self.video_stream()?).codecpar };
+        unsafe { Some(((*codecpar).width as u32, (*codecpar).height as u32)) }
+    }
+
+    /// Average frame rate of the video, if the file tells it
+    pub(crate) fn frame_rate(&self) -> Option<f64> {
+        let frame_rate = unsafe { (*self.video_stream()?).avg_frame_rate };
+        (frame_rate.num > 0 && frame_rate.den > 0)
+            .then(|| frame_rate.num as f64 / frame_rate.den as f64)
+    }
+
+    /// Codec of the video, if it is one recordings can be made with
+    pub(crate) fn video_codec(&self) -> Option<VideoCodec> {
+        let codec_id = unsafe { (*(*self.video_stream()?).codecpar).codec_id };
+        VideoCodec::ALL
+            .into_iter()
+            .find(|codec| codec.codec_id() == codec_id)
+    }
+
+    /// Reads the next packet of any stream, returning `false` at the end of the file.
+    /// The packet must be unreferenced by the caller.
+    pub(crate) unsafe fn read_packet(&mut self, packet: *mut AVPacket) -> bool {
+        av_read_frame(self.fmt_ctx, packet) >= 0
+    }
+}
+
+impl Drop for InputFile {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.fmt_ctx.is_null() {
+                avformat_close_input(&mut self.fmt_ctx);
+            }
+        }
+    }
+}
+
+/// A media file that packets are written to as they are
+pub(crate) struct OutputFile {
+    fmt_ctx: *mut AVFormatContext,
+}
+
+impl OutputFile {
+    pub(crate) fn create(output_path: &Path, container: Container) -> Result<Self> {
+        let output_path_c = path_to_cstring(output_path)?;
+        let mut output = Self {
+            fmt_ctx: ptr::null_mut(),
+        };
+        unsafe {
+            if avformat_alloc_output_context2(
+                &mut output.fmt_ctx,
+                ptr::null(),
+                container.format_name().as_ptr(),
+                output_path_c.as_ptr(),
+            ) < 0
+            {
+                return Err(XlabError::Encode("Failed to create output context".into()));
+            }
+            let mut avio_ctx = ptr::null_mut();
+            if avio_open(&mut avio_ctx, output_path_c.as_ptr(), AVIO_FLAG_WRITE) < 0 {
+                return Err(XlabError::Encode("Failed to open output file".into()));
+            }
+            (*output.fmt_ctx).pb = avio_ctx;
+        }
+        Ok(output)
+    }
+
+    pub(crate) fn fmt_ctx(&self) -> *mut AVFormatContext {
+        self.fmt_ctx
+    }
+
+    /// Adds a stream with the codec of `input`, whose packets can be copied into it
+    pub(crate) unsafe fn copy_stream(&mut self, input: *const AVStream) -> Result<*mut AVStream> {
+        let stream = avformat_new_stream(self.fmt_ctx, ptr::null());
+        if stream.is_null() {
+            return Err(XlabError::Encode("Failed to create stream".into()));
+        }
+        if avcodec_parameters_copy((*stream).codecpar, (*input).codecpar) < 0 {
+            return Err(XlabError::Encode("Failed to copy codec parameters".into()));
+        }
+        // The tag of the input container may mean nothing in the output container
+        (*(*stream).codecpar).codec_tag = 0;
+        (*stream).time_base = (*input).time_base;
+        Ok(stream)
+    }
+
+    pub(crate) unsafe fn write_header(&mut self) -> Result<()> {
+        if avformat_write_header(self.fmt_ctx, ptr::null_mut()) < 0 {
+            return Err(XlabError::Encode(
+                "Failed to write header, the container may not support the codecs".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes a packet with timestamps in `time_base` to `stream`, taking its data
+    pub(crate) unsafe fn write_packet(
+        &mut self,
+        packet: *mut AVPacket,
+        time_base: AVRational,
+        stream: *mut AVStream,
+    ) -> Result<()> {
+        av_packet_rescale_ts(packet, time_base, (*stream).time_base);
+        (*packet).stream_index = (*stream).index;
+        (*packet).pos = -1;
+        if av_interleaved_write_frame(self.fmt_ctx, packet) < 0 {
+            return Err(XlabError::Encode("Failed to write packet".into()));
+        }
+        Ok(())
+    }
+
+    pub(crate) unsafe fn finish(self) -> Result<()> {
+        if av_write_trailer(self.fmt_ctx) < 0 {
+            return Err(XlabError::Encode("Failed to write trailer".into()));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        unsafe {
+            if self.fmt_ctx.is_null() {
+                return;
+            }
+            if !(*self.fmt_ctx).pb.is_null() {
+                avio_closep(&mut (*self.fmt_ctx).pb);
+            }
+            avformat_free_context(self.fmt_ctx);
+        }
+    }
+}
+
+/// Output streams joined from parts of recordings, whose timestamps are moved so that
+/// the parts follow each other
+pub(crate) struct Timeline {
+    streams: Vec<*mut AVStream>,
+    /// Added to the timestamps of each stream, in its time base
+    shifts: Vec<i64>,
+    last_dts: Vec<Option<i64>>,
+}
+
+impl Timeline {
+    pub(crate) fn new(streams: Vec<*mut AVStream>) -> Self {
+        Self {
+            shifts: vec![0; streams.len()],
+            last_dts: vec![None; streams.len()],
+            streams,
+        }
+    }
+
+    /// Moves the part of an input that starts at `start` to `offset` in the output.
+    /// The muxer settles the time bases of the streams with its header, so parts start
+    /// after it was written.
+    pub(crate) fn start_part(&mut self, start: Duration, offset: Duration) {
+        for (shift, &stream) in self.shifts.iter_mut().zip(&self.streams) {
+            let time_base = unsafe { (*stream).time_base };
+            *shift = to_time_base(offset, time_base) - to_time_base(start, time_base);
+        }
+    }
+
+    /// Writes a packet with timestamps in `time_base` to stream `index` of `output`
+    pub(crate) unsafe fn write(
+        &mut self,
+        output: &mut OutputFile,
+        packet: *mut AVPacket,
+        time_base: AVRational,
+        index: usize,
+    ) -> Result<()> {
+        let stream = self.streams[index];
+        av_packet_rescale_ts(packet, time_base, (*stream).time_base);
+        for timestamp in [&mut (*packet).pts, &mut (*packet).dts] {
+            if *timestamp != AV_NOPTS_VALUE {
+                *timestamp += self.shifts[index];
+            }
+        }
+        // Decoding times must keep increasing across the joins of the parts
+        if (*packet).dts != AV_NOPTS_VALUE {
+            if let Some(last) = self.last_dts[index] {
+                (*packet).dts = (*packet).dts.max(last + 1);
+            }
+            if (*packet).pts != AV_NOPTS_VALUE {
+                (*packet).pts = (*packet).pts.max((*packet).dts);
+            }
+            self.last_dts[index] = Some((*packet).dts);
+        }
+        output.write_packet(packet, (*stream).time_base, stream)
+    }
+}
+
+fn to_time_base(time: Duration, time_base: AVRational) -> i64 {
+    unsafe { av_rescale_q(time.as_micros() as i64, MICROSECONDS, time_base) }
+}
+
+/// Timestamp of `packet` in microseconds, from its decoding or presentation time
+unsafe fn packet_time(packet: *const AVPacket, time_base: AVRational) -> Duration {
+    let time = match (*packet).dts {
+        AV_NOPTS_VALUE => (*packet).pts,
+        dts => dts,
+    };
+    match time {
+        AV_NOPTS_VALUE => Duration::ZERO,
+        time => Duration::from_micros(av_rescale_q(time, time_base, MICROSECONDS).max(0) as u64),
+    }
+}
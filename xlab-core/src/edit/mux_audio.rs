@@ -0,0 +1,283 @@
+use std::ffi::CString;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ffmpeg_sys_next::*;
+
+use super::{output_container, packet_time, InputFile, OutputFile, MICROSECONDS};
+use crate::{
+    audio::{
+        sample_position, AudioCodec, AudioDecoder, AudioEncoder, AudioTrack, CHANNELS, MAX_GAIN,
+    },
+    Result, XlabError,
+};
+
+/// Title of the track the audio is added as
+const TITLE: &str = "Voice-over";
+
+/// Audio from a file, such as a voice-over, to add to a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioOverlay {
+    /// WAV, MP3, Opus, AAC or any other audio file ffmpeg can decode
+    pub path: PathBuf,
+    /// Position in the recording the audio starts at
+    pub offset: Duration,
+    /// Volume of the audio, 1.0 keeps it as it is
+    pub gain: f32,
+}
+
+impl AudioOverlay {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            offset: Duration::ZERO,
+            gain: 1.0,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=MAX_GAIN).contains(&self.gain) {
+            return Err(XlabError::Config(format!(
+                "audio gain must be between 0 and {MAX_GAIN}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Writes the recording at `video_path` with the audio of `overlay` as an additional
+/// track to `output_path`. The streams of the recording are copied; the audio is
+/// copied as well if the container supports its codec and its volume stays the same,
+/// and encoded again otherwise. Audio past the end of the recording is cut off.
+/// `on_progress` is called with the milliseconds of the recording written and its
+/// duration.
+pub(crate) fn mux_audio(
+    video_path: &Path,
+    overlay: &AudioOverlay,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    overlay.validate()?;
+    let container = output_container(output_path)?;
+    let mut input = InputFile::open(video_path)?;
+    let decoder = AudioDecoder::open(&overlay.path)?;
+    let mut output = OutputFile::create(output_path, container)?;
+    let end = input.duration();
+    let total_millis = end.as_millis() as u64;
+    unsafe {
+        // 1. Copy the streams of the recording
+        let mut streams = Vec::with_capacity(input.streams().len());
+        for &stream in input.streams() {
+            streams.push(output.copy_stream(stream)?);
+        }
+
+        // 2. Add the audio as a new track
+        let codec_id = (*(*decoder.stream()).codecpar).codec_id;
+        let copyable = AudioCodec::ALL
+            .into_iter()
+            .any(|codec| codec.codec_id() == codec_id && container.supports_audio(codec));
+        let mut track = if copyable && overlay.gain == 1.0 {
+            let stream = output.copy_stream(decoder.stream())?;
+            let title = CString::new(TITLE).unwrap();
+            av_dict_set(
+                &mut (*stream).metadata,
+                c"title".as_ptr(),
+                title.as_ptr(),
+                0,
+            );
+            OverlayTrack::copy(decoder, stream, overlay.offset)?
+        } else {
+            let codec = AudioCodec::ALL
+                .into_iter()
+                .find(|codec| container.supports_audio(*codec) && codec.is_available())
+                .ok_or_else(|| {
+                    XlabError::Encode(format!(
+                        "no audio encoder for {container} files is available"
+                    ))
+                })?;
+            let audio_track = AudioTrack {
+                codec,
+                title: Some(TITLE.into()),
+            };
+            let encoder = AudioEncoder::new(output.fmt_ctx(), &audio_track)?;
+            OverlayTrack::Encode {
+                samples: decoder.peekable(),
+                encoder,
+                offset: overlay.offset,
+                gain: overlay.gain,
+                started: false,
+            }
+        };
+        output.write_header()?;
+
+        // 3. Copy the packets of the recording, with the audio interleaved by time
+        let packet = av_packet_alloc();
+        if packet.is_null() {
+            return Err(XlabError::Encode("Failed to allocate packet".into()));
+        }
+        let mut result = Ok(());
+        while input.read_packet(packet) {
+            let input_stream = input.streams()[(*packet).stream_index as usize];
+            let time = packet_time(packet, (*input_stream).time_base);
+            on_progress(time.min(end).as_millis() as u64, total_millis);
+            result = track
+                .write_until(&mut output, time.min(end), end)
+                .and_then(|_| {
+                    output.write_packet(
+                        packet,
+                        (*input_stream).time_base,
+                        streams[(*packet).stream_index as usize],
+                    )
+                });
+            av_packet_unref(packet);
+            if result.is_err() {
+                break;
+            }
+        }
+        let mut packet = packet;
+        av_packet_free(&mut packet);
+        result?;
+        track.write_until(&mut output, end, end)?;
+        track.finish(&mut output)?;
+        output.finish()
+    }
+}
+
+/// The track the audio of an [`AudioOverlay`] is written to
+enum OverlayTrack {
+    /// Packets of the audio file are copied, shifted by the offset
+    Copy {
+        decoder: AudioDecoder,
+        stream: *mut AVStream,
+        packet: *mut AVPacket,
+        /// Whether `packet` holds a packet that wasn't written yet
+        pending: bool,
+        /// Added to the timestamps of the packets, in the time base of the audio file
+        shift: i64,
+    },
+    /// The audio file is decoded and encoded again
+    Encode {
+        samples: Peekable<AudioDecoder>,
+        encoder: AudioEncoder,
+        offset: Duration,
+        gain: f32,
+        started: bool,
+    },
+}
+
+impl OverlayTrack {
+    unsafe fn copy(decoder: AudioDecoder, stream: *mut AVStream, offset: Duration) -> Result<Self> {
+        let packet = av_packet_alloc();
+        if packet.is_null() {
+            return Err(XlabError::Encode("Failed to allocate packet".into()));
+        }
+        let input_stream = decoder.stream();
+        let start = match (*input_stream).start_time {
+            AV_NOPTS_VALUE => 0,
+            start => start,
+        };
+        let shift = av_rescale_q(
+            offset.as_micros() as i64,
+            MICROSECONDS,
+            (*input_stream).time_base,
+        ) - start;
+        Ok(Self::Copy {
+            decoder,
+            stream,
+            packet,
+            pending: false,
+            shift,
+        })
+    }
+
+    /// Writes the audio that plays before `until`, dropping audio that plays after `end`
+    unsafe fn write_until(
+        &mut self,
+        output: &mut OutputFile,
+        until: Duration,
+        end: Duration,
+    ) -> Result<()> {
+        match self {
+            Self::Copy {
+                decoder,
+                stream,
+                packet,
+                pending,
+                shift,
+            } => {
+                let time_base = (*decoder.stream()).time_base;
+                loop {
+                    if !*pending {
+                        if !decoder.read_packet(*packet) {
+                            return Ok(());
+                        }
+                        for timestamp in [&mut (**packet).pts, &mut (**packet).dts] {
+                            if *timestamp != AV_NOPTS_VALUE {
+                                *timestamp += *shift;
+                            }
+                        }
+                        *pending = true;
+                    }
+                    let time = packet_time(*packet, time_base);
+                    if time >= end {
+                        av_packet_unref(*packet);
+                        *pending = false;
+                        continue;
+                    }
+                    if time > until {
+                        return Ok(());
+                    }
+                    output.write_packet(*packet, time_base, *stream)?;
+                    av_packet_unref(*packet);
+                    *pending = false;
+                }
+            }
+            Self::Encode {
+                samples,
+                encoder,
+                offset,
+                gain,
+                started,
+            } => {
+                if !*started {
+                    // Silence up to the offset places the audio precisely
+                    let silence = vec![0.0; sample_position(*offset) as usize * CHANNELS];
+                    encoder.append(output.fmt_ctx(), Duration::ZERO, &silence)?;
+                    *started = true;
+                }
+                while let Some(chunk) = samples.next_if(
+                    |chunk| !matches!(chunk, Ok((timestamp, _)) if *offset + *timestamp > until),
+                ) {
+                    let (timestamp, mut chunk) = chunk?;
+                    let timestamp = *offset + timestamp;
+                    if timestamp >= end {
+                        continue;
+                    }
+                    let remaining = (sample_position(end) - sample_position(timestamp)) as usize;
+                    chunk.truncate(remaining * CHANNELS);
+                    if *gain != 1.0 {
+                        chunk.iter_mut().for_each(|sample| *sample *= *gain);
+                    }
+                    encoder.append(output.fmt_ctx(), timestamp, &chunk)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    unsafe fn finish(&mut self, output: &mut OutputFile) -> Result<()> {
+        match self {
+            Self::Copy { .. } => Ok(()),
+            Self::Encode { encoder, .. } => encoder.finish(output.fmt_ctx()),
+        }
+    }
+}
+
+impl Drop for OverlayTrack {
+    fn drop(&mut self) {
+        if let Self::Copy { packet, .. } = self {
+            unsafe { av_packet_free(packet) };
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::Path;
+use std::ptr;
+
+use ffmpeg_sys_next::*;
+
+use super::InputFile;
+use crate::{
+    video::{apply_quality, QualitySettings, VideoCodec},
+    Result, XlabError,
+};
+
+/// Decodes the frames of the video stream of `input_path` shown from `from` up to
+/// `to`, in the time base of the stream, and encodes them again with the same codec.
+/// The packets are handed to `write` with timestamps in the time base of the stream;
+/// the first one is a keyframe that carries the codec headers of the encoder.
+pub(crate) fn reencode_range(
+    input_path: &Path,
+    stream_index: usize,
+    from: i64,
+    to: i64,
+    write: &mut dyn FnMut(*mut AVPacket) -> Result<()>,
+) -> Result<()> {
+    let mut input = InputFile::open(input_path)?;
+    let stream = input.streams()[stream_index];
+    let mut coder = unsafe { GopEncoder::new(stream)? };
+    unsafe {
+        if av_seek_frame(
+            input.fmt_ctx,
+            stream_index as i32,
+            from,
+            AVSEEK_FLAG_BACKWARD,
+        ) < 0
+        {
+            return Err(XlabError::Encode("Failed to seek".into()));
+        }
+        let mut done = false;
+        while !done && input.read_packet(coder.packet) {
+            if (*coder.packet).stream_index as usize != stream_index {
+                av_packet_unref(coder.packet);
+                continue;
+            }
+            let sent = avcodec_send_packet(coder.decoder, coder.packet);
+            av_packet_unref(coder.packet);
+            if sent < 0 {
+                return Err(XlabError::Encode("Failed to send packet to decoder".into()));
+            }
+            done = coder.encode_decoded(from, to, write)?;
+        }
+        if !done {
+            // End of file, the decoder still holds the last frames
+            avcodec_send_packet(coder.decoder, ptr::null());
+            coder.encode_decoded(from, to, write)?;
+        }
+        avcodec_send_frame(coder.encoder, ptr::null());
+        coder.write_encoded(write)
+    }
+}
+
+/// Decoder and encoder of a video stream, so that parts of it can be encoded again
+struct GopEncoder {
+    decoder: *mut AVCodecContext,
+    encoder: *mut AVCodecContext,
+    frame: *mut AVFrame,
+    packet: *mut AVPacket,
+}
+
+impl GopEncoder {
+    unsafe fn new(stream: *const AVStream) -> Result<Self> {
+        let codecpar = (*stream).codecpar;
+        let codec = VideoCodec::ALL
+            .into_iter()
+            .find(|codec| codec.codec_id() == (*codecpar).codec_id)
+            .ok_or_else(|| {
+                XlabError::Encode("the video can only be cut at its keyframes".into())
+            })?;
+        // Everything allocated so far is released by `drop` when a step fails
+        let mut coder = Self {
+            decoder: ptr::null_mut(),
+            encoder: ptr::null_mut(),
+            frame: ptr::null_mut(),
+            packet: ptr::null_mut(),
+        };
+
+        // 1. Open the decoder
+        let decoder = avcodec_find_decoder((*codecpar).codec_id);
+        if decoder.is_null() {
+            return Err(XlabError::Encode(format!(
+                "no {codec} decoder is available"
+            )));
+        }
+        coder.decoder = avcodec_alloc_context3(decoder);
+        if coder.decoder.is_null() {
+            return Err(XlabError::Encode("Failed to allocate codec context".into()));
+        }
+        if avcodec_parameters_to_context(coder.decoder, codecpar) < 0 {
+            return Err(XlabError::Encode("Failed to copy codec parameters".into()));
+        }
+        (*coder.decoder).pkt_timebase = (*stream).time_base;
+        if avcodec_open2(coder.decoder, decoder, ptr::null_mut()) < 0 {
+            return Err(XlabError::Encode("Failed to open codec".into()));
+        }
+
+        // 2. Open an encoder with the format of the stream. Without a global header
+        // the encoder puts its headers in front of its first keyframe.
+        let encoder = codec.find_encoder()?;
+        coder.encoder = avcodec_alloc_context3(encoder);
+        if coder.encoder.is_null() {
+            return Err(XlabError::Encode("Failed to allocate codec context".into()));
+        }
+        let encoder_ctx = coder.encoder;
+        (*encoder_ctx).width = (*codecpar).width;
+        (*encoder_ctx).height = (*codecpar).height;
+        (*encoder_ctx).sample_aspect_ratio = (*codecpar).sample_aspect_ratio;
+        (*encoder_ctx).pix_fmt = (*coder.decoder).pix_fmt;
+        (*encoder_ctx).time_base = (*stream).time_base;
+        if (*stream).avg_frame_rate.num > 0 {
+            (*encoder_ctx).framerate = (*stream).avg_frame_rate;
+        }
+        (*encoder_ctx).color_primaries = (*codecpar).color_primaries;
+        (*encoder_ctx).color_trc = (*codecpar).color_trc;
+        (*encoder_ctx).colorspace = (*codecpar).color_space;
+        (*encoder_ctx).color_range = (*codecpar).color_range;
+        // Frames leave the encoder in order, so that their decoding times can't run
+        // into those of the copied packets that follow
+        (*encoder_ctx).max_b_frames = 0;
+        apply_quality(encoder_ctx, codec, &QualitySettings::default())?;
+        if avcodec_open2(encoder_ctx, encoder, ptr::null_mut()) < 0 {
+            return Err(XlabError::Encode("Failed to open codec".into()));
+        }
+
+        // 3. Allocate frame and packet
+        coder.frame = av_frame_alloc();
+        coder.packet = av_packet_alloc();
+        if coder.frame.is_null() || coder.packet.is_null() {
+            return Err(XlabError::Encode("Failed to allocate frame".into()));
+        }
+        Ok(coder)
+    }
+
+    /// Encodes the decoded frames shown from `from` up to `to`, returning `true` once a
+    /// frame past `to` was decoded
+    unsafe fn encode_decoded(
+        &mut self,
+        from: i64,
+        to: i64,
+        write: &mut dyn FnMut(*mut AVPacket) -> Result<()>,
+    ) -> Result<bool> {
+        while avcodec_receive_frame(self.decoder, self.frame) >= 0 {
+            let pts = (*self.frame).best_effort_timestamp;
+            if pts == AV_NOPTS_VALUE || pts < from {
+                av_frame_unref(self.frame);
+                continue;
+            }
+            if pts >= to {
+                av_frame_unref(self.frame);
+                return Ok(true);
+            }
+            (*self.frame).pts = pts;
+            let sent = avcodec_send_frame(self.encoder, self.frame);
+            av_frame_unref(self.frame);
+            if sent < 0 {
+                return Err(XlabError::Encode("Failed to send frame to encoder".into()));
+            }
+            self.write_encoded(write)?;
+        }
+        Ok(false)
+    }
+
+    unsafe fn write_encoded(
+        &mut self,
+        write: &mut dyn FnMut(*mut AVPacket) -> Result<()>,
+    ) -> Result<()> {
+        while avcodec_receive_packet(self.encoder, self.packet) >= 0 {
+            let written = write(self.packet);
+            av_packet_unref(self.packet);
+            written?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GopEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            av_packet_free(&mut self.packet);
+            av_frame_free(&mut self.frame);
+            avcodec_free_context(&mut self.encoder);
+            avcodec_free_context(&mut self.decoder);
+        }
+    }
+}
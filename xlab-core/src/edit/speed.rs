@@ -0,0 +1,230 @@
+use std::iter::Peekable;
+use std::path::Path;
+use std::time::Duration;
+
+use ffmpeg_sys_next::AVMediaType;
+use xcap::image::RgbaImage;
+
+use super::{encoding_audio, encoding_codec, output_container, InputFile, TimeRange, DEFAULT_FPS};
+use crate::{
+    audio::{position_timestamp, sample_position, AudioDecoder, CHANNELS},
+    video::{EncoderConfig, VideoDecoder, VideoEncoder},
+    Result, XlabError,
+};
+
+/// Slowest speed a recording can be played at
+pub const MIN_SPEED: f64 = 0.1;
+/// Fastest speed a recording can be played at
+pub const MAX_SPEED: f64 = 1000.0;
+
+/// A change of the speed a recording, or a part of it, plays at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedChange {
+    /// Factor the speed is multiplied by, above 1 speeds up and below 1 slows down
+    pub speed: f64,
+    /// Part of the recording that plays at `speed`, `None` changes all of it
+    pub range: Option<TimeRange>,
+    /// Whether the frames that are skipped when speeding up are blended into the
+    /// ones that are shown, which smears motion, rather than dropped
+    pub blend: bool,
+}
+
+impl SpeedChange {
+    pub fn new(speed: f64) -> Self {
+        Self {
+            speed,
+            range: None,
+            blend: false,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(MIN_SPEED..=MAX_SPEED).contains(&self.speed) {
+            return Err(XlabError::Config(format!(
+                "speed must be between {MIN_SPEED} and {MAX_SPEED}"
+            )));
+        }
+        match self.range {
+            Some(range) => range.validate(),
+            None => Ok(()),
+        }
+    }
+
+    /// Time of the result at which `time` of the recording plays, `range` being the
+    /// part that changes speed
+    fn map(&self, time: Duration, range: TimeRange) -> Duration {
+        if time <= range.start {
+            time
+        } else if time < range.end {
+            range.start + (time - range.start).div_f64(self.speed)
+        } else {
+            range.start + range.duration().div_f64(self.speed) + (time - range.end)
+        }
+    }
+}
+
+/// Writes the recording at `video_path` to `output_path` with the speed of its video
+/// changed, returning the duration and resolution of the result. Sound outside of the
+/// changed part is kept, sound within it is left out, since it can't play at another
+/// speed without changing its pitch. `on_progress` is called with the milliseconds
+/// of the recording done and its duration.
+pub(crate) fn change_speed(
+    video_path: &Path,
+    change: &SpeedChange,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<(Duration, (u32, u32))> {
+    change.validate()?;
+    let container = output_container(output_path)?;
+    let input = InputFile::open(video_path)?;
+    let duration = input.duration();
+    let range = match change.range {
+        Some(range) => TimeRange::new(range.start, range.end.min(duration)),
+        None => TimeRange::new(Duration::ZERO, duration),
+    };
+    if range.is_empty() {
+        return Err(XlabError::Config(format!(
+            "{:.3}s is past the end of the recording",
+            range.start.as_secs_f64()
+        )));
+    }
+    let fps = match input.frame_rate().map_or(0, |fps| fps.round() as u32) {
+        0 => DEFAULT_FPS,
+        fps => fps,
+    };
+    let codec = encoding_codec(input.video_codec(), container)?;
+    let audio = match input.find_stream(AVMediaType::AVMEDIA_TYPE_AUDIO) {
+        Some(_) => vec![encoding_audio(container)?],
+        None => Vec::new(),
+    };
+    drop(input);
+
+    let frames = VideoDecoder::open(video_path)?;
+    let dimensions = frames.dimensions();
+    let mut sound = match audio.is_empty() {
+        true => None,
+        false => Some(AudioDecoder::open(video_path)?.peekable()),
+    };
+    let mut encoder = VideoEncoder::new(
+        output_path.to_path_buf(),
+        fps,
+        dimensions,
+        EncoderConfig {
+            codec,
+            container,
+            audio,
+            ..Default::default()
+        },
+    )?;
+    let total_millis = duration.as_millis() as u64;
+    let mut pending: Option<MergedFrame> = None;
+    for frame in frames {
+        let (timestamp, image) = frame?;
+        let time = change.map(timestamp, range);
+        if let Some(sound) = &mut sound {
+            write_audio(&mut encoder, sound, Some(timestamp), change, range)?;
+        }
+        // Sped up frames that fall into the same frame of the result become one
+        let slot = (change.speed > 1.0 && (range.start..range.end).contains(&timestamp))
+            .then(|| (time.as_micros() as u64 * fps as u64) / 1_000_000);
+        match &mut pending {
+            Some(merged) if slot == Some(merged.slot) => {
+                if change.blend {
+                    merged.add(&image);
+                }
+            }
+            _ => {
+                if let Some(merged) = pending.take() {
+                    merged.write(&mut encoder)?;
+                }
+                match slot {
+                    Some(slot) => pending = Some(MergedFrame::new(slot, time, image)),
+                    None => encoder.append_image(image, time)?,
+                }
+            }
+        }
+        on_progress(
+            (timestamp.as_millis() as u64).min(total_millis),
+            total_millis,
+        );
+    }
+    if let Some(merged) = pending.take() {
+        merged.write(&mut encoder)?;
+    }
+    if let Some(sound) = &mut sound {
+        write_audio(&mut encoder, sound, None, change, range)?;
+    }
+    encoder.finalize()?;
+    Ok((change.map(duration, range), dimensions))
+}
+
+/// Passes the audio of the recording up to `until`, or all of it, to `encoder`,
+/// leaving out what is within `range`
+fn write_audio(
+    encoder: &mut VideoEncoder,
+    sound: &mut Peekable<AudioDecoder>,
+    until: Option<Duration>,
+    change: &SpeedChange,
+    range: TimeRange,
+) -> Result<()> {
+    let (range_start, range_end) = (sample_position(range.start), sample_position(range.end));
+    while let Some(chunk) = sound.next_if(|chunk| match (chunk, until) {
+        (Ok((timestamp, _)), Some(until)) => *timestamp <= until,
+        _ => true,
+    }) {
+        let (timestamp, samples) = chunk?;
+        let start = sample_position(timestamp);
+        let frames = (samples.len() / CHANNELS) as i64;
+        let before = (range_start - start).clamp(0, frames) as usize;
+        if before > 0 {
+            encoder.append_audio(0, &samples[..before * CHANNELS], timestamp)?;
+        }
+        let after = (range_end - start).clamp(0, frames) as usize;
+        if (after as i64) < frames {
+            let time = position_timestamp(start + after as i64);
+            encoder.append_audio(0, &samples[after * CHANNELS..], change.map(time, range))?;
+        }
+    }
+    Ok(())
+}
+
+/// Frames of the recording shown within the same frame of the result
+struct MergedFrame {
+    slot: u64,
+    timestamp: Duration,
+    image: RgbaImage,
+    /// Sums of the channels of the frames blended so far, once there is more than one
+    sums: Vec<u32>,
+    count: u32,
+}
+
+impl MergedFrame {
+    fn new(slot: u64, timestamp: Duration, image: RgbaImage) -> Self {
+        Self {
+            slot,
+            timestamp,
+            image,
+            sums: Vec::new(),
+            count: 1,
+        }
+    }
+
+    fn add(&mut self, image: &RgbaImage) {
+        if self.sums.is_empty() {
+            self.sums = self.image.iter().map(|&channel| channel as u32).collect();
+        }
+        for (sum, &channel) in self.sums.iter_mut().zip(image.iter()) {
+            *sum += channel as u32;
+        }
+        self.count += 1;
+    }
+
+    fn write(mut self, encoder: &mut VideoEncoder) -> Result<()> {
+        if self.count > 1 {
+            for (channel, sum) in self.image.iter_mut().zip(&self.sums) {
+                *channel = ((sum + self.count / 2) / self.count) as u8;
+            }
+        }
+        encoder.append_image(self.image, self.timestamp)
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+use std::time::Duration;
+
+use ffmpeg_sys_next::AVMediaType;
+use xcap::image::RgbaImage;
+
+use super::{output_container, InputFile, DEFAULT_FPS};
+use crate::{
+    audio::{AudioDecoder, AudioTrack},
+    user::get_user_options,
+    video::{EncoderConfig, VideoDecoder, VideoEncoder},
+    Result,
+};
+
+/// Decodes the recording at `video_path`, passes each frame with its timestamp
+/// through `transform` and writes the results, of `dimensions`, to `output_path`,
+/// encoded with the codec and quality the user chose. The sound of the main audio
+/// track is kept. Returns the duration of the recording; `on_progress` is called with
+/// the milliseconds of it done and its duration.
+pub(super) fn transform_frames(
+    video_path: &Path,
+    output_path: &Path,
+    dimensions: (u32, u32),
+    on_progress: &mut dyn FnMut(u64, u64),
+    mut transform: impl FnMut(Duration, RgbaImage) -> Result<RgbaImage>,
+) -> Result<Duration> {
+    let container = output_container(output_path)?;
+    let input = InputFile::open(video_path)?;
+    let duration = input.duration();
+    let fps = match input.frame_rate().map_or(0, |fps| fps.round() as u32) {
+        0 => DEFAULT_FPS,
+        fps => fps,
+    };
+    let has_audio = input.find_stream(AVMediaType::AVMEDIA_TYPE_AUDIO).is_some();
+    drop(input);
+
+    let config = {
+        let options = get_user_options();
+        let options = options.lock().unwrap();
+        EncoderConfig {
+            codec: options.codec,
+            container,
+            quality: options.quality.clone(),
+            audio: match has_audio {
+                true => vec![AudioTrack {
+                    codec: options.audio_codec,
+                    title: None,
+                }],
+                false => Vec::new(),
+            },
+            ..Default::default()
+        }
+    };
+    let frames = VideoDecoder::open(video_path)?;
+    let mut encoder = VideoEncoder::new(output_path.to_path_buf(), fps, dimensions, config)?;
+    let mut sound = match has_audio {
+        true => Some(AudioDecoder::open(video_path)?.peekable()),
+        false => None,
+    };
+    let total_millis = duration.as_millis() as u64;
+    for frame in frames {
+        let (timestamp, image) = frame?;
+        // Sound goes along with the video, so that the muxer doesn't hold back frames
+        if let Some(sound) = &mut sound {
+            while let Some(chunk) =
+                sound.next_if(|chunk| !matches!(chunk, Ok((time, _)) if *time > timestamp))
+            {
+                let (time, samples) = chunk?;
+                encoder.append_audio(0, &samples, time)?;
+            }
+        }
+        encoder.append_image(transform(timestamp, image)?, timestamp)?;
+        on_progress(
+            (timestamp.as_millis() as u64).min(total_millis),
+            total_millis,
+        );
+    }
+    for chunk in sound.into_iter().flatten() {
+        let (timestamp, samples) = chunk?;
+        encoder.append_audio(0, &samples, timestamp)?;
+    }
+    encoder.finalize()?;
+    Ok(duration)
+}
@@ -0,0 +1,352 @@
+use std::path::Path;
+use std::time::Duration;
+
+use ffmpeg_sys_next::*;
+
+use super::bitstream::{packet_with_data, StreamFormat};
+use super::reencode::reencode_range;
+use super::{output_container, packet_time, to_time_base, InputFile, OutputFile, Timeline};
+use crate::{Result, XlabError};
+
+/// Muxers store audio at most this long after the video it plays with, so reading
+/// stops once packets are this far past the end of a range
+const MAX_INTERLEAVE: Duration = Duration::from_secs(1);
+
+/// A part of a recording, from `start` up to `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl TimeRange {
+    pub fn new(start: Duration, end: Duration) -> Self {
+        Self { start, end }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.is_empty() {
+            return Err(XlabError::Config(
+                "the start of the range must come before its end".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Writes the part of the recording at `video_path` within `range` to `output_path`,
+/// returning its duration
+pub(crate) fn trim(
+    video_path: &Path,
+    range: TimeRange,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<Duration> {
+    keep_ranges(video_path, output_path, on_progress, |duration| {
+        vec![TimeRange::new(range.start, range.end.min(duration))]
+    })
+}
+
+/// Writes the recording at `video_path` without the part within `range` to
+/// `output_path`, returning the duration of what is left
+pub(crate) fn cut(
+    video_path: &Path,
+    range: TimeRange,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+) -> Result<Duration> {
+    if range.is_empty() {
+        return Err(XlabError::Config(
+            "the start of the cut must come before its end".into(),
+        ));
+    }
+    keep_ranges(video_path, output_path, on_progress, |duration| {
+        [
+            TimeRange::new(Duration::ZERO, range.start),
+            TimeRange::new(range.end, duration),
+        ]
+        .into_iter()
+        .filter(|range| !range.is_empty())
+        .collect()
+    })
+}
+
+/// Writes the parts of a recording returned by `ranges`, given its duration, one
+/// after the other. Video is copied from the first keyframe of each part on; the
+/// frames before it, and those after its last keyframe when the part ends before the
+/// recording does, are encoded again so that the cuts are frame accurate.
+/// `on_progress` is called with the number of packets written and the total.
+fn keep_ranges(
+    video_path: &Path,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64, u64),
+    ranges: impl FnOnce(Duration) -> Vec<TimeRange>,
+) -> Result<Duration> {
+    let container = output_container(output_path)?;
+    let mut input = InputFile::open(video_path)?;
+    let duration = input.duration();
+    let ranges = ranges(duration);
+    validate_ranges(&ranges, duration)?;
+    let video_index = input
+        .find_stream(AVMediaType::AVMEDIA_TYPE_VIDEO)
+        .ok_or_else(|| {
+            XlabError::Encode(format!("{} has no video stream", video_path.display()))
+        })?;
+    let mut output = OutputFile::create(output_path, container)?;
+    unsafe {
+        let mut streams = Vec::with_capacity(input.streams().len());
+        for &stream in input.streams() {
+            streams.push(output.copy_stream(stream)?);
+        }
+        output.write_header()?;
+
+        let mut packet = av_packet_alloc();
+        if packet.is_null() {
+            return Err(XlabError::Encode("Failed to allocate packet".into()));
+        }
+        let (keyframes, total) = index_packets(&mut input, packet, video_index, &ranges);
+        let mut splicer = Splicer {
+            output: &mut output,
+            time_bases: input
+                .streams()
+                .iter()
+                .map(|&stream| (*stream).time_base)
+                .collect(),
+            format: StreamFormat::of((*input.streams()[video_index]).codecpar),
+            timeline: Timeline::new(streams),
+            video_index,
+            headers_pending: false,
+            written: 0,
+            total,
+            on_progress,
+        };
+        let mut result = Ok(());
+        let mut offset = Duration::ZERO;
+        for range in &ranges {
+            splicer.timeline.start_part(range.start, offset);
+            result = splicer.write_range(
+                &mut input,
+                video_path,
+                packet,
+                *range,
+                range.end >= duration,
+                &keyframes,
+            );
+            if result.is_err() {
+                break;
+            }
+            offset += range.duration();
+        }
+        av_packet_free(&mut packet);
+        result?;
+        output.finish()?;
+        Ok(offset)
+    }
+}
+
+/// Checks that `ranges` are in order, don't overlap and lie within the recording
+fn validate_ranges(ranges: &[TimeRange], duration: Duration) -> Result<()> {
+    if ranges.is_empty() {
+        return Err(XlabError::Config(
+            "nothing of the recording would be left".into(),
+        ));
+    }
+    for range in ranges {
+        if range.is_empty() {
+            return Err(XlabError::Config(
+                "the start of a range must come before its end".into(),
+            ));
+        }
+        if range.start >= duration {
+            return Err(XlabError::Config(format!(
+                "{:.3}s is past the end of the recording",
+                range.start.as_secs_f64()
+            )));
+        }
+    }
+    if ranges.windows(2).any(|pair| pair[0].end > pair[1].start) {
+        return Err(XlabError::Config(
+            "ranges must be in order and must not overlap".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Reads all packets, returning the presentation timestamps of the video keyframes
+/// in order and the number of packets within `ranges`
+unsafe fn index_packets(
+    input: &mut InputFile,
+    packet: *mut AVPacket,
+    video_index: usize,
+    ranges: &[TimeRange],
+) -> (Vec<i64>, u64) {
+    let mut keyframes = Vec::new();
+    let mut total = 0;
+    while input.read_packet(packet) {
+        let stream_index = (*packet).stream_index as usize;
+        let time = packet_time(packet, (*input.streams()[stream_index]).time_base);
+        if ranges
+            .iter()
+            .any(|range| (range.start..range.end).contains(&time))
+        {
+            total += 1;
+        }
+        if stream_index == video_index && (*packet).flags & AV_PKT_FLAG_KEY != 0 {
+            keyframes.push(presentation_ts(packet));
+        }
+        av_packet_unref(packet);
+    }
+    keyframes.sort_unstable();
+    (keyframes, total)
+}
+
+unsafe fn presentation_ts(packet: *const AVPacket) -> i64 {
+    match (*packet).pts {
+        AV_NOPTS_VALUE => (*packet).dts,
+        pts => pts,
+    }
+}
+
+/// Writes the packets of the kept ranges one after the other
+struct Splicer<'a> {
+    output: &'a mut OutputFile,
+    timeline: Timeline,
+    /// Time bases of the input streams
+    time_bases: Vec<AVRational>,
+    video_index: usize,
+    format: StreamFormat,
+    /// Whether encoded packets replaced the codec headers of the video in decoders
+    headers_pending: bool,
+    written: u64,
+    total: u64,
+    on_progress: &'a mut dyn FnMut(u64, u64),
+}
+
+impl Splicer<'_> {
+    unsafe fn write_range(
+        &mut self,
+        input: &mut InputFile,
+        video_path: &Path,
+        packet: *mut AVPacket,
+        range: TimeRange,
+        to_end: bool,
+        keyframes: &[i64],
+    ) -> Result<()> {
+        let video_time_base = self.time_bases[self.video_index];
+        let start = to_time_base(range.start, video_time_base);
+        let end = match to_end {
+            true => i64::MAX,
+            false => to_time_base(range.end, video_time_base),
+        };
+
+        // 1. Find the keyframes the video can be copied between
+        let first_keyframe = keyframes
+            .iter()
+            .copied()
+            .find(|&keyframe| keyframe >= start);
+        let copied = first_keyframe.and_then(|first| {
+            let last = match to_end {
+                true => i64::MAX,
+                false => keyframes
+                    .iter()
+                    .copied()
+                    .rfind(|&keyframe| keyframe <= end)?,
+            };
+            (first < last).then_some((first, last))
+        });
+
+        // 2. Encode the frames before the first keyframe again
+        let head_end = copied.map_or(end, |(first, _)| first);
+        if start < head_end {
+            reencode_range(
+                video_path,
+                self.video_index,
+                start,
+                head_end,
+                &mut |packet| self.write_encoded(packet),
+            )?;
+        }
+
+        // 3. Copy the video between the keyframes and the audio of the whole range
+        if av_seek_frame(
+            input.fmt_ctx,
+            self.video_index as i32,
+            start,
+            AVSEEK_FLAG_BACKWARD,
+        ) < 0
+        {
+            return Err(XlabError::Encode("Failed to seek".into()));
+        }
+        while input.read_packet(packet) {
+            let stream_index = (*packet).stream_index as usize;
+            let time = packet_time(packet, self.time_bases[stream_index]);
+            if !to_end && time > range.end + MAX_INTERLEAVE {
+                av_packet_unref(packet);
+                break;
+            }
+            let keep = match stream_index == self.video_index {
+                true => copied
+                    .is_some_and(|(first, last)| (first..last).contains(&presentation_ts(packet))),
+                false => (range.start..range.end).contains(&time) || to_end && time >= range.start,
+            };
+            let result = match keep {
+                true => self.write_copied(packet),
+                false => Ok(()),
+            };
+            av_packet_unref(packet);
+            result?;
+        }
+
+        // 4. Encode the frames after the last keyframe again
+        if let Some((_, last)) = copied.filter(|&(_, last)| last < end) {
+            reencode_range(video_path, self.video_index, last, end, &mut |packet| {
+                self.write_encoded(packet)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Writes a packet read from the recording
+    unsafe fn write_copied(&mut self, packet: *mut AVPacket) -> Result<()> {
+        if (*packet).stream_index as usize != self.video_index
+            || !std::mem::take(&mut self.headers_pending)
+            || self.format.headers().is_empty()
+        {
+            return self.write(packet);
+        }
+        // The first keyframe after encoded packets brings the headers of the recording
+        let data = std::slice::from_raw_parts((*packet).data, (*packet).size as usize);
+        let mut spliced = packet_with_data(packet, &[self.format.headers(), data].concat())?;
+        let result = self.write(spliced);
+        av_packet_free(&mut spliced);
+        result
+    }
+
+    /// Writes a packet of the encoder of the video
+    unsafe fn write_encoded(&mut self, packet: *mut AVPacket) -> Result<()> {
+        let data = std::slice::from_raw_parts((*packet).data, (*packet).size as usize);
+        let mut converted = packet_with_data(packet, &self.format.convert(data))?;
+        (*converted).stream_index = self.video_index as i32;
+        self.headers_pending = true;
+        let result = self.write(converted);
+        av_packet_free(&mut converted);
+        result
+    }
+
+    unsafe fn write(&mut self, packet: *mut AVPacket) -> Result<()> {
+        let index = (*packet).stream_index as usize;
+        self.timeline
+            .write(self.output, packet, self.time_bases[index], index)?;
+        self.written += 1;
+        (self.on_progress)(self.written.min(self.total), self.total);
+        Ok(())
+    }
+}
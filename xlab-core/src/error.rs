@@ -0,0 +1,94 @@
+use std::fmt;
+
+/// Errors surfaced by the public API of xlab-core
+#[derive(Debug)]
+pub enum XlabError {
+    /// Capturing the screen or the cursor failed
+    Capture(String),
+    /// Reading or writing the cache, the recordings log or an output file failed
+    Io(std::io::Error),
+    /// Converting, encoding or decoding frames failed
+    Encode(String),
+    /// An option or argument is invalid, or a required setting is missing
+    Config(String),
+    /// The operation is not allowed in the current recording or saving state
+    State(String),
+}
+
+pub type Result<T> = std::result::Result<T, XlabError>;
+
+impl fmt::Display for XlabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XlabError::Capture(msg) => write!(f, "capture error: {msg}"),
+            XlabError::Io(err) => write!(f, "io error: {err}"),
+            XlabError::Encode(msg) => write!(f, "encode error: {msg}"),
+            XlabError::Config(msg) => write!(f, "config error: {msg}"),
+            XlabError::State(msg) => write!(f, "state error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for XlabError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            XlabError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for XlabError {
+    fn from(err: std::io::Error) -> Self {
+        XlabError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for XlabError {
+    fn from(err: serde_json::Error) -> Self {
+        XlabError::Io(err.into())
+    }
+}
+
+impl From<gif::EncodingError> for XlabError {
+    fn from(err: gif::EncodingError) -> Self {
+        match err {
+            gif::EncodingError::Io(err) => XlabError::Io(err),
+            err => XlabError::Encode(err.to_string()),
+        }
+    }
+}
+
+impl From<png::EncodingError> for XlabError {
+    fn from(err: png::EncodingError) -> Self {
+        match err {
+            png::EncodingError::IoError(err) => XlabError::Io(err),
+            err => XlabError::Encode(err.to_string()),
+        }
+    }
+}
+
+impl From<xcap::XCapError> for XlabError {
+    fn from(err: xcap::XCapError) -> Self {
+        XlabError::Capture(err.to_string())
+    }
+}
+
+impl From<xcap::image::ImageError> for XlabError {
+    fn from(err: xcap::image::ImageError) -> Self {
+        match err {
+            xcap::image::ImageError::IoError(err) => XlabError::Io(err),
+            err => XlabError::Io(std::io::Error::other(err)),
+        }
+    }
+}
+
+// Errors cross the Tauri boundary as their display message
+impl serde::Serialize for XlabError {
+    fn serialize<S>(&self, sz: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        sz.serialize_str(&self.to_string())
+    }
+}
@@ -0,0 +1,235 @@
+use std::{fs::File, io::BufWriter, path::Path, time::Duration};
+
+use png::{BitDepth, ColorType, Compression, Encoder, Writer};
+use xcap::image::{imageops, RgbaImage};
+
+use super::{match_dimensions, prepare_frames, validate_frames};
+use crate::{Result, XlabError};
+
+/// Frame delays are whole milliseconds, recordings don't go above 60 frames per second
+const MAX_FRAME_RATE: u32 = 60;
+
+/// How hard the PNG compressor works. Frames are always stored without loss, so this
+/// only trades encoding time for file size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ApngCompression {
+    Fast,
+    #[default]
+    Balanced,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ApngOptions {
+    pub compression: ApngCompression,
+    /// Number of times the animation plays, 0 loops forever
+    pub loop_count: u16,
+    /// Frames per second of the animation, `None` keeps every recorded frame
+    pub frame_rate: Option<u32>,
+    /// Frames wider than this are scaled down, keeping their aspect ratio
+    pub max_width: Option<u32>,
+}
+
+impl ApngOptions {
+    pub fn validate(&self) -> Result<()> {
+        validate_frames("APNG", self.frame_rate, self.max_width, MAX_FRAME_RATE)
+    }
+}
+
+/// Part of a frame that changed since the previous one, with its position
+struct ChangedArea {
+    position: (u32, u32),
+    image: RgbaImage,
+}
+
+/// Writes the frames returned by `open_frames` to an animated PNG at `output_path` and
+/// returns its dimensions. The frame count is part of the file header, so the frames
+/// are read twice: once to count them and once to write them. `on_frame` is called with
+/// the number of source frames written so far.
+pub(super) fn encode_apng<I, F>(
+    output_path: &Path,
+    options: &ApngOptions,
+    open_frames: F,
+    mut on_frame: impl FnMut(u64),
+) -> Result<(u32, u32)>
+where
+    F: Fn() -> Result<I>,
+    I: Iterator<Item = Result<(Duration, RgbaImage)>>,
+{
+    options.validate()?;
+
+    // Scaling doesn't change which frames are kept, so counting skips it
+    let mut frame_count = 0;
+    for frame in prepare_frames(open_frames()?, options.frame_rate, None) {
+        frame?;
+        frame_count += 1;
+    }
+
+    let mut source_frames = 0;
+    let frames = open_frames()?.inspect(|_| {
+        source_frames += 1;
+        on_frame(source_frames);
+    });
+    let mut writer: Option<Writer<BufWriter<File>>> = None;
+    let mut dimensions = (0, 0);
+    let mut previous: Option<RgbaImage> = None;
+    // A frame is written once the next one arrives, since its delay lasts until then
+    let mut pending: Option<(u64, ChangedArea)> = None;
+    let mut last_delay = match options.frame_rate {
+        Some(frame_rate) => (1000 / frame_rate) as u16,
+        None => 100,
+    };
+    for frame in prepare_frames(frames, options.frame_rate, options.max_width) {
+        let (timestamp, mut image) = frame?;
+        let writer = match writer.as_mut() {
+            Some(writer) => writer,
+            None => {
+                dimensions = image.dimensions();
+                let file = BufWriter::new(File::create(output_path)?);
+                let mut encoder = Encoder::new(file, dimensions.0, dimensions.1);
+                encoder.set_color(ColorType::Rgba);
+                encoder.set_depth(BitDepth::Eight);
+                encoder.set_compression(match options.compression {
+                    ApngCompression::Fast => Compression::Fast,
+                    ApngCompression::Balanced => Compression::Balanced,
+                    ApngCompression::High => Compression::High,
+                });
+                encoder.set_animated(frame_count, options.loop_count as u32)?;
+                writer.insert(encoder.write_header()?)
+            }
+        };
+        match_dimensions(&mut image, dimensions)?;
+
+        let millis = timestamp.as_millis() as u64;
+        if let Some((pending_millis, area)) = pending.take() {
+            last_delay = millis
+                .saturating_sub(pending_millis)
+                .clamp(1, u16::MAX as u64) as u16;
+            write_area(writer, &area, last_delay)?;
+        }
+        let area = changed_area(previous.as_ref(), &image);
+        previous = Some(image);
+        pending = Some((millis, area));
+    }
+
+    let (Some(mut writer), Some((_, area))) = (writer, pending) else {
+        return Err(XlabError::Encode("the recording has no frames".into()));
+    };
+    write_area(&mut writer, &area, last_delay)?;
+    writer.finish()?;
+    Ok(dimensions)
+}
+
+/// Finds the smallest rectangle containing every pixel that differs from `previous`.
+/// Frames are drawn over the previous ones, so only that rectangle needs to be stored.
+fn changed_area(previous: Option<&RgbaImage>, image: &RgbaImage) -> ChangedArea {
+    let Some(previous) = previous else {
+        return ChangedArea {
+            position: (0, 0),
+            image: image.clone(),
+        };
+    };
+    let (width, height) = image.dimensions();
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for (y, (row, previous_row)) in image.rows().zip(previous.rows()).enumerate() {
+        for (x, (pixel, previous_pixel)) in row.zip(previous_row).enumerate() {
+            if pixel != previous_pixel {
+                left = left.min(x as u32);
+                right = right.max(x as u32 + 1);
+                top = top.min(y as u32);
+                bottom = bottom.max(y as u32 + 1);
+            }
+        }
+    }
+    // Unchanged frames still need a frame to keep their delay, a single pixel does
+    if left >= right {
+        (left, top, right, bottom) = (0, 0, 1, 1);
+    }
+    let image = imageops::crop_imm(image, left, top, right - left, bottom - top).to_image();
+    ChangedArea {
+        position: (left, top),
+        image,
+    }
+}
+
+fn write_area(writer: &mut Writer<BufWriter<File>>, area: &ChangedArea, delay: u16) -> Result<()> {
+    // The position is reset first, so that the new dimensions fit in the image
+    writer.reset_frame_position()?;
+    writer.set_frame_dimension(area.image.width(), area.image.height())?;
+    writer.set_frame_position(area.position.0, area.position.1)?;
+    writer.set_frame_delay(delay, 1000)?;
+    writer.write_image_data(area.image.as_raw())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use png::Decoder;
+    use xcap::image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn encode_animated_png() {
+        let dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("animated.png");
+        let first = RgbaImage::from_pixel(32, 16, Rgba([0, 0, 255, 255]));
+        let mut second = first.clone();
+        for (x, y) in [(3, 2), (10, 7)] {
+            second.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        }
+        let mut fourth = second.clone();
+        fourth.put_pixel(31, 15, Rgba([0, 255, 0, 128]));
+        // The third frame repeats the second one
+        let frames = vec![
+            (Duration::ZERO, first),
+            (Duration::from_millis(40), second.clone()),
+            (Duration::from_millis(140), second),
+            (Duration::from_millis(170), fourth),
+        ];
+        let options = ApngOptions {
+            loop_count: 2,
+            ..Default::default()
+        };
+        let mut written = 0;
+        let open_frames = || Ok(frames.clone().into_iter().map(Ok));
+        let dimensions =
+            encode_apng(&path, &options, open_frames, |count| written = count).unwrap();
+        assert_eq!(dimensions, (32, 16));
+        assert_eq!(written, 4);
+
+        let data = std::fs::read(&path).unwrap();
+        let mut reader = Decoder::new(Cursor::new(data)).read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!((animation.num_frames, animation.num_plays), (4, 2));
+        // Drawing every stored area over the frames before it gives back the input
+        let mut canvas = RgbaImage::new(32, 16);
+        let mut buffer = vec![0; reader.output_buffer_size().unwrap()];
+        let mut delays = Vec::new();
+        for (_, frame) in &frames {
+            let output = reader.next_frame(&mut buffer).unwrap();
+            let control = reader.info().frame_control.unwrap();
+            assert_eq!(control.delay_den, 1000);
+            delays.push(control.delay_num);
+            let area = RgbaImage::from_raw(
+                output.width,
+                output.height,
+                buffer[..output.buffer_size()].to_vec(),
+            )
+            .unwrap();
+            imageops::replace(
+                &mut canvas,
+                &area,
+                control.x_offset as i64,
+                control.y_offset as i64,
+            );
+            assert_eq!(&canvas, frame);
+        }
+        assert_eq!(delays, [40, 100, 30, 30]);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,406 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use color_quant::NeuQuant;
+use gif::{Encoder, Frame, Repeat};
+use xcap::image::RgbaImage;
+
+use super::{match_dimensions, prepare_frames, validate_frames};
+use crate::{Result, XlabError};
+
+/// NeuQuant sampling factor, 10 is the usual balance between speed and quality
+const QUANTIZER_SAMPLING: i32 = 10;
+
+/// Pixels each frame contributes to the global palette
+const PALETTE_SAMPLES_PER_FRAME: usize = 4096;
+
+/// Pixels the global palette is learned from at most, 4 MB of samples
+const MAX_PALETTE_SAMPLES: usize = 256 * PALETTE_SAMPLES_PER_FRAME;
+
+/// GIF delays are in centiseconds and most viewers slow down anything shorter than 2
+const MIN_DELAY: u16 = 2;
+
+/// Estimates made to get under the target size before settling for the last one
+const MAX_SIZE_ATTEMPTS: usize = 5;
+
+/// Every this many frames one is encoded to estimate the size of a GIF
+const SIZE_SAMPLE_STEP: u64 = 10;
+
+/// Frames don't get narrower than this to meet a target size, the frame rate drops instead
+const MIN_TARGET_WIDTH: u32 = 240;
+
+/// How the colors of a GIF are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum PaletteMode {
+    /// One palette for the whole recording, generated from all of its frames.
+    /// Colors stay stable between frames and the file is smaller.
+    #[default]
+    Global,
+    /// A palette for every frame, for recordings whose colors change a lot
+    PerFrame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct GifOptions {
+    pub palette: PaletteMode,
+    /// Spreads the error of the palette colors over neighbouring pixels, which smooths
+    /// gradients at the cost of a larger file
+    pub dither: bool,
+    /// Frames per second of the GIF, `None` keeps every recorded frame
+    pub frame_rate: Option<u32>,
+    /// Frames wider than this are scaled down, keeping their aspect ratio
+    pub max_width: Option<u32>,
+    /// Size in bytes the GIF should fit in. The width and then the frame rate are
+    /// lowered until it does, so this is a hint rather than a guarantee.
+    pub target_size: Option<u64>,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self {
+            palette: PaletteMode::default(),
+            dither: true,
+            frame_rate: Some(15),
+            max_width: Some(800),
+            target_size: None,
+        }
+    }
+}
+
+impl GifOptions {
+    pub fn validate(&self) -> Result<()> {
+        let max_frame_rate = 100 / MIN_DELAY as u32;
+        validate_frames("GIF", self.frame_rate, self.max_width, max_frame_rate)?;
+        if self.target_size == Some(0) {
+            return Err(XlabError::Config("GIF target size must be positive".into()));
+        }
+        Ok(())
+    }
+}
+
+/// Frame rate and width of one encoding attempt
+#[derive(Clone, Copy)]
+struct Pass {
+    frame_rate: Option<u32>,
+    max_width: Option<u32>,
+}
+
+/// What an encoding attempt produced
+struct Encoded {
+    dimensions: (u32, u32),
+    /// Frames of the GIF, of which `sampled` were encoded
+    frame_count: u64,
+    sampled: u64,
+    duration: Duration,
+    /// Bytes written
+    size: u64,
+}
+
+impl Encoded {
+    /// Size of the GIF with all of its frames encoded
+    fn estimated_size(&self) -> u64 {
+        self.size * self.frame_count / self.sampled.max(1)
+    }
+}
+
+/// Writes the frames returned by `open_frames` to a GIF at `output_path` and returns its
+/// dimensions. `open_frames` is called for every pass over the frames: once for the
+/// global palette and once for writing, and twice more for each estimate of the size
+/// made to meet the target size. `on_frame` is called with the number of source frames
+/// written so far.
+pub(super) fn encode_gif<I, F>(
+    output_path: &Path,
+    options: &GifOptions,
+    open_frames: F,
+    mut on_frame: impl FnMut(u64),
+) -> Result<(u32, u32)>
+where
+    F: Fn() -> Result<I>,
+    I: Iterator<Item = Result<(Duration, RgbaImage)>>,
+{
+    options.validate()?;
+    let mut pass = Pass {
+        frame_rate: options.frame_rate,
+        max_width: options.max_width,
+    };
+    if let Some(target_size) = options.target_size {
+        pass = fit_target_size(options, pass, target_size, &open_frames)?;
+    }
+    let open_output = || Ok(BufWriter::new(File::create(output_path)?));
+    let encoded = write_gif(open_output, options, pass, 1, &open_frames, &mut on_frame)?;
+    Ok(encoded.dimensions)
+}
+
+/// Lowers the width and then the frame rate of `pass` until a sample of the frames
+/// suggests the GIF fits into `target_size`. Only the sample is encoded, so that
+/// progress is reported for a single pass over all of the frames.
+fn fit_target_size<I, F>(
+    options: &GifOptions,
+    mut pass: Pass,
+    target_size: u64,
+    open_frames: &F,
+) -> Result<Pass>
+where
+    F: Fn() -> Result<I>,
+    I: Iterator<Item = Result<(Duration, RgbaImage)>>,
+{
+    let open_output = || Ok(std::io::sink());
+    for attempt in 1..=MAX_SIZE_ATTEMPTS {
+        let encoded = write_gif(
+            open_output,
+            options,
+            pass,
+            SIZE_SAMPLE_STEP,
+            open_frames,
+            &mut |_| {},
+        )?;
+        let size = encoded.estimated_size();
+        if size <= target_size || attempt == MAX_SIZE_ATTEMPTS {
+            break;
+        }
+
+        // The size follows the area of the frames, so the width shrinks with the square
+        // root of the excess, with some margin since compression doesn't scale linearly
+        let excess = target_size as f64 / size as f64;
+        let width = encoded.dimensions.0;
+        let new_width = (width as f64 * (excess.sqrt() * 0.9).clamp(0.5, 0.9)) as u32;
+        if new_width >= MIN_TARGET_WIDTH {
+            pass.max_width = Some(new_width);
+        } else {
+            pass.max_width = Some(width.min(MIN_TARGET_WIDTH));
+            let frame_rate = match pass.frame_rate {
+                Some(frame_rate) => frame_rate as f64,
+                None => encoded.frame_count as f64 / encoded.duration.as_secs_f64().max(1.0),
+            };
+            pass.frame_rate = Some(((frame_rate * excess.clamp(0.5, 0.9)) as u32).max(1));
+        }
+    }
+    Ok(pass)
+}
+
+/// Encodes every `sample_step`th frame of `pass` into the writer `open_output` returns,
+/// which is only opened once there is a frame
+fn write_gif<W, O, I, F>(
+    mut open_output: O,
+    options: &GifOptions,
+    pass: Pass,
+    sample_step: u64,
+    open_frames: &F,
+    on_frame: &mut impl FnMut(u64),
+) -> Result<Encoded>
+where
+    W: Write,
+    O: FnMut() -> Result<W>,
+    F: Fn() -> Result<I>,
+    I: Iterator<Item = Result<(Duration, RgbaImage)>>,
+{
+    let sample_step = sample_step as usize;
+    // First pass, learning the colors of the whole recording
+    let global_palette = match options.palette {
+        PaletteMode::Global => Some(learn_palette(
+            prepare_frames(open_frames()?, pass.frame_rate, pass.max_width).step_by(sample_step),
+        )?),
+        PaletteMode::PerFrame => None,
+    };
+
+    // Second pass, mapping every frame onto its palette
+    let mut source_frames = 0;
+    let frames = open_frames()?.inspect(|_| {
+        source_frames += 1;
+        on_frame(source_frames);
+    });
+    let mut frame_count = 0;
+    let frames = prepare_frames(frames, pass.frame_rate, pass.max_width)
+        .inspect(|_| frame_count += 1)
+        .step_by(sample_step);
+    let mut encoder: Option<Encoder<CountingWriter<W>>> = None;
+    let mut dimensions = (0, 0);
+    // A frame is written once the next one arrives, since its delay lasts until then
+    let mut pending: Option<(u64, Frame<'static>)> = None;
+    let mut last_delay = match pass.frame_rate {
+        Some(frame_rate) => (100 / frame_rate) as u16,
+        None => 10,
+    };
+    let mut sampled = 0;
+    let mut duration = Duration::ZERO;
+    for frame in frames {
+        let (timestamp, mut image) = frame?;
+        let encoder = match encoder.as_mut() {
+            Some(encoder) => encoder,
+            None => {
+                dimensions = image.dimensions();
+                let (width, height) = gif_dimensions(dimensions)?;
+                let palette = match &global_palette {
+                    Some(quantizer) => quantizer.color_map_rgb(),
+                    None => Vec::new(),
+                };
+                let output = CountingWriter::new(open_output()?);
+                let mut new_encoder = Encoder::new(output, width, height, &palette)?;
+                new_encoder.set_repeat(Repeat::Infinite)?;
+                encoder.insert(new_encoder)
+            }
+        };
+        match_dimensions(&mut image, dimensions)?;
+
+        let (width, height) = gif_dimensions(dimensions)?;
+        let local_quantizer;
+        let (quantizer, palette) = match &global_palette {
+            Some(quantizer) => (quantizer, None),
+            None => {
+                local_quantizer = NeuQuant::new(QUANTIZER_SAMPLING, 256, image.as_raw());
+                (&local_quantizer, Some(local_quantizer.color_map_rgb()))
+            }
+        };
+        let buffer = index_pixels(&image, quantizer, options.dither);
+
+        // Delays are rounded from the timestamps rather than from each other,
+        // so that rounding errors don't add up over the recording
+        let centis = (timestamp.as_micros() / 10_000) as u64;
+        if let Some((pending_centis, mut pending_frame)) = pending.take() {
+            last_delay = (centis.saturating_sub(pending_centis) as u16).max(MIN_DELAY);
+            pending_frame.delay = last_delay;
+            encoder.write_frame(&pending_frame)?;
+        }
+        pending = Some((
+            centis,
+            Frame {
+                width,
+                height,
+                palette,
+                buffer: Cow::Owned(buffer),
+                ..Frame::default()
+            },
+        ));
+        sampled += 1;
+        duration = timestamp;
+    }
+
+    let (Some(mut encoder), Some((_, mut last_frame))) = (encoder, pending) else {
+        return Err(XlabError::Encode("the recording has no frames".into()));
+    };
+    last_frame.delay = last_delay;
+    encoder.write_frame(&last_frame)?;
+    let mut output = encoder.into_inner()?;
+    output.flush()?;
+    Ok(Encoded {
+        dimensions,
+        frame_count,
+        sampled,
+        duration,
+        size: output.count,
+    })
+}
+
+/// Passes writes on to a writer, counting the bytes
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Trains a quantizer on pixels sampled evenly from the frames. Long recordings
+/// contribute pixels of every second, fourth and so on frame, so that the samples
+/// stay within [`MAX_PALETTE_SAMPLES`].
+fn learn_palette<I>(frames: I) -> Result<NeuQuant>
+where
+    I: Iterator<Item = Result<(Duration, RgbaImage)>>,
+{
+    let mut samples = Vec::new();
+    let mut frame_step = 1;
+    for (index, frame) in frames.enumerate() {
+        let (_, image) = frame?;
+        if index % frame_step != 0 {
+            continue;
+        }
+        let pixels = image.as_raw().chunks_exact(4);
+        let step = (pixels.len() / PALETTE_SAMPLES_PER_FRAME).max(1);
+        samples.extend(pixels.step_by(step).flatten());
+        // Halving the samples so far along with the frames to come keeps them spread
+        // evenly over the recording
+        if samples.len() / 4 > MAX_PALETTE_SAMPLES {
+            samples = samples
+                .chunks_exact(4)
+                .step_by(2)
+                .flatten()
+                .copied()
+                .collect();
+            frame_step *= 2;
+        }
+    }
+    if samples.is_empty() {
+        return Err(XlabError::Encode("the recording has no frames".into()));
+    }
+    Ok(NeuQuant::new(QUANTIZER_SAMPLING, 256, &samples))
+}
+
+/// Maps every pixel of `image` to the index of its closest palette color, optionally
+/// with Floyd-Steinberg dithering
+fn index_pixels(image: &RgbaImage, quantizer: &NeuQuant, dither: bool) -> Vec<u8> {
+    if !dither {
+        return image
+            .pixels()
+            .map(|pixel| quantizer.index_of(&pixel.0) as u8)
+            .collect();
+    }
+
+    let palette = quantizer.color_map_rgb();
+    let width = image.width() as usize;
+    let mut indices = Vec::with_capacity(width * image.height() as usize);
+    // Errors carried to the current and the next row, scaled by 16 and padded by a
+    // pixel on each side
+    let mut current_errors = vec![[0i32; 3]; width + 2];
+    let mut next_errors = vec![[0i32; 3]; width + 2];
+    for row in image.rows() {
+        for (x, pixel) in row.enumerate() {
+            let mut color = pixel.0;
+            for channel in 0..3 {
+                let value = color[channel] as i32 + current_errors[x + 1][channel] / 16;
+                color[channel] = value.clamp(0, 255) as u8;
+            }
+            let index = quantizer.index_of(&color);
+            for channel in 0..3 {
+                let error = color[channel] as i32 - palette[index * 3 + channel] as i32;
+                current_errors[x + 2][channel] += error * 7;
+                next_errors[x][channel] += error * 3;
+                next_errors[x + 1][channel] += error * 5;
+                next_errors[x + 2][channel] += error;
+            }
+            indices.push(index as u8);
+        }
+        std::mem::swap(&mut current_errors, &mut next_errors);
+        next_errors.fill([0; 3]);
+    }
+    indices
+}
+
+fn gif_dimensions((width, height): (u32, u32)) -> Result<(u16, u16)> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(XlabError::Config(format!(
+            "{width}x{height} is too large for a GIF"
+        ))),
+    }
+}
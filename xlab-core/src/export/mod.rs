@@ -0,0 +1,150 @@
+use std::{path::Path, time::Duration};
+
+use xcap::image::RgbaImage;
+
+use crate::{video::Container, Result, XlabError};
+
+mod apng;
+mod gif;
+mod webp;
+
+pub use self::apng::{ApngCompression, ApngOptions};
+pub use self::gif::{GifOptions, PaletteMode};
+pub use self::webp::WebpOptions;
+
+/// What a finished recording is saved as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum OutputFormat {
+    /// A video in the codec and container of the user options
+    #[default]
+    Video,
+    Gif(GifOptions),
+    WebP(WebpOptions),
+    Apng(ApngOptions),
+}
+
+impl OutputFormat {
+    /// File extension of the output, without the dot
+    pub fn extension(&self, container: Container) -> &'static str {
+        match self {
+            Self::Video => container.extension(),
+            Self::Gif(_) => "gif",
+            Self::WebP(_) => "webp",
+            // Animated PNGs keep the extension of PNGs, so that any image viewer opens them
+            Self::Apng(_) => "png",
+        }
+    }
+
+    /// Name of the file type of the output, for file dialogs
+    pub fn file_type(&self, container: Container) -> String {
+        match self {
+            Self::Video => container.to_string(),
+            Self::Gif(_) => "GIF".to_owned(),
+            Self::WebP(_) => "WebP".to_owned(),
+            Self::Apng(_) => "APNG".to_owned(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Self::Video => Ok(()),
+            Self::Gif(options) => options.validate(),
+            Self::WebP(options) => options.validate(),
+            Self::Apng(options) => options.validate(),
+        }
+    }
+}
+
+/// Writes the frames returned by `open_frames` to `output_path` as an animated image in
+/// `format` and returns its dimensions. `open_frames` may be called several times, as
+/// some formats need more than one pass over the frames. `on_frame` is called with the
+/// number of recorded frames written so far.
+pub(crate) fn encode_animation<I, F>(
+    output_path: &Path,
+    format: &OutputFormat,
+    open_frames: F,
+    on_frame: impl FnMut(u64),
+) -> Result<(u32, u32)>
+where
+    F: Fn() -> Result<I>,
+    I: Iterator<Item = Result<(Duration, RgbaImage)>>,
+{
+    match format {
+        OutputFormat::Video => Err(XlabError::Config(
+            "videos are encoded by the video encoder".into(),
+        )),
+        OutputFormat::Gif(options) => gif::encode_gif(output_path, options, open_frames, on_frame),
+        OutputFormat::WebP(options) => {
+            webp::encode_webp(output_path, options, open_frames, on_frame)
+        }
+        OutputFormat::Apng(options) => {
+            apng::encode_apng(output_path, options, open_frames, on_frame)
+        }
+    }
+}
+
+/// Fails unless `frame_rate` and `max_width` can be used for the `format` output
+fn validate_frames(
+    format: &str,
+    frame_rate: Option<u32>,
+    max_width: Option<u32>,
+    max_frame_rate: u32,
+) -> Result<()> {
+    if let Some(frame_rate) = frame_rate {
+        if frame_rate == 0 || frame_rate > max_frame_rate {
+            return Err(XlabError::Config(format!(
+                "{format} frame rate must be between 1 and {max_frame_rate}"
+            )));
+        }
+    }
+    if max_width == Some(0) {
+        return Err(XlabError::Config(format!(
+            "{format} width must be positive"
+        )));
+    }
+    Ok(())
+}
+
+/// Drops the frames in excess of `frame_rate`, and scales the rest down to `max_width`
+fn prepare_frames<I>(
+    frames: I,
+    frame_rate: Option<u32>,
+    max_width: Option<u32>,
+) -> impl Iterator<Item = Result<(Duration, RgbaImage)>>
+where
+    I: Iterator<Item = Result<(Duration, RgbaImage)>>,
+{
+    let interval = frame_rate.map(|rate| 1_000_000 / rate as u128);
+    let mut last_slot = None;
+    frames.filter_map(move |frame| {
+        let (timestamp, mut image) = match frame {
+            Ok(frame) => frame,
+            Err(err) => return Some(Err(err)),
+        };
+        // Keeps the first frame of every interval of the frame rate
+        if let Some(interval) = interval {
+            let slot = timestamp.as_micros() / interval;
+            if last_slot.is_some_and(|last_slot| slot <= last_slot) {
+                return None;
+            }
+            last_slot = Some(slot);
+        }
+        let (width, height) = image.dimensions();
+        if let Some(max_width) = max_width.filter(|max_width| width > *max_width) {
+            let new_height = (height as u64 * max_width as u64 / width as u64).max(1);
+            if let Err(err) = crate::resize_image(&mut image, (max_width, new_height as u32)) {
+                return Some(Err(err));
+            }
+        }
+        Some(Ok((timestamp, image)))
+    })
+}
+
+/// Resizes frames that don't match the dimensions of the first one. Recordings keep
+/// their dimensions, this only guards against odd decoded frames.
+fn match_dimensions(image: &mut RgbaImage, dimensions: (u32, u32)) -> Result<()> {
+    if image.dimensions() != dimensions {
+        crate::resize_image(image, dimensions)?;
+    }
+    Ok(())
+}
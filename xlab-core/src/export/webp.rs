@@ -0,0 +1,294 @@
+use std::{ffi::CStr, path::Path, ptr, time::Duration};
+
+use libwebp_sys::*;
+use xcap::image::RgbaImage;
+
+use super::{match_dimensions, prepare_frames, validate_frames};
+use crate::{Result, XlabError};
+
+/// Frame durations are whole milliseconds, recordings don't go above 60 frames per second
+const MAX_FRAME_RATE: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WebpOptions {
+    /// Keeps every pixel exactly as recorded, at the cost of a larger file
+    pub lossless: bool,
+    /// From 0 to 100. Lossy files look better and get larger as it rises; lossless
+    /// files look the same but take longer to encode and get smaller.
+    pub quality: u8,
+    /// Number of times the animation plays, 0 loops forever
+    pub loop_count: u16,
+    /// Frames per second of the animation, `None` keeps every recorded frame
+    pub frame_rate: Option<u32>,
+    /// Frames wider than this are scaled down, keeping their aspect ratio
+    pub max_width: Option<u32>,
+}
+
+impl Default for WebpOptions {
+    fn default() -> Self {
+        Self {
+            lossless: false,
+            quality: 80,
+            loop_count: 0,
+            frame_rate: None,
+            max_width: None,
+        }
+    }
+}
+
+impl WebpOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.quality > 100 {
+            return Err(XlabError::Config(
+                "WebP quality must be between 0 and 100".into(),
+            ));
+        }
+        validate_frames("WebP", self.frame_rate, self.max_width, MAX_FRAME_RATE)
+    }
+
+    /// Encoder settings for every frame
+    fn config(&self) -> Result<WebPConfig> {
+        unsafe {
+            let mut config = std::mem::zeroed::<WebPConfig>();
+            // Screen recordings are mostly text and flat shapes rather than photos
+            let initialized = WebPConfigInitInternal(
+                &mut config,
+                WebPPreset::WEBP_PRESET_DRAWING,
+                self.quality as f32,
+                WEBP_ENCODER_ABI_VERSION as i32,
+            ) != 0;
+            if !initialized {
+                return Err(XlabError::Encode(
+                    "Failed to initialize the WebP encoder".into(),
+                ));
+            }
+            if self.lossless {
+                // Lossless presets go from 0, fastest, to 9, smallest
+                let level = (self.quality as i32 * 9 + 50) / 100;
+                WebPConfigLosslessPreset(&mut config, level);
+            }
+            config.thread_level = 1;
+            if WebPValidateConfig(&config) == 0 {
+                return Err(XlabError::Config("invalid WebP settings".into()));
+            }
+            Ok(config)
+        }
+    }
+}
+
+/// Animation encoder of libwebp, freed when dropped
+struct AnimEncoder(*mut WebPAnimEncoder);
+
+impl AnimEncoder {
+    fn new((width, height): (u32, u32), loop_count: u16) -> Result<Self> {
+        unsafe {
+            let mut options = std::mem::zeroed::<WebPAnimEncoderOptions>();
+            if WebPAnimEncoderOptionsInitInternal(&mut options, WebPGetMuxABIVersion()) == 0 {
+                return Err(XlabError::Encode(
+                    "Failed to initialize the WebP encoder".into(),
+                ));
+            }
+            options.anim_params.loop_count = loop_count as i32;
+            let encoder = WebPAnimEncoderNewInternal(
+                width as i32,
+                height as i32,
+                &options,
+                WebPGetMuxABIVersion(),
+            );
+            if encoder.is_null() {
+                return Err(XlabError::Encode(
+                    "Failed to create the WebP encoder".into(),
+                ));
+            }
+            Ok(Self(encoder))
+        }
+    }
+
+    /// Adds a frame shown from `timestamp_ms` until the timestamp of the next one
+    fn add_frame(
+        &mut self,
+        image: &RgbaImage,
+        timestamp_ms: i32,
+        config: &WebPConfig,
+    ) -> Result<()> {
+        unsafe {
+            let mut picture = std::mem::zeroed::<WebPPicture>();
+            if WebPPictureInitInternal(&mut picture, WEBP_ENCODER_ABI_VERSION as i32) == 0 {
+                return Err(XlabError::Encode("Failed to allocate WebP frame".into()));
+            }
+            picture.use_argb = 1;
+            picture.width = image.width() as i32;
+            picture.height = image.height() as i32;
+            let imported =
+                WebPPictureImportRGBA(&mut picture, image.as_ptr(), image.width() as i32 * 4) != 0;
+            let added =
+                imported && WebPAnimEncoderAdd(self.0, &mut picture, timestamp_ms, config) != 0;
+            WebPPictureFree(&mut picture);
+            if !added {
+                return Err(self.error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the animation at `timestamp_ms` and returns the encoded file
+    fn finish(self, timestamp_ms: i32) -> Result<Vec<u8>> {
+        unsafe {
+            if WebPAnimEncoderAdd(self.0, ptr::null_mut(), timestamp_ms, ptr::null()) == 0 {
+                return Err(self.error());
+            }
+            let mut data = WebPData::default();
+            if WebPAnimEncoderAssemble(self.0, &mut data) == 0 {
+                return Err(self.error());
+            }
+            let bytes = std::slice::from_raw_parts(data.bytes, data.size).to_vec();
+            WebPDataClear(&mut data);
+            Ok(bytes)
+        }
+    }
+
+    fn error(&self) -> XlabError {
+        let message = unsafe { WebPAnimEncoderGetError(self.0) };
+        if message.is_null() {
+            return XlabError::Encode("Failed to encode WebP frame".into());
+        }
+        let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+        XlabError::Encode(format!("Failed to encode WebP frame: {message}"))
+    }
+}
+
+impl Drop for AnimEncoder {
+    fn drop(&mut self) {
+        unsafe { WebPAnimEncoderDelete(self.0) }
+    }
+}
+
+/// Writes the frames returned by `open_frames` to an animated WebP at `output_path` and
+/// returns its dimensions. `on_frame` is called with the number of source frames
+/// written so far.
+pub(super) fn encode_webp<I, F>(
+    output_path: &Path,
+    options: &WebpOptions,
+    open_frames: F,
+    mut on_frame: impl FnMut(u64),
+) -> Result<(u32, u32)>
+where
+    F: Fn() -> Result<I>,
+    I: Iterator<Item = Result<(Duration, RgbaImage)>>,
+{
+    options.validate()?;
+    let config = options.config()?;
+
+    let mut source_frames = 0;
+    let frames = open_frames()?.inspect(|_| {
+        source_frames += 1;
+        on_frame(source_frames);
+    });
+    let mut encoder: Option<AnimEncoder> = None;
+    let mut dimensions = (0, 0);
+    let mut last_timestamp: Option<i32> = None;
+    let mut last_duration = match options.frame_rate {
+        Some(frame_rate) => (1000 / frame_rate) as i32,
+        None => 100,
+    };
+    for frame in prepare_frames(frames, options.frame_rate, options.max_width) {
+        let (timestamp, mut image) = frame?;
+        let encoder = match encoder.as_mut() {
+            Some(encoder) => encoder,
+            None => {
+                dimensions = image.dimensions();
+                encoder.insert(AnimEncoder::new(dimensions, options.loop_count)?)
+            }
+        };
+        match_dimensions(&mut image, dimensions)?;
+
+        // Frames must follow each other by at least a millisecond
+        let mut timestamp = timestamp.as_millis() as i32;
+        if let Some(last_timestamp) = last_timestamp {
+            timestamp = timestamp.max(last_timestamp + 1);
+            last_duration = timestamp - last_timestamp;
+        }
+        encoder.add_frame(&image, timestamp, &config)?;
+        last_timestamp = Some(timestamp);
+    }
+
+    let (Some(encoder), Some(last_timestamp)) = (encoder, last_timestamp) else {
+        return Err(XlabError::Encode("the recording has no frames".into()));
+    };
+    // The last frame lasts as long as the one before it
+    let webp = encoder.finish(last_timestamp + last_duration)?;
+    std::fs::write(output_path, webp)?;
+    Ok(dimensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use xcap::image::Rgba;
+
+    use super::*;
+
+    /// Parses the chunks that follow the RIFF header, as fourcc and data
+    fn chunks(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut rest = data;
+        while rest.len() >= 8 {
+            let size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            chunks.push((&rest[..4], &rest[8..8 + size]));
+            // Chunks are padded to an even size
+            rest = &rest[(8 + size + size % 2).min(rest.len())..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn encode_animated_webp() {
+        let dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target");
+        std::fs::create_dir_all(&dir).unwrap();
+        let frames: Vec<(Duration, RgbaImage)> = [(0, 255, 0), (40, 0, 255), (140, 128, 64)]
+            .into_iter()
+            .map(|(millis, red, blue)| {
+                let frame = RgbaImage::from_pixel(32, 16, Rgba([red, 0, blue, 255]));
+                (Duration::from_millis(millis), frame)
+            })
+            .collect();
+
+        for (lossless, name, bitstream) in [
+            (false, "lossy.webp", b"VP8 "),
+            (true, "lossless.webp", b"VP8L"),
+        ] {
+            let path = dir.join(name);
+            let options = WebpOptions {
+                lossless,
+                loop_count: 3,
+                ..Default::default()
+            };
+            let mut written = 0;
+            let open_frames = || Ok(frames.clone().into_iter().map(Ok));
+            let dimensions =
+                encode_webp(&path, &options, open_frames, |count| written = count).unwrap();
+            assert_eq!(dimensions, (32, 16));
+            assert_eq!(written, 3);
+
+            let data = std::fs::read(&path).unwrap();
+            assert_eq!(&data[..4], b"RIFF");
+            assert_eq!(&data[8..12], b"WEBP");
+            let chunks = chunks(&data[12..]);
+            let (_, anim) = chunks.iter().find(|(fourcc, _)| fourcc == b"ANIM").unwrap();
+            assert_eq!(u16::from_le_bytes([anim[4], anim[5]]), 3);
+            let anmf: Vec<&[u8]> = chunks
+                .iter()
+                .filter(|(fourcc, _)| fourcc == b"ANMF")
+                .map(|(_, data)| *data)
+                .collect();
+            // Every frame lasts until the next one, the last as long as the one before it
+            let durations: Vec<u32> = anmf
+                .iter()
+                .map(|frame| u32::from_le_bytes([frame[12], frame[13], frame[14], 0]))
+                .collect();
+            assert_eq!(durations, [40, 100, 100]);
+            assert!(anmf.iter().all(|frame| &frame[16..20] == bitstream));
+            std::fs::remove_file(&path).ok();
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Append-only store for the frames of a recording.
+//!
+//! All frames live in one file that is written and read sequentially:
+//!
+//! ```text
+//! header:  MAGIC (8 bytes) | version (u32)
+//! frame:   width (u32) | height (u32) | timestamp in µs (u64) | length (u32) |
+//!          LZ4 compressed RGBA (length bytes)
+//! footer:  frame offsets (u64 each) | frame count (u64) | INDEX_MAGIC (8 bytes)
+//! ```
+//!
+//! All integers are little endian. Timestamps count from the start of the recording.
+//! The footer is only written once the recording ends; a store without it is still
+//! readable by scanning the frame headers.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use xcap::image::RgbaImage;
+
+use crate::{Result, XlabError};
+
+const MAGIC: &[u8; 8] = b"XLABFRMS";
+const INDEX_MAGIC: &[u8; 8] = b"XLABIDX1";
+const VERSION: u32 = 2;
+const HEADER_LEN: u64 = 12;
+const FRAME_HEADER_LEN: u64 = 20;
+
+pub struct FrameStoreWriter {
+    file: BufWriter<File>,
+    offsets: Vec<u64>,
+    position: u64,
+}
+
+impl FrameStoreWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self {
+            file,
+            offsets: Vec::new(),
+            position: HEADER_LEN,
+        })
+    }
+
+    /// Appends a frame captured `timestamp` after the recording started
+    pub fn append(&mut self, timestamp: Duration, frame: &RgbaImage) -> Result<()> {
+        let (width, height) = frame.dimensions();
+        let compressed = lz4_flex::compress_prepend_size(frame.as_raw());
+        self.file.write_all(&width.to_le_bytes())?;
+        self.file.write_all(&height.to_le_bytes())?;
+        self.file
+            .write_all(&(timestamp.as_micros() as u64).to_le_bytes())?;
+        self.file
+            .write_all(&(compressed.len() as u32).to_le_bytes())?;
+        self.file.write_all(&compressed)?;
+        self.offsets.push(self.position);
+        self.position += FRAME_HEADER_LEN + compressed.len() as u64;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Writes the index and flushes the store to disk
+    pub fn finish(mut self) -> Result<()> {
+        for offset in &self.offsets {
+            self.file.write_all(&offset.to_le_bytes())?;
+        }
+        self.file
+            .write_all(&(self.offsets.len() as u64).to_le_bytes())?;
+        self.file.write_all(INDEX_MAGIC)?;
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+/// Reads the frames of a store, sequentially by iterating or at random through the index
+pub struct FrameStoreReader {
+    file: BufReader<File>,
+    offsets: Vec<u64>,
+    next: usize,
+}
+
+impl FrameStoreReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        let version = read_u32(&mut file)?;
+        if &magic != MAGIC || version != VERSION {
+            return Err(XlabError::Config(format!(
+                "{} is not a frame store",
+                path.display()
+            )));
+        }
+        let offsets = match read_index(&mut file)? {
+            Some(offsets) => offsets,
+            None => scan_index(&mut file)?,
+        };
+        file.seek(SeekFrom::Start(HEADER_LEN))?;
+        Ok(Self {
+            file,
+            offsets,
+            next: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Reads the frame at `index` with its timestamp; iteration continues after it
+    pub fn read_frame(&mut self, index: usize) -> Result<(Duration, RgbaImage)> {
+        let offset = *self
+            .offsets
+            .get(index)
+            .ok_or_else(|| XlabError::Config(format!("the frame store has no frame {index}")))?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.next = index + 1;
+        read_frame(&mut self.file)
+    }
+}
+
+impl Iterator for FrameStoreReader {
+    type Item = Result<(Duration, RgbaImage)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.offsets.len() {
+            return None;
+        }
+        self.next += 1;
+        Some(read_frame(&mut self.file))
+    }
+}
+
+fn read_frame(file: &mut BufReader<File>) -> Result<(Duration, RgbaImage)> {
+    let width = read_u32(file)?;
+    let height = read_u32(file)?;
+    let timestamp = Duration::from_micros(read_u64(file)?);
+    let length = read_u32(file)?;
+    let mut compressed = vec![0; length as usize];
+    file.read_exact(&mut compressed)?;
+    let raw = lz4_flex::decompress_size_prepended(&compressed)
+        .map_err(|e| XlabError::Io(std::io::Error::other(e)))?;
+    let frame = RgbaImage::from_raw(width, height, raw).ok_or_else(|| {
+        XlabError::Io(std::io::Error::other(
+            "frame size does not match its dimensions",
+        ))
+    })?;
+    Ok((timestamp, frame))
+}
+
+/// Reads the index from the footer, if the store was finished
+fn read_index(file: &mut BufReader<File>) -> Result<Option<Vec<u64>>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    if file_len < HEADER_LEN + 16 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-16))?;
+    let count = read_u64(file)?;
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    let index_len = count.saturating_mul(8);
+    if &magic != INDEX_MAGIC || index_len > file_len - HEADER_LEN - 16 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(file_len - 16 - index_len))?;
+    (0..count)
+        .map(|_| read_u64(file))
+        .collect::<Result<_>>()
+        .map(Some)
+}
+
+/// Rebuilds the index of an unfinished store, dropping a truncated last frame
+fn scan_index(file: &mut BufReader<File>) -> Result<Vec<u64>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut offsets = Vec::new();
+    let mut position = HEADER_LEN;
+    while position + FRAME_HEADER_LEN <= file_len {
+        file.seek(SeekFrom::Start(position + 16))?;
+        let length = read_u32(file)? as u64;
+        let end = position + FRAME_HEADER_LEN + length;
+        if end > file_len {
+            break;
+        }
+        offsets.push(position);
+        position = end;
+    }
+    Ok(offsets)
+}
+
+fn read_u32(file: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    file.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(file: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    file.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use xcap::image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn frame_store_roundtrip() {
+        let dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("roundtrip.frames");
+        let frames: Vec<(Duration, RgbaImage)> = (0..5u8)
+            .map(|i| {
+                let timestamp = Duration::from_millis(i as u64 * 33);
+                let frame = RgbaImage::from_pixel(16, 9, Rgba([i * 40, 255 - i * 40, i, 255]));
+                (timestamp, frame)
+            })
+            .collect();
+
+        let mut writer = FrameStoreWriter::create(&path).unwrap();
+        for (timestamp, frame) in &frames {
+            writer.append(*timestamp, frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = FrameStoreReader::open(&path).unwrap();
+        assert_eq!(reader.len(), frames.len());
+        assert_eq!(reader.read_frame(3).unwrap(), frames[3]);
+        let mut reader = FrameStoreReader::open(&path).unwrap();
+        let read: Vec<(Duration, RgbaImage)> =
+            reader.by_ref().map(|frame| frame.unwrap()).collect();
+        assert_eq!(read, frames);
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -1,12 +1,24 @@
-use std::{path::PathBuf, sync::OnceLock, time::SystemTime};
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
 
+use export::OutputFormat;
 use serde::Deserialize;
 use user::get_pointers;
 
+pub use error::{Result, XlabError};
+
 static APP_CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Held while the recordings log is read and written again, so that recordings saved
+/// or edited at the same time don't drop each other's entries
+static RECORDINGS_LOG: Mutex<()> = Mutex::new(());
 use fast_image_resize::images::Image;
-use fast_image_resize::{PixelType, Resizer};
-use xcap::image::RgbaImage;
+use fast_image_resize::{PixelType, ResizeOptions, Resizer};
+use xcap::image::{imageops, Rgba, RgbaImage};
 
 /// This function when called first before app starts, initializes several static variables
 /// and prevents initializations during runtime
@@ -15,7 +27,16 @@ pub fn init() {
     let _ = get_pointers();
 }
 
-pub(crate) fn resize_image(img: &mut RgbaImage, (new_width, new_height): (u32, u32)) {
+pub(crate) fn resize_image(img: &mut RgbaImage, dimensions: (u32, u32)) -> Result<()> {
+    resize_image_with(img, dimensions, &ResizeOptions::new())
+}
+
+/// [`resize_image`] with the algorithm and other settings of `options`
+pub(crate) fn resize_image_with(
+    img: &mut RgbaImage,
+    (new_width, new_height): (u32, u32),
+    options: &ResizeOptions,
+) -> Result<()> {
     // Convert RgbaImage to fast_image_resize::images::Image
     let (old_width, old_height) = img.dimensions();
     let buffer_mut = unsafe {
@@ -24,76 +45,504 @@ pub(crate) fn resize_image(img: &mut RgbaImage, (new_width, new_height): (u32, u
             old_width as usize * old_height as usize * 4,
         )
     };
-    let old_img = Image::from_slice_u8(old_width, old_height, buffer_mut, PixelType::U8x4).unwrap();
+    let old_img = Image::from_slice_u8(old_width, old_height, buffer_mut, PixelType::U8x4)
+        .map_err(|e| XlabError::Encode(format!("invalid source image: {e}")))?;
 
     // Create a new image with the desired dimensions
     let mut new_img = Image::new(new_width, new_height, PixelType::U8x4);
 
     // Resize the image
     Resizer::new()
-        .resize(&old_img, &mut new_img, None)
-        .expect("Failed to resize image");
+        .resize(&old_img, &mut new_img, options)
+        .map_err(|e| XlabError::Encode(format!("failed to resize image: {e}")))?;
 
     // Replace the original image with the resized image
-    *img = RgbaImage::from_vec(new_width, new_height, new_img.into_vec()).unwrap();
+    *img = RgbaImage::from_vec(new_width, new_height, new_img.into_vec())
+        .ok_or_else(|| XlabError::Encode("resized image buffer has the wrong size".into()))?;
+    Ok(())
+}
+
+/// Placement of an image that was scaled into a fixed frame by [`letterbox`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Letterbox {
+    scale: f32,
+    offset: (u32, u32),
+}
+
+impl Letterbox {
+    pub(crate) const IDENTITY: Letterbox = Letterbox {
+        scale: 1.0,
+        offset: (0, 0),
+    };
+
+    /// Fits an image of `size` into a frame of `dimensions`, returning its placement
+    /// and the size it is scaled to
+    fn fit((old_width, old_height): (u32, u32), (width, height): (u32, u32)) -> (Self, (u32, u32)) {
+        if (old_width, old_height) == (width, height) {
+            return (Self::IDENTITY, (width, height));
+        }
+        let scale = f32::min(
+            width as f32 / old_width as f32,
+            height as f32 / old_height as f32,
+        );
+        let scaled = (
+            ((old_width as f32 * scale).round() as u32).clamp(1, width),
+            ((old_height as f32 * scale).round() as u32).clamp(1, height),
+        );
+        let offset = ((width - scaled.0) / 2, (height - scaled.1) / 2);
+        (Self { scale, offset }, scaled)
+    }
+
+    /// Maps a position in the original image to the letterboxed frame
+    pub(crate) fn map(&self, (x, y): (u32, u32)) -> (u32, u32) {
+        (
+            (x as f32 * self.scale) as u32 + self.offset.0,
+            (y as f32 * self.scale) as u32 + self.offset.1,
+        )
+    }
 }
 
-pub fn set_app_cache_dir(app_cache_dir: PathBuf) {
+/// Scales `img` to fit into `dimensions` while keeping its aspect ratio,
+/// and centers it on a black frame of exactly `dimensions`
+pub(crate) fn letterbox(
+    mut img: RgbaImage,
+    (width, height): (u32, u32),
+) -> Result<(RgbaImage, Letterbox)> {
+    let (letterbox, scaled) = Letterbox::fit(img.dimensions(), (width, height));
+    if img.dimensions() == (width, height) {
+        return Ok((img, letterbox));
+    }
+    resize_image(&mut img, scaled)?;
+    let (x, y) = letterbox.offset;
+    let mut frame = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    imageops::replace(&mut frame, &img, x as i64, y as i64);
+    Ok((frame, letterbox))
+}
+
+pub fn set_app_cache_dir(app_cache_dir: PathBuf) -> Result<()> {
     if !app_cache_dir.exists() {
-        std::fs::create_dir_all(&app_cache_dir).expect("failed to create app cache dir");
+        std::fs::create_dir_all(&app_cache_dir)?;
     }
     APP_CACHE_DIR.set(app_cache_dir).ok();
+    Ok(())
 }
 
 pub fn get_app_cache_dir() -> Option<&'static PathBuf> {
     APP_CACHE_DIR.get()
 }
 
-pub fn get_app_cache_output_dir() -> PathBuf {
-    get_app_cache_dir().unwrap().join("recordings")
+/// Returns the app cache directory, failing if [`set_app_cache_dir`] was never called
+pub(crate) fn app_cache_dir() -> Result<&'static PathBuf> {
+    get_app_cache_dir()
+        .ok_or_else(|| XlabError::Config("the app cache directory has not been set".into()))
+}
+
+pub fn get_app_cache_output_dir() -> Result<PathBuf> {
+    Ok(app_cache_dir()?.join("recordings"))
+}
+
+/// Returns the resolution of the monitor chosen in the user options
+pub fn screen_resolution() -> Result<(u32, u32)> {
+    let monitor = user::get_user_options().lock().unwrap().monitor;
+    monitor_resolution(monitor)
 }
 
-pub fn screen_resolution() -> (u32, u32) {
-    let monitor = xcap::Monitor::all().unwrap().into_iter().next().unwrap();
-    (monitor.width().unwrap(), monitor.height().unwrap())
+/// Returns the resolution of the monitor with the given id, or of the primary monitor
+pub fn monitor_resolution(id: Option<u32>) -> Result<(u32, u32)> {
+    let monitor = capture::find_monitor(id)?;
+    Ok((monitor.width()?, monitor.height()?))
 }
 
-pub fn valid_resolutions() -> [[u32; 2]; 8] {
-    let (width, height) = screen_resolution();
+/// Returns the dimensions of the selected window, or of the selected monitor
+pub fn source_resolution() -> Result<(u32, u32)> {
+    let window = user::get_user_options().lock().unwrap().window;
+    match window {
+        Some(id) => {
+            let window = capture::find_window(id)?;
+            Ok((window.width()?, window.height()?))
+        }
+        None => screen_resolution(),
+    }
+}
+
+/// Returns the dimensions of what gets recorded: the selected region if there is one,
+/// otherwise the selected window or monitor
+pub fn capture_resolution() -> Result<(u32, u32)> {
+    let region = user::get_user_options().lock().unwrap().region;
+    match region {
+        Some(region) => Ok(region.dimensions()),
+        None => source_resolution(),
+    }
+}
+
+pub fn valid_resolutions() -> Result<[[u32; 2]; 8]> {
+    Ok(resolutions_like(capture_resolution()?))
+}
+
+/// Common heights, each with the width that keeps the aspect ratio of `(width, height)`
+fn resolutions_like((width, height): (u32, u32)) -> [[u32; 2]; 8] {
     const RAW_RESOLUTIONS: [u32; 8] = [144, 240, 360, 480, 720, 1080, 1440, 2160];
-    (0..RAW_RESOLUTIONS.len())
-        .map(|i| {
-            let resolution = RAW_RESOLUTIONS[i];
-            let aspect_ratio = width as f32 / height as f32;
-            let new_width = (resolution as f32 * aspect_ratio).round() as u32;
-            [new_width, resolution]
-        })
-        .collect::<Vec<[u32; 2]>>()
-        .try_into()
-        .unwrap()
+    let aspect_ratio = width as f32 / height as f32;
+    RAW_RESOLUTIONS.map(|resolution| {
+        let new_width = (resolution as f32 * aspect_ratio).round() as u32;
+        [new_width, resolution]
+    })
 }
 
-pub fn previous_recordings() -> Vec<PreviousRecording> {
-    let recordings = match std::fs::read_to_string(completed_recordings_log())
+pub fn previous_recordings() -> Result<Vec<PreviousRecording>> {
+    // A missing or corrupted log is treated as an empty history
+    let recordings = std::fs::read_to_string(completed_recordings_log()?)
         .ok()
-        .map(|v| serde_json::from_str(&v).ok())
-        .flatten()
+        .and_then(|v| serde_json::from_str(&v).ok())
+        .unwrap_or_default();
+    Ok(recordings)
+}
+
+/// Removes the previous recording at `index` from the log, along with its cursor track
+pub fn delete_previous_recording(index: usize) -> Result<()> {
+    let _log = RECORDINGS_LOG.lock().unwrap();
+    let mut recordings = previous_recordings()?;
+    if index >= recordings.len() {
+        return Err(XlabError::Config(format!(
+            "no previous recording at index {index}"
+        )));
+    }
+    let recording = recordings.remove(index);
+    let serialized = serde_json::to_string(&recordings)?;
+    std::fs::write(completed_recordings_log()?, serialized)?;
+    std::fs::remove_file(cursor::track_path(&recording.file_path)).ok();
+    Ok(())
+}
+
+/// Exports the previous recording at `index` as `format` to `output_path`, reporting
+/// its progress to `job`, and logs the export as a recording of its own
+pub fn export_previous_recording(
+    index: usize,
+    format: OutputFormat,
+    output_path: PathBuf,
+    job: &EditJob,
+) -> Result<()> {
+    format.validate()?;
+    let recording = previous_recording(index)?;
+    if output_path == recording.file_path {
+        return Err(XlabError::Config(
+            "a recording can't be exported over itself".into(),
+        ));
+    }
+    if format == OutputFormat::Video {
+        return Err(XlabError::Config(
+            "previous recordings are already saved as video".into(),
+        ));
+    }
+    edit_previous_recordings(
+        &[index],
+        output_path,
+        job,
+        RecordingKind::Animation,
+        |sources, partial_path, on_progress| {
+            let video_path = &sources[0].file_path;
+            let duration = edit::InputFile::open(video_path)?.duration();
+            let total_millis = duration.as_millis() as u64;
+            // Progress follows the timestamp of the frame decoded last, as the animation may
+            // drop frames or read them more than once
+            let decoded = Cell::new(Duration::ZERO);
+            let open_frames = || {
+                Ok(video::VideoDecoder::open(video_path)?.inspect(|frame| {
+                    if let Ok((timestamp, _)) = frame {
+                        decoded.set(*timestamp);
+                    }
+                }))
+            };
+            let resolution = export::encode_animation(partial_path, &format, open_frames, |_| {
+                on_progress(
+                    (decoded.get().as_millis() as u64).min(total_millis),
+                    total_millis,
+                )
+            })?;
+            Ok((duration, resolution))
+        },
+    )
+}
+
+/// Adds the audio of `overlay` to the previous recording at `index` as a new track,
+/// writes the result to `output_path` and logs it as a recording of its own
+pub fn add_audio_to_previous_recording(
+    index: usize,
+    overlay: &edit::AudioOverlay,
+    output_path: PathBuf,
+    job: &EditJob,
+) -> Result<()> {
+    overlay.validate()?;
+    edit_previous_recordings(
+        &[index],
+        output_path,
+        job,
+        RecordingKind::Video,
+        |recordings, output_path, on_progress| {
+            let recording = &recordings[0];
+            edit::mux_audio(&recording.file_path, overlay, output_path, on_progress)?;
+            Ok((
+                Duration::from_secs(recording.duration),
+                recording.resolution,
+            ))
+        },
+    )
+}
+
+/// Progress of an edit or export of previous recordings. Every edit reports to its own
+/// job, so that edits running at the same time don't overwrite each other's progress.
+#[derive(Clone, Default)]
+pub struct EditJob {
+    progress: Arc<Mutex<Option<record::SaveProgress>>>,
+}
+
+impl EditJob {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` until the edit starts and once it failed
+    pub fn progress(&self) -> Option<record::SaveProgress> {
+        *self.progress.lock().unwrap()
+    }
+
+    fn set_progress(&self, progress: Option<record::SaveProgress>) {
+        *self.progress.lock().unwrap() = progress;
+    }
+}
+
+/// Writes the part of the previous recording at `index` within `range` to
+/// `output_path` and logs it as a recording of its own
+pub fn trim_previous_recording(
+    index: usize,
+    range: edit::TimeRange,
+    output_path: PathBuf,
+    job: &EditJob,
+) -> Result<()> {
+    range.validate()?;
+    edit_previous_recordings(
+        &[index],
+        output_path,
+        job,
+        RecordingKind::Video,
+        |recordings, output_path, on_progress| {
+            let recording = &recordings[0];
+            let duration = edit::trim(&recording.file_path, range, output_path, on_progress)?;
+            Ok((duration, recording.resolution))
+        },
+    )
+}
+
+/// Writes the previous recording at `index` without the part within `range` to
+/// `output_path` and logs it as a recording of its own
+pub fn cut_previous_recording(
+    index: usize,
+    range: edit::TimeRange,
+    output_path: PathBuf,
+    job: &EditJob,
+) -> Result<()> {
+    range.validate()?;
+    edit_previous_recordings(
+        &[index],
+        output_path,
+        job,
+        RecordingKind::Video,
+        |recordings, output_path, on_progress| {
+            let recording = &recordings[0];
+            let duration = edit::cut(&recording.file_path, range, output_path, on_progress)?;
+            Ok((duration, recording.resolution))
+        },
+    )
+}
+
+/// Writes the previous recording at `index` to `output_path` with its speed, or that
+/// of a part of it, changed by `change` and logs it as a recording of its own
+pub fn change_previous_recording_speed(
+    index: usize,
+    change: &edit::SpeedChange,
+    output_path: PathBuf,
+    job: &EditJob,
+) -> Result<()> {
+    change.validate()?;
+    edit_previous_recordings(
+        &[index],
+        output_path,
+        job,
+        RecordingKind::Video,
+        |recordings, output_path, on_progress| {
+            edit::change_speed(&recordings[0].file_path, change, output_path, on_progress)
+        },
+    )
+}
+
+/// Writes the previous recording at `index` cut down and scaled as `crop` says to
+/// `output_path` and logs it as a recording of its own
+pub fn crop_previous_recording(
+    index: usize,
+    crop: &edit::Crop,
+    output_path: PathBuf,
+    job: &EditJob,
+) -> Result<()> {
+    edit_previous_recordings(
+        &[index],
+        output_path,
+        job,
+        RecordingKind::Video,
+        |recordings, output_path, on_progress| {
+            edit::crop(&recordings[0].file_path, crop, output_path, on_progress)
+        },
+    )
+}
+
+/// Writes the previous recording at `index` zoomed in on the cursor as `settings`
+/// say to `output_path` and logs it as a recording of its own. Only recordings that
+/// kept their cursor track can be zoomed.
+pub fn auto_zoom_previous_recording(
+    index: usize,
+    settings: &zoom::ZoomSettings,
+    output_path: PathBuf,
+    job: &EditJob,
+) -> Result<()> {
+    settings.validate()?;
+    edit_previous_recordings(
+        &[index],
+        output_path,
+        job,
+        RecordingKind::Video,
+        |recordings, output_path, on_progress| {
+            edit::auto_zoom(&recordings[0].file_path, settings, output_path, on_progress)
+        },
+    )
+}
+
+/// Joins the previous recordings at `indices`, in that order, with `transition`
+/// between them, writes the result to `output_path` and logs it as a recording of its
+/// own
+pub fn concat_previous_recordings(
+    indices: &[usize],
+    transition: &edit::Transition,
+    output_path: PathBuf,
+    job: &EditJob,
+) -> Result<()> {
+    if indices.len() < 2 {
+        return Err(XlabError::Config(
+            "at least two recordings are needed to join them".into(),
+        ));
+    }
+    transition.validate()?;
+    edit_previous_recordings(
+        indices,
+        output_path,
+        job,
+        RecordingKind::Video,
+        |recordings, output_path, on_progress| {
+            let video_paths: Vec<PathBuf> = recordings
+                .iter()
+                .map(|recording| recording.file_path.clone())
+                .collect();
+            edit::concat(&video_paths, transition, output_path, on_progress)
+        },
+    )
+}
+
+/// Runs `write`, which writes an edit of the previous recordings at `indices` to the
+/// path it is given and returns its duration and resolution, reporting its progress to
+/// `job`. The edit replaces `output_path` once it is complete and is logged as `kind`.
+fn edit_previous_recordings(
+    indices: &[usize],
+    output_path: PathBuf,
+    job: &EditJob,
+    kind: RecordingKind,
+    write: impl FnOnce(
+        &[PreviousRecording],
+        &Path,
+        &mut dyn FnMut(u64, u64),
+    ) -> Result<(Duration, (u32, u32))>,
+) -> Result<()> {
+    let recordings = previous_recordings()?;
+    let sources = indices
+        .iter()
+        .map(|&index| {
+            recordings
+                .get(index)
+                .cloned()
+                .ok_or_else(|| XlabError::Config(format!("no previous recording at index {index}")))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for recording in &sources {
+        recording.ensure_video()?;
+    }
+    if sources
+        .iter()
+        .any(|recording| recording.file_path == output_path)
     {
-        Some(u) => u,
-        None => Vec::new(),
+        return Err(XlabError::Config(
+            "a recording can't be edited in place".into(),
+        ));
+    }
+    job.set_progress(Some(record::SaveProgress::Initializing));
+    let mut on_progress = |written, total| {
+        job.set_progress(Some(record::SaveProgress::Saving(written, total)));
     };
-    recordings
+    let result = write_replacing(&output_path, |partial_path| {
+        write(&sources, partial_path, &mut on_progress)
+    })
+    .and_then(|(duration, resolution)| {
+        job.set_progress(Some(record::SaveProgress::Finalizing));
+        log_new_recording(output_path.clone(), duration.as_secs(), resolution, kind)
+    });
+    match result {
+        Ok(()) => {
+            job.set_progress(Some(record::SaveProgress::Done));
+            Ok(())
+        }
+        Err(err) => {
+            job.set_progress(None);
+            Err(err)
+        }
+    }
 }
 
-pub fn delete_previous_recording(index: usize) {
-    let mut recordings = previous_recordings();
-    recordings.remove(index);
-    let serialized = serde_json::to_string(&recordings).unwrap();
-    std::fs::write(completed_recordings_log(), serialized).unwrap();
+/// Runs `write` on a file next to `output_path`, which replaces `output_path` once
+/// `write` succeeded. A failed edit leaves whatever was at `output_path` as it was.
+fn write_replacing<T>(output_path: &Path, write: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+    // The extension stays last, since it picks the container of the edit
+    let stem = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let partial_name = match output_path.extension() {
+        Some(extension) => format!(".{stem}.partial.{}", extension.to_string_lossy()),
+        None => format!(".{stem}.partial"),
+    };
+    let partial_path = output_path.with_file_name(partial_name);
+    let result = write(&partial_path).and_then(|value| {
+        std::fs::rename(&partial_path, output_path)?;
+        // Edits have no cursor track, and that of a recording they replaced doesn't fit
+        std::fs::remove_file(cursor::track_path(output_path)).ok();
+        Ok(value)
+    });
+    if result.is_err() {
+        std::fs::remove_file(&partial_path).ok();
+    }
+    result
 }
 
-fn log_new_recording(file_path: PathBuf, duration: u64) {
-    let mut recordings = previous_recordings();
+fn previous_recording(index: usize) -> Result<PreviousRecording> {
+    previous_recordings()?
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| XlabError::Config(format!("no previous recording at index {index}")))
+}
+
+fn log_new_recording(
+    file_path: PathBuf,
+    duration: u64,
+    resolution: (u32, u32),
+    kind: RecordingKind,
+) -> Result<()> {
+    let _log = RECORDINGS_LOG.lock().unwrap();
+    let mut recordings = previous_recordings()?;
 
     if let Some(index) = recordings.iter().position(|v| &v.file_path == &file_path) {
         recordings.remove(index);
@@ -102,19 +551,21 @@ fn log_new_recording(file_path: PathBuf, duration: u64) {
     let recording = PreviousRecording {
         time_recorded: SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
+            .unwrap_or_default()
             .as_secs(),
         duration,
         file_path,
-        resolution: screen_resolution(),
+        resolution,
+        kind,
     };
 
     recordings.push(recording);
-    let serialized = serde_json::to_string(&recordings).unwrap();
-    std::fs::write(completed_recordings_log(), serialized).unwrap();
+    let serialized = serde_json::to_string(&recordings)?;
+    std::fs::write(completed_recordings_log()?, serialized)?;
+    Ok(())
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PreviousRecording {
     #[serde(
         serialize_with = "serialize_time_recorded",
@@ -128,16 +579,49 @@ pub struct PreviousRecording {
     )]
     file_path: PathBuf,
     resolution: (u32, u32),
+    // Logs written before animations were told apart only held videos
+    #[serde(default)]
+    kind: RecordingKind,
+}
+
+impl PreviousRecording {
+    pub fn file_path(&self) -> &std::path::Path {
+        &self.file_path
+    }
+
+    pub fn kind(&self) -> RecordingKind {
+        self.kind
+    }
+
+    /// Fails unless the recording is a video, which is all edits can read
+    fn ensure_video(&self) -> Result<()> {
+        match self.kind {
+            RecordingKind::Video => Ok(()),
+            RecordingKind::Animation => Err(XlabError::Config(format!(
+                "{} is an animated image, only videos can be edited",
+                self.file_path.display()
+            ))),
+        }
+    }
 }
 
-fn serialize_path_buf<S>(path_buf: &PathBuf, sz: S) -> Result<S::Ok, S::Error>
+/// What a previous recording was saved as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum RecordingKind {
+    #[default]
+    Video,
+    /// A GIF, WebP or APNG
+    Animation,
+}
+
+fn serialize_path_buf<S>(path_buf: &PathBuf, sz: S) -> std::result::Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    sz.serialize_str(path_buf.to_str().unwrap())
+    sz.serialize_str(&path_buf.to_string_lossy())
 }
 
-fn deserialize_path_buf<'de, D>(dz: D) -> Result<PathBuf, D::Error>
+fn deserialize_path_buf<'de, D>(dz: D) -> std::result::Result<PathBuf, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -145,7 +629,7 @@ where
     Ok(PathBuf::from(path_str))
 }
 
-fn serialize_time_recorded<S>(time: &u64, sz: S) -> Result<S::Ok, S::Error>
+fn serialize_time_recorded<S>(time: &u64, sz: S) -> std::result::Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
@@ -160,7 +644,7 @@ where
     .serialize(sz)
 }
 
-fn deserialize_time_recorded<'de, D>(dz: D) -> Result<u64, D::Error>
+fn deserialize_time_recorded<'de, D>(dz: D) -> std::result::Result<u64, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -177,50 +661,747 @@ where
     }
 }
 
-fn completed_recordings_log() -> PathBuf {
-    let cache_path = get_app_cache_dir().unwrap();
+fn completed_recordings_log() -> Result<PathBuf> {
+    let cache_path = app_cache_dir()?;
     let log_path = cache_path.join("prev_recordings.json");
     std::fs::OpenOptions::new()
         .create(true)
+        .truncate(false)
         .write(true)
         .read(true)
-        .open(&log_path)
-        .unwrap();
-    log_path
+        .open(&log_path)?;
+    Ok(log_path)
 }
 
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
-    use record::{record, save_video, stop};
-    use user::{update_frame_rate, update_pointer, update_resolution};
+    use capture::SyntheticSource;
+    use options::EncodeMode;
+    use record::Recorder;
+    use user::{
+        update_frame_rate, update_pointer, update_resolution, update_timelapse_interval,
+        UserOptions,
+    };
 
     use super::*;
 
-    #[test]
-    fn record_screen() {
+    fn test_cache_dir() -> PathBuf {
         let app_cache_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
             .join("target")
             .join("app-cache");
-        set_app_cache_dir(app_cache_dir);
-        update_pointer(2);
-        update_frame_rate(24);
-        let width = 1366 * 720 / 768;
-        update_resolution(width, 720);
-        record();
-        std::thread::sleep(Duration::from_secs(12));
-        stop();
-        save_video(|save_fn| save_fn(None));
-        super::record::get_save_handle()
+        set_app_cache_dir(app_cache_dir.clone()).unwrap();
+        app_cache_dir
+    }
+
+    /// Serializes the tests that log recordings. They share one recordings log, and
+    /// logging a recording again moves it to the end, so the index a test looked up is
+    /// only good until another test logs something.
+    fn lock_recordings_log() -> std::sync::MutexGuard<'static, ()> {
+        static LOGGING_TESTS: Mutex<()> = Mutex::new(());
+        // A failed test leaves the log as usable as before
+        LOGGING_TESTS
             .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    #[test]
+    fn resolutions_follow_region() {
+        let region = capture::CaptureRegion::new(100, 50, 400, 300);
+        let resolutions = resolutions_like(region.dimensions());
+        assert_eq!(resolutions[0], [192, 144]);
+        assert_eq!(resolutions[4], [960, 720]);
+        assert_eq!(resolutions_like((1920, 1080))[5], [1920, 1080]);
+    }
+
+    #[test]
+    fn letterbox_centers_images() {
+        let fit = |size, dimensions| {
+            let (letterbox, scaled) = Letterbox::fit(size, dimensions);
+            (letterbox.scale, letterbox.offset, scaled)
+        };
+        // Wider images get bars above and below, taller ones on the sides
+        assert_eq!(fit((640, 180), (320, 180)), (0.5, (0, 45), (320, 90)));
+        assert_eq!(fit((90, 180), (320, 180)), (1.0, (115, 0), (90, 180)));
+        assert_eq!(fit((180, 720), (320, 180)), (0.25, (137, 0), (45, 180)));
+        // Images of the same aspect ratio fill the frame
+        assert_eq!(fit((640, 360), (320, 180)), (0.5, (0, 0), (320, 180)));
+        assert_eq!(fit((320, 180), (320, 180)), (1.0, (0, 0), (320, 180)));
+    }
+
+    #[test]
+    fn record_synthetic_source() {
+        let _log = lock_recordings_log();
+        let output_path = test_cache_dir().join("synthetic.mp4");
+        std::fs::remove_file(&output_path).ok();
+        let pointer = get_pointers()[2].as_ref();
+        let recorder = Recorder::with_user_options(UserOptions::new(pointer, 24, (320, 180)))
+            .with_source(|_| Ok(Box::new(SyntheticSource::new(640, 360))));
+        recorder.start().unwrap();
+        std::thread::sleep(Duration::from_secs(2));
+        recorder.stop().unwrap();
+        let save_path = output_path.clone();
+        recorder
+            .save(OutputFormat::Video, move |save_fn| save_fn(Some(save_path)))
+            .unwrap();
+        recorder.wait();
+        assert!(recorder.take_error().is_none());
+        assert!(std::fs::metadata(&output_path).unwrap().len() > 0);
+    }
+
+    #[test]
+    fn record_synthetic_gif() {
+        let _log = lock_recordings_log();
+        let output_path = test_cache_dir().join("synthetic.gif");
+        std::fs::remove_file(&output_path).ok();
+        let pointer = get_pointers()[2].as_ref();
+        let recorder = Recorder::with_user_options(UserOptions::new(pointer, 24, (320, 180)))
+            .with_source(|_| Ok(Box::new(SyntheticSource::new(640, 360))));
+        recorder.start().unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+        recorder.stop().unwrap();
+        let options = export::GifOptions {
+            frame_rate: Some(10),
+            max_width: Some(160),
+            ..Default::default()
+        };
+        let save_path = output_path.clone();
+        recorder
+            .save(OutputFormat::Gif(options), move |save_fn| {
+                save_fn(Some(save_path))
+            })
+            .unwrap();
+        recorder.wait();
+        assert!(recorder.take_error().is_none());
+        let gif = std::fs::read(&output_path).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+        // Logical screen width and height, scaled down to the maximum width
+        assert_eq!(&gif[6..10], &[160, 0, 90, 0]);
+        assert!(!cursor::track_path(&output_path).exists());
+
+        // Animations are logged, but can't be edited like videos
+        let recordings = previous_recordings().unwrap();
+        let index = recordings
+            .iter()
+            .rposition(|recording| recording.file_path == output_path)
+            .unwrap();
+        assert_eq!(recordings[index].kind(), RecordingKind::Animation);
+        let range = edit::TimeRange::new(Duration::ZERO, Duration::from_millis(500));
+        let trimmed_path = test_cache_dir().join("synthetic_trimmed.gif");
+        assert!(
+            trim_previous_recording(index, range, trimmed_path.clone(), &EditJob::new()).is_err()
+        );
+        assert!(!trimmed_path.exists());
+    }
+
+    #[test]
+    fn pause_excludes_paused_time() {
+        test_cache_dir();
+        let pointer = get_pointers()[0].as_ref();
+        let recorder = Recorder::with_user_options(UserOptions::new(pointer, 24, (320, 180)))
+            .with_source(|_| Ok(Box::new(SyntheticSource::new(320, 180))));
+        recorder.start().unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+        recorder.pause().unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+        recorder.resume().unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+        recorder.stop().unwrap();
+        let duration = recorder.recording_state().duration();
+        assert!(duration >= Duration::from_secs(1));
+        assert!(duration < Duration::from_millis(1500));
+        recorder.discard().unwrap();
+        recorder.wait();
+        assert!(recorder.take_error().is_none());
+    }
+
+    /// Counts the audio streams of the file at `path`
+    fn audio_stream_count(path: &std::path::Path) -> usize {
+        use ffmpeg_sys_next::*;
+
+        let path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let mut fmt_ctx = std::ptr::null_mut();
+            if avformat_open_input(
+                &mut fmt_ctx,
+                path.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null_mut(),
+            ) < 0
+            {
+                return 0;
+            }
+            let streams =
+                std::slice::from_raw_parts((*fmt_ctx).streams, (*fmt_ctx).nb_streams as usize);
+            let count = streams
+                .iter()
+                .filter(|stream| {
+                    (*(***stream).codecpar).codec_type == AVMediaType::AVMEDIA_TYPE_AUDIO
+                })
+                .count();
+            avformat_close_input(&mut fmt_ctx);
+            count
+        }
+    }
+
+    /// Records two seconds of a synthetic screen with `user_options` to `output_path`,
+    /// with the audio sources `configure` sets up, and returns the number of audio
+    /// streams of the video
+    fn record_with_audio(
+        output_path: PathBuf,
+        user_options: UserOptions,
+        configure: impl FnOnce(Recorder) -> Recorder,
+    ) -> usize {
+        std::fs::remove_file(&output_path).ok();
+        let recorder = Recorder::with_user_options(user_options)
+            .with_source(|_| Ok(Box::new(SyntheticSource::new(320, 180))));
+        let recorder = configure(recorder);
+        recorder.start().unwrap();
+        std::thread::sleep(Duration::from_secs(2));
+        recorder.stop().unwrap();
+        let save_path = output_path.clone();
+        recorder
+            .save(OutputFormat::Video, move |save_fn| save_fn(Some(save_path)))
+            .unwrap();
+        recorder.wait();
+        assert!(recorder.take_error().is_none());
+        audio_stream_count(&output_path)
+    }
+
+    fn system_audio_options(encode_mode: EncodeMode) -> UserOptions {
+        let pointer = get_pointers()[0].as_ref();
+        UserOptions {
+            encode_mode,
+            system_audio: true,
+            ..UserOptions::new(pointer, 24, (320, 180))
+        }
+    }
+
+    #[test]
+    fn record_tone() {
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        for (encode_mode, name) in [
+            (EncodeMode::Cached, "tone-cached.mp4"),
+            (EncodeMode::Streaming, "tone-streaming.mp4"),
+        ] {
+            let streams = record_with_audio(
+                cache_dir.join(name),
+                system_audio_options(encode_mode),
+                |recorder| {
+                    recorder.with_audio_source(|_| Ok(Box::new(audio::ToneSource::new(440.0))))
+                },
+            );
+            assert_eq!(streams, 1);
+        }
+    }
+
+    #[test]
+    fn record_microphone() {
+        use audio::{AudioInput, AudioMix, ToneSource};
+
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        for (audio_mix, name, expected_streams) in [
+            (AudioMix::Mixed, "microphone-mixed.mkv", 1),
+            (AudioMix::Separate, "microphone-separate.mkv", 2),
+        ] {
+            let user_options = UserOptions {
+                microphone: true,
+                microphone_gain: 2.0,
+                audio_mix,
+                container: video::Container::Mkv,
+                ..system_audio_options(EncodeMode::Cached)
+            };
+            let streams = record_with_audio(cache_dir.join(name), user_options, |recorder| {
+                recorder.set_muted(AudioInput::System, true);
+                recorder
+                    .with_audio_source(|_| Ok(Box::new(ToneSource::new(440.0))))
+                    .with_microphone_source(|_| Ok(Box::new(ToneSource::new(660.0))))
+            });
+            assert_eq!(streams, expected_streams);
+        }
+    }
+
+    /// Writes a second of a 440 Hz tone to a 16 bit stereo WAV file
+    fn write_wav(path: &std::path::Path) {
+        let tone = audio::ToneSource::new(440.0);
+        let samples: Vec<u8> = (0..audio::SAMPLE_RATE as u64)
+            .flat_map(|position| {
+                let sample = (tone.sample(position) * i16::MAX as f32) as i16;
+                [sample.to_le_bytes(), sample.to_le_bytes()].concat()
+            })
+            .collect();
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&audio::SAMPLE_RATE.to_le_bytes());
+        wav.extend_from_slice(&(audio::SAMPLE_RATE * 4).to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&samples);
+        std::fs::write(path, wav).unwrap();
+    }
+
+    /// Index of the recording at `path` in the recordings log
+    fn logged_recording(path: &std::path::Path) -> usize {
+        previous_recordings()
+            .unwrap()
+            .iter()
+            .position(|recording| recording.file_path == path)
+            .unwrap()
+    }
+
+    /// Two seconds of a synthetic screen and a 440 Hz tone, recorded once per run for
+    /// the edit tests to start from. Callers hold [`lock_recordings_log`], since the
+    /// recording is logged when it is first used.
+    fn source_clip() -> &'static std::path::Path {
+        static SOURCE_CLIP: OnceLock<PathBuf> = OnceLock::new();
+        SOURCE_CLIP.get_or_init(|| {
+            let video_path = test_cache_dir().join("edit-source.mp4");
+            let streams = record_with_audio(
+                video_path.clone(),
+                system_audio_options(EncodeMode::Cached),
+                |recorder| {
+                    recorder.with_audio_source(|_| Ok(Box::new(audio::ToneSource::new(440.0))))
+                },
+            );
+            assert_eq!(streams, 1);
+            video_path
+        })
+    }
+
+    fn duration(path: &std::path::Path) -> Duration {
+        edit::InputFile::open(path).unwrap().duration()
+    }
+
+    /// Fails unless `actual` is within a few frames of `expected`
+    fn assert_near(actual: Duration, expected: Duration) {
+        assert!(
+            actual.abs_diff(expected) < Duration::from_millis(150),
+            "{actual:?} is not near {expected:?}"
+        );
+    }
+
+    #[test]
+    fn add_voice_over() {
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        let video_path = source_clip();
+
+        // WAV audio is encoded, with its volume and position changed
+        let wav_path = cache_dir.join("voice-over.wav");
+        write_wav(&wav_path);
+        let overlay = edit::AudioOverlay {
+            offset: Duration::from_millis(500),
+            gain: 0.5,
+            ..edit::AudioOverlay::new(wav_path)
+        };
+        let output_path = cache_dir.join("voice-over-wav.mkv");
+        let job = EditJob::new();
+        add_audio_to_previous_recording(
+            logged_recording(video_path),
+            &overlay,
+            output_path.clone(),
+            &job,
+        )
+        .unwrap();
+        assert!(matches!(job.progress(), Some(record::SaveProgress::Done)));
+        assert_eq!(audio_stream_count(&output_path), 2);
+        logged_recording(&output_path);
+
+        // AAC audio, here that of the recording itself, is copied
+        let overlay = edit::AudioOverlay::new(video_path.to_path_buf());
+        let output_path = cache_dir.join("voice-over-aac.mp4");
+        add_audio_to_previous_recording(
+            logged_recording(video_path),
+            &overlay,
+            output_path.clone(),
+            &EditJob::new(),
+        )
+        .unwrap();
+        assert_eq!(audio_stream_count(&output_path), 2);
+
+        let overlay = edit::AudioOverlay {
+            gain: -1.0,
+            ..overlay
+        };
+        assert!(add_audio_to_previous_recording(
+            logged_recording(video_path),
+            &overlay,
+            cache_dir.join("voice-over-invalid.mp4"),
+            &EditJob::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn trim_and_cut() {
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        let video_path = source_clip();
+
+        // Neither end falls on a keyframe, so both boundary GOPs are encoded again
+        let output_path = cache_dir.join("trimmed.mp4");
+        let range = edit::TimeRange::new(Duration::from_millis(300), Duration::from_millis(1400));
+        let job = EditJob::new();
+        trim_previous_recording(
+            logged_recording(video_path),
+            range,
+            output_path.clone(),
+            &job,
+        )
+        .unwrap();
+        assert!(matches!(job.progress(), Some(record::SaveProgress::Done)));
+        assert_near(duration(&output_path), range.duration());
+        assert_eq!(audio_stream_count(&output_path), 1);
+
+        let output_path = cache_dir.join("cut.mkv");
+        let range = edit::TimeRange::new(Duration::from_millis(500), Duration::from_millis(1000));
+        cut_previous_recording(
+            logged_recording(video_path),
+            range,
+            output_path.clone(),
+            &EditJob::new(),
+        )
+        .unwrap();
+        assert_near(
+            duration(&output_path),
+            duration(video_path) - range.duration(),
+        );
+
+        let range = edit::TimeRange::new(Duration::from_secs(1), Duration::from_secs(1));
+        assert!(trim_previous_recording(
+            logged_recording(video_path),
+            range,
+            cache_dir.join("trimmed-invalid.mp4"),
+            &job
+        )
+        .is_err());
+
+        // A failed edit leaves the file it would have replaced alone
+        let output_path = cache_dir.join("trimmed.mp4");
+        let range = edit::TimeRange::new(Duration::from_secs(10), Duration::from_secs(11));
+        assert!(trim_previous_recording(
+            logged_recording(video_path),
+            range,
+            output_path.clone(),
+            &job
+        )
+        .is_err());
+        assert!(job.progress().is_none());
+        assert_near(duration(&output_path), Duration::from_millis(1100));
+    }
+
+    #[test]
+    fn concat_recordings() {
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        let video_path = source_clip();
+        // The clip is joined to itself
+        let indices = [logged_recording(video_path); 2];
+        let total = duration(video_path) * 2;
+
+        // Takes with the same settings are copied
+        let output_path = cache_dir.join("concat.mp4");
+        concat_previous_recordings(
+            &indices,
+            &edit::Transition::Cut,
+            output_path.clone(),
+            &EditJob::new(),
+        )
+        .unwrap();
+        assert_near(duration(&output_path), total);
+        assert_eq!(audio_stream_count(&output_path), 1);
+
+        // A crossfade is encoded, overlapping the takes
+        let crossfade = Duration::from_millis(500);
+        let output_path = cache_dir.join("concat-crossfade.mkv");
+        let transition = edit::Transition::Crossfade(crossfade);
+        concat_previous_recordings(&indices, &transition, output_path.clone(), &EditJob::new())
+            .unwrap();
+        assert_near(duration(&output_path), total - crossfade);
+        assert_eq!(audio_stream_count(&output_path), 1);
+
+        assert!(concat_previous_recordings(
+            &indices[..1],
+            &edit::Transition::Cut,
+            cache_dir.join("concat-invalid.mp4"),
+            &EditJob::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn change_speed() {
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        let video_path = source_clip();
+
+        let output_path = cache_dir.join("speed-blended.mp4");
+        let change = edit::SpeedChange {
+            blend: true,
+            ..edit::SpeedChange::new(4.0)
+        };
+        change_previous_recording_speed(
+            logged_recording(video_path),
+            &change,
+            output_path.clone(),
+            &EditJob::new(),
+        )
+        .unwrap();
+        assert_near(duration(&output_path), duration(video_path) / 4);
+
+        // Only the range is slowed down, the sound around it is kept
+        let output_path = cache_dir.join("speed-range.mkv");
+        let range = edit::TimeRange::new(Duration::from_millis(500), Duration::from_millis(1000));
+        let change = edit::SpeedChange {
+            range: Some(range),
+            ..edit::SpeedChange::new(0.5)
+        };
+        change_previous_recording_speed(
+            logged_recording(video_path),
+            &change,
+            output_path.clone(),
+            &EditJob::new(),
+        )
+        .unwrap();
+        assert_near(
+            duration(&output_path),
+            duration(video_path) + range.duration(),
+        );
+        assert_eq!(audio_stream_count(&output_path), 1);
+
+        assert!(change_previous_recording_speed(
+            logged_recording(video_path),
+            &edit::SpeedChange::new(0.0),
+            cache_dir.join("speed-invalid.mp4"),
+            &EditJob::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn crop_and_rescale() {
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        let video_path = source_clip();
+        let dimensions = |path: &std::path::Path| {
+            edit::InputFile::open(path)
+                .unwrap()
+                .video_dimensions()
+                .unwrap()
+        };
+
+        // Odd sizes are rounded down to even ones
+        let output_path = cache_dir.join("cropped.mp4");
+        let crop = edit::Crop::new(capture::CaptureRegion::new(10, 20, 161, 91));
+        crop_previous_recording(
+            logged_recording(video_path),
+            &crop,
+            output_path.clone(),
+            &EditJob::new(),
+        )
+        .unwrap();
+        assert_eq!(dimensions(&output_path), (160, 90));
+        assert_eq!(audio_stream_count(&output_path), 1);
+        let logged = &previous_recordings().unwrap()[logged_recording(&output_path)];
+        assert_eq!(logged.resolution, (160, 90));
+
+        let output_path = cache_dir.join("cropped-scaled.mkv");
+        let crop = edit::Crop {
+            resolution: Some((256, 144)),
+            filter: edit::ScaleFilter::Nearest,
+            ..edit::Crop::new(capture::CaptureRegion::new(0, 0, 160, 90))
+        };
+        crop_previous_recording(
+            logged_recording(video_path),
+            &crop,
+            output_path.clone(),
+            &EditJob::new(),
+        )
+        .unwrap();
+        assert_eq!(dimensions(&output_path), (256, 144));
+
+        let crop = edit::Crop::new(capture::CaptureRegion::new(200, 0, 160, 90));
+        assert!(crop_previous_recording(
+            logged_recording(video_path),
+            &crop,
+            cache_dir.join("cropped-invalid.mp4"),
+            &EditJob::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn record_and_zoom_cursor() {
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        let video_path = cache_dir.join("zoom-live.mp4");
+        let pointer = get_pointers()[0].as_ref();
+        let user_options = UserOptions {
+            encode_mode: EncodeMode::Cached,
+            auto_zoom: Some(zoom::ZoomSettings::default()),
+            ..UserOptions::new(pointer, 24, (320, 180))
+        };
+        record_with_audio(video_path.clone(), user_options, |recorder| {
+            recorder.with_source(|_| {
+                Ok(Box::new(
+                    SyntheticSource::new(320, 180).with_presses(vec![5, 6]),
+                ))
+            })
+        });
+        // The cursor track is kept next to the recording
+        let track = cursor::CursorTrack::read(&cursor::track_path(&video_path)).unwrap();
+        assert_eq!(track.dimensions, (320, 180));
+        assert!(track.samples.iter().any(|sample| sample.pressed));
+
+        let output_path = cache_dir.join("zoom-export.mkv");
+        auto_zoom_previous_recording(
+            logged_recording(&video_path),
+            &zoom::ZoomSettings::default(),
+            output_path.clone(),
+            &EditJob::new(),
+        )
+        .unwrap();
+        let dimensions = edit::InputFile::open(&output_path)
             .unwrap()
-            .take()
-            .map(|v| v.join());
+            .video_dimensions();
+        assert_eq!(dimensions, Some((320, 180)));
+
+        // Edits don't keep the cursor track, so they can't be zoomed
+        assert!(auto_zoom_previous_recording(
+            logged_recording(&output_path),
+            &zoom::ZoomSettings::default(),
+            cache_dir.join("zoom-invalid.mp4"),
+            &EditJob::new()
+        )
+        .is_err());
+
+        // The track goes once the recording is no longer logged
+        delete_previous_recording(logged_recording(&video_path)).unwrap();
+        assert!(!cursor::track_path(&video_path).exists());
+    }
+
+    #[test]
+    fn old_log_entries_are_videos() {
+        let entry = r#"{"time_recorded":{"secs_since_epoch":1},"duration":2,"file_path":"a.mp4","resolution":[640,360]}"#;
+        let recording: PreviousRecording = serde_json::from_str(entry).unwrap();
+        assert_eq!(recording.kind(), RecordingKind::Video);
+    }
+
+    #[test]
+    fn record_timelapse() {
+        let _log = lock_recordings_log();
+        let cache_dir = test_cache_dir();
+        let output_path = cache_dir.join("timelapse.mp4");
+        // Five frames a second, played at 24 frames a second, without sound
+        let user_options = UserOptions {
+            timelapse_interval: Some(Duration::from_millis(200)),
+            ..system_audio_options(EncodeMode::Cached)
+        };
+        let streams = record_with_audio(output_path.clone(), user_options, |recorder| {
+            recorder.with_audio_source(|_| Ok(Box::new(audio::ToneSource::new(440.0))))
+        });
+        assert_eq!(streams, 0);
+        let duration = edit::InputFile::open(&output_path).unwrap().duration();
+        assert!(duration < Duration::from_millis(750), "{duration:?}");
+        assert!(update_timelapse_interval(Some(Duration::ZERO)).is_err());
+    }
+
+    /// Records the monitor of a null sink that a tone is played into, which works on a
+    /// headless machine running a PulseAudio or PipeWire server
+    #[test]
+    #[ignore = "needs a PulseAudio or PipeWire server"]
+    fn record_null_sink() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let _log = lock_recordings_log();
+        use audio::{AudioSource, PulseSource, ToneSource};
+
+        let module = Command::new("pactl")
+            .args(["load-module", "module-null-sink", "sink_name=xlab_test"])
+            .output()
+            .unwrap();
+        assert!(module.status.success());
+        let module = String::from_utf8(module.stdout).unwrap();
+        let mut player = Command::new("pacat")
+            .args([
+                "--device=xlab_test",
+                "--format=float32le",
+                "--rate=48000",
+                "--channels=2",
+            ])
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut player_input = player.stdin.take().unwrap();
+        std::thread::spawn(move || {
+            let mut tone = ToneSource::new(440.0);
+            while let Ok(samples) = tone.next_samples() {
+                let bytes: Vec<u8> = samples
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect();
+                if player_input.write_all(&bytes).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let streams = record_with_audio(
+            test_cache_dir().join("null-sink.mp4"),
+            system_audio_options(EncodeMode::Cached),
+            |recorder| {
+                recorder.with_audio_source(|_| Ok(Box::new(PulseSource::new("xlab_test.monitor")?)))
+            },
+        );
+        assert_eq!(streams, 1);
+        player.kill().ok();
+        player.wait().ok();
+        Command::new("pactl")
+            .args(["unload-module", module.trim()])
+            .status()
+            .unwrap();
+    }
+
+    #[test]
+    fn record_screen() {
+        let _log = lock_recordings_log();
+        test_cache_dir();
+        update_pointer(2).unwrap();
+        update_frame_rate(24).unwrap();
+        let width = 1366 * 720 / 768;
+        update_resolution(width, 720).unwrap();
+        let recorder = Recorder::new();
+        recorder.start().unwrap();
+        std::thread::sleep(Duration::from_secs(12));
+        recorder.stop().unwrap();
+        recorder
+            .save(OutputFormat::Video, |save_fn| save_fn(None))
+            .unwrap();
+        recorder.wait();
+        assert!(recorder.take_error().is_none());
     }
 }
 
+pub mod audio;
+pub mod capture;
+pub mod cursor;
+pub mod edit;
+pub mod error;
+pub mod export;
+pub mod frame_store;
 pub mod options;
 pub mod record;
 pub mod user;
 pub mod video;
+pub mod zoom;
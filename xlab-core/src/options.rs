@@ -5,11 +5,23 @@ use std::{
 };
 use xcap::image::RgbaImage;
 
+use crate::{
+    audio::AudioTrack,
+    capture::CaptureRegion,
+    user::UserOptions,
+    video::{Container, QualitySettings, VideoCodec},
+    zoom::ZoomSettings,
+};
+
 #[derive(Clone, Copy, serde::Serialize)]
 pub enum RecordingState {
     Idle,
+    /// Recording since the instant, which is moved forward by the time spent paused
     #[serde(serialize_with = "serialize_instant")]
     Recording(Instant),
+    /// Paused after recording for the duration
+    #[serde(serialize_with = "serialize_duration")]
+    Paused(Duration),
     #[serde(serialize_with = "serialize_duration")]
     Done(Duration),
 }
@@ -69,40 +81,67 @@ impl RecordingState {
         match self {
             RecordingState::Idle => Duration::from_secs(0),
             RecordingState::Recording(instant) => instant.elapsed(),
-            RecordingState::Done(duration) => *duration,
+            RecordingState::Paused(duration) | RecordingState::Done(duration) => *duration,
         }
     }
 }
 
+/// How captured frames reach the encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum EncodeMode {
+    /// Frames are cached on disk while recording and encoded when the recording is saved
+    #[default]
+    Cached,
+    /// Frames are encoded while recording, so the video is ready once recording stops
+    Streaming,
+}
+
 pub struct RecordOptions {
     pub(crate) pointer: &'static (dyn Pointer + Send + Sync),
     pub(crate) frame_rate: u32,
     pub(crate) resolution: (u32, u32),
+    /// Time between the frames of a timelapse, `None` records in real time
+    pub(crate) timelapse_interval: Option<Duration>,
+    /// Zooms in on the cursor while recording
+    pub(crate) auto_zoom: Option<ZoomSettings>,
+    pub(crate) region: Option<CaptureRegion>,
+    pub(crate) encode_mode: EncodeMode,
+    pub(crate) codec: VideoCodec,
+    pub(crate) container: Container,
+    pub(crate) quality: QualitySettings,
+    /// Audio tracks of the recording, none when it has no audio
+    pub(crate) audio_tracks: Vec<AudioTrack>,
     pub cache_count: Mutex<u64>,
     pub(crate) recording_state: Mutex<RecordingState>,
     pub session_name: String,
     pub output_dir: PathBuf,
-    pub cache_dir: PathBuf,
+    pub cache_path: PathBuf,
 }
 
 impl RecordOptions {
     pub fn new(
-        pointer: &'static (dyn Pointer + Send + Sync),
-        frame_rate: u32,
-        resolution: (u32, u32),
+        user_options: &UserOptions,
         session_name: String,
         output_dir: PathBuf,
-        cache_dir: PathBuf,
+        cache_path: PathBuf,
     ) -> Self {
         Self {
-            pointer,
-            frame_rate,
-            resolution,
+            pointer: user_options.pointer,
+            frame_rate: user_options.frame_rate,
+            resolution: user_options.resolution,
+            timelapse_interval: user_options.timelapse_interval,
+            auto_zoom: user_options.auto_zoom,
+            region: user_options.region,
+            encode_mode: user_options.encode_mode,
+            codec: user_options.codec,
+            container: user_options.container,
+            quality: user_options.quality.clone(),
+            audio_tracks: user_options.audio_tracks(),
             cache_count: Mutex::new(0),
             recording_state: Mutex::new(RecordingState::Idle),
             session_name,
             output_dir,
-            cache_dir,
+            cache_path,
         }
     }
 
@@ -117,6 +156,31 @@ impl RecordOptions {
         )
     }
 
+    pub fn is_paused(&self) -> bool {
+        matches!(
+            *self.recording_state.lock().unwrap(),
+            RecordingState::Paused(_)
+        )
+    }
+
+    /// Pauses the recording, keeping the duration recorded so far
+    pub fn pause_recording(&self) {
+        let mut recording_state = self.recording_state.lock().unwrap();
+        if let RecordingState::Recording(instant) = *recording_state {
+            *recording_state = RecordingState::Paused(instant.elapsed());
+        }
+    }
+
+    /// Resumes a paused recording. The start instant is moved forward by the time
+    /// spent paused, so durations and frame timestamps continue where they stopped.
+    pub fn resume_recording(&self) {
+        let mut recording_state = self.recording_state.lock().unwrap();
+        if let RecordingState::Paused(duration) = *recording_state {
+            let now = Instant::now();
+            *recording_state = RecordingState::Recording(now.checked_sub(duration).unwrap_or(now));
+        }
+    }
+
     pub fn is_done_recording(&self) -> bool {
         matches!(
             *self.recording_state.lock().unwrap(),
@@ -139,6 +203,38 @@ impl RecordOptions {
         self.resolution
     }
 
+    pub fn get_timelapse_interval(&self) -> Option<Duration> {
+        self.timelapse_interval
+    }
+
+    pub fn get_auto_zoom(&self) -> Option<ZoomSettings> {
+        self.auto_zoom
+    }
+
+    pub fn get_region(&self) -> Option<CaptureRegion> {
+        self.region
+    }
+
+    pub fn get_encode_mode(&self) -> EncodeMode {
+        self.encode_mode
+    }
+
+    pub fn get_codec(&self) -> VideoCodec {
+        self.codec
+    }
+
+    pub fn get_container(&self) -> Container {
+        self.container
+    }
+
+    pub fn get_quality(&self) -> QualitySettings {
+        self.quality.clone()
+    }
+
+    pub fn get_audio_tracks(&self) -> Vec<AudioTrack> {
+        self.audio_tracks.clone()
+    }
+
     pub fn cache_count(&self) -> u64 {
         *self.cache_count.lock().unwrap()
     }
@@ -153,8 +249,8 @@ impl RecordOptions {
         self.pointer
     }
 
-    pub fn cache_dir(&self) -> &PathBuf {
-        &self.cache_dir
+    pub fn cache_path(&self) -> &PathBuf {
+        &self.cache_path
     }
 
     pub fn output_dir(&self) -> &PathBuf {
@@ -1,315 +1,1259 @@
 use std::{
-    path::PathBuf,
-    sync::{Mutex, OnceLock},
-    time::Duration,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, SyncSender},
+        Arc, Mutex, OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
-use mouse_position::mouse_position;
 use xcap::image::RgbaImage;
 
 use crate::{
-    get_app_cache_dir, get_app_cache_output_dir, log_new_recording, options::RecordingState,
-    user::get_user_options,
+    app_cache_dir,
+    audio::{
+        default_audio_source, default_microphone_source, samples_duration, AudioInput, AudioMix,
+        AudioMixer, AudioSource, AudioSourceFactory, AudioStoreReader, AudioStoreWriter,
+        AudioTrack,
+    },
+    capture::{default_source, CaptureSource, SourceFactory},
+    cursor::{self, CursorSample, CursorTrack, CursorTrackWriter},
+    export::{encode_animation, OutputFormat},
+    frame_store::{FrameStoreReader, FrameStoreWriter},
+    get_app_cache_output_dir, log_new_recording,
+    options::{EncodeMode, RecordingState},
+    user::{get_user_options, UserOptions},
+    video::{Container, EncoderConfig, QualitySettings, VideoCodec, VideoDecoder, VideoEncoder},
+    zoom::AutoZoom,
+    RecordingKind, Result, XlabError,
 };
 
 use super::options::{Pointer, RecordOptions};
 
-static OPTIONS: OnceLock<Mutex<RecordOptions>> = OnceLock::new();
-static RECORD_HANDLE: OnceLock<Mutex<Option<std::thread::JoinHandle<()>>>> = OnceLock::new();
-static SAVE_HANDLE: OnceLock<Mutex<Option<std::thread::JoinHandle<()>>>> = OnceLock::new();
-static SAVE_PROGRESS: OnceLock<Mutex<Option<SaveProgress>>> = OnceLock::new();
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
 
-pub fn get_options() -> &'static Mutex<RecordOptions> {
-    OPTIONS.get_or_init(move || {
-        let user_options = super::user::get_user_options().lock().unwrap();
-        let frame_rate = user_options.frame_rate;
-        let resolution = user_options.resolution;
-        let pointer = user_options.pointer;
-        // these are placeholders and are guaranteed to be replaced by the record function
-        let cache_dir = PathBuf::new();
-        let output_dir = get_app_cache_dir().unwrap().join("recordings");
-        Mutex::new(RecordOptions::new(
-            pointer,
-            frame_rate,
-            resolution,
-            String::new(),
-            output_dir,
-            cache_dir,
-        ))
-    })
+/// Returns the process-wide recorder that backs the free functions of this module
+pub fn default_recorder() -> &'static Recorder {
+    RECORDER.get_or_init(Recorder::new)
 }
 
-pub fn get_record_handle() -> &'static Mutex<Option<std::thread::JoinHandle<()>>> {
-    RECORD_HANDLE.get_or_init(|| Mutex::new(None))
+pub fn get_options() -> &'static Mutex<RecordOptions> {
+    default_recorder().options()
 }
 
-pub(crate) fn get_save_handle() -> &'static Mutex<Option<std::thread::JoinHandle<()>>> {
-    SAVE_HANDLE.get_or_init(|| Mutex::new(None))
+pub fn get_record_handle() -> &'static Mutex<Option<JoinHandle<()>>> {
+    &default_recorder().record_handle
 }
 
 pub fn get_save_progress() -> &'static Mutex<Option<SaveProgress>> {
-    SAVE_PROGRESS.get_or_init(|| Mutex::new(None))
+    &default_recorder().save_progress
 }
 
-pub fn record() {
-    if get_options().lock().unwrap().is_recording() {
-        return;
-    };
-    // Starting the recording here is important for the frontend to immediately start
-    // state updates after this function is called
-    get_options().lock().unwrap().start_recording();
-    let handle = std::thread::spawn(move || {
-        let user_options_lock = get_user_options().lock().unwrap();
-        let pointer = user_options_lock.pointer;
-        let frame_rate = user_options_lock.frame_rate;
-        let resolution = user_options_lock.resolution;
+pub fn record() -> Result<()> {
+    default_recorder().start()
+}
+
+pub fn save_video<F>(format: OutputFormat, save_file_at_loc: F) -> Result<()>
+where
+    F: FnOnce(Box<dyn FnOnce(Option<PathBuf>) + Send + 'static>) + Send + 'static,
+{
+    default_recorder().save(format, save_file_at_loc)
+}
+
+pub fn discard_video() -> Result<()> {
+    default_recorder().discard()
+}
+
+pub fn stop() -> Result<()> {
+    default_recorder().stop()
+}
+
+pub fn pause() -> Result<()> {
+    default_recorder().pause()
+}
+
+pub fn resume() -> Result<()> {
+    default_recorder().resume()
+}
+
+pub fn set_muted(input: AudioInput, muted: bool) {
+    default_recorder().set_muted(input, muted)
+}
+
+pub fn is_muted(input: AudioInput) -> bool {
+    default_recorder().is_muted(input)
+}
+
+/// A recording session. Each recorder owns its options, capture thread, save thread
+/// and save progress, so several recorders can be created, driven and dropped
+/// independently of each other and of the default recorder.
+pub struct Recorder {
+    options: Arc<Mutex<RecordOptions>>,
+    user_options: Option<UserOptions>,
+    source_factory: Arc<SourceFactory>,
+    audio_source_factory: Arc<AudioSourceFactory>,
+    microphone_source_factory: Arc<AudioSourceFactory>,
+    /// Whether each [`AudioInput`] is muted, indexed by the input
+    muted: Arc<[AtomicBool; 2]>,
+    record_handle: Mutex<Option<JoinHandle<()>>>,
+    save_handle: Mutex<Option<JoinHandle<()>>>,
+    save_progress: Arc<Mutex<Option<SaveProgress>>>,
+    error: Arc<Mutex<Option<XlabError>>>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    /// Creates a recorder that picks up the global user options each time it starts
+    pub fn new() -> Self {
+        let user_options = get_user_options().lock().unwrap().clone();
+        Self::with_options(None, &user_options)
+    }
+
+    /// Creates a recorder that always records with the given options, regardless of
+    /// later changes to the global user options
+    pub fn with_user_options(user_options: UserOptions) -> Self {
+        Self::with_options(Some(user_options.clone()), &user_options)
+    }
+
+    fn with_options(user_options: Option<UserOptions>, initial: &UserOptions) -> Self {
+        // these are placeholders and are guaranteed to be replaced by `start`
+        let record_options =
+            RecordOptions::new(initial, String::new(), PathBuf::new(), PathBuf::new());
+        Self {
+            options: Arc::new(Mutex::new(record_options)),
+            user_options,
+            source_factory: Arc::new(default_source),
+            audio_source_factory: Arc::new(default_audio_source),
+            microphone_source_factory: Arc::new(default_microphone_source),
+            muted: Arc::new([AtomicBool::new(false), AtomicBool::new(false)]),
+            record_handle: Mutex::new(None),
+            save_handle: Mutex::new(None),
+            save_progress: Arc::new(Mutex::new(None)),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Records from the sources created by `factory` instead of the first monitor
+    pub fn with_source<F>(mut self, factory: F) -> Self
+    where
+        F: Fn(&UserOptions) -> Result<Box<dyn CaptureSource>> + Send + Sync + 'static,
+    {
+        self.source_factory = Arc::new(factory);
+        self
+    }
+
+    /// Records the system audio from the sources created by `factory` instead of the
+    /// monitor of the default output
+    pub fn with_audio_source<F>(mut self, factory: F) -> Self
+    where
+        F: Fn(&UserOptions) -> Result<Box<dyn AudioSource>> + Send + Sync + 'static,
+    {
+        self.audio_source_factory = Arc::new(factory);
+        self
+    }
+
+    /// Records narration from the sources created by `factory` instead of the
+    /// microphone selected in the options
+    pub fn with_microphone_source<F>(mut self, factory: F) -> Self
+    where
+        F: Fn(&UserOptions) -> Result<Box<dyn AudioSource>> + Send + Sync + 'static,
+    {
+        self.microphone_source_factory = Arc::new(factory);
+        self
+    }
+
+    pub fn options(&self) -> &Mutex<RecordOptions> {
+        &self.options
+    }
+
+    pub fn recording_state(&self) -> RecordingState {
+        self.options.lock().unwrap().recording_state()
+    }
+
+    pub fn save_progress(&self) -> Option<SaveProgress> {
+        *self.save_progress.lock().unwrap()
+    }
+
+    /// Takes the error that ended the last capture or save in the background, if any
+    pub fn take_error(&self) -> Option<XlabError> {
+        self.error.lock().unwrap().take()
+    }
+
+    pub fn start(&self) -> Result<()> {
+        match self.recording_state() {
+            RecordingState::Idle => {}
+            RecordingState::Recording(_) | RecordingState::Paused(_) => {
+                return Err(XlabError::State(
+                    "a recording is already in progress".into(),
+                ))
+            }
+            RecordingState::Done(_) => {
+                return Err(XlabError::State(
+                    "the last recording must be saved or discarded first".into(),
+                ))
+            }
+        }
+
+        // The previous capture thread has already left its loop at this point
+        if let Some(old_handle) = self.record_handle.lock().unwrap().take() {
+            old_handle.join().ok();
+        }
+
+        let user_options = match &self.user_options {
+            Some(user_options) => user_options.clone(),
+            None => get_user_options().lock().unwrap().clone(),
+        };
+        user_options.container.validate(user_options.codec)?;
+        user_options.quality.validate(user_options.codec)?;
+        let audio_inputs: Vec<_> = user_options
+            .audio_inputs()
+            .into_iter()
+            .map(|input| {
+                let factory = match input {
+                    AudioInput::System => Arc::clone(&self.audio_source_factory),
+                    AudioInput::Microphone => Arc::clone(&self.microphone_source_factory),
+                };
+                (input, factory)
+            })
+            .collect();
+        if !audio_inputs.is_empty() {
+            user_options
+                .container
+                .validate_audio(user_options.audio_codec)?;
+        }
         let session_name = generate_random_string(12);
-        let cache_dir = generate_session_cache_dir(&session_name);
-        let output_dir = get_app_cache_output_dir();
+        let cache_path = generate_session_cache_path(&session_name)?;
+        let output_dir = get_app_cache_output_dir()?;
+        let output_path = generate_output_path(
+            &output_dir,
+            &session_name,
+            user_options.container.extension(),
+        );
+        match user_options.encode_mode {
+            EncodeMode::Cached => std::fs::create_dir_all(app_cache_dir()?)?,
+            EncodeMode::Streaming => std::fs::create_dir_all(&output_dir)?,
+        }
+
         let new_record_options = RecordOptions::new(
-            pointer,
-            frame_rate,
-            resolution,
+            &user_options,
             session_name.clone(),
             output_dir,
-            cache_dir.clone(),
+            cache_path.clone(),
         );
-        std::mem::drop(user_options_lock);
-        let record_options_mtx = get_options();
-        *record_options_mtx.lock().unwrap() = new_record_options;
-        if cache_dir.exists() {
-            std::fs::remove_dir_all(&cache_dir).ok();
-        }
-        std::fs::create_dir_all(&cache_dir).ok();
-        const ONE_NANO: u64 = 1_000_000_000;
-        let wait_duration = Duration::from_nanos(ONE_NANO / frame_rate as u64);
-
-        // Calling start recording again will update the start time to the current time
-        // Improves accuracy of the recording duration by nanoseconds (not really needed)
-        // But it's good in case the above code takes a long time to execute
-        let monitor = xcap::Monitor::all().unwrap().into_iter().next().unwrap();
-        get_options().lock().unwrap().start_recording();
-
-        while record_options_mtx.lock().unwrap().is_recording() {
-            let start = std::time::Instant::now();
-
-            // Reduce mutex lock contention by acquiring once per frame
-            let (cache_count, target_resolution) = {
-                let options = record_options_mtx.lock().unwrap();
-                (options.next_cache_count(), options.get_resolution())
-            };
+        // Starting the recording here is important for the frontend to immediately start
+        // state updates after this function is called
+        new_record_options.start_recording();
+        *self.options.lock().unwrap() = new_record_options;
+        self.error.lock().unwrap().take();
 
-            let image_dir = generate_cached_image_path(&cache_dir, &session_name, cache_count);
-            let screen = monitor.capture_image().unwrap();
-            let pointer_position = get_mouse_position();
+        let record_options_mtx = Arc::clone(&self.options);
+        let source_factory = Arc::clone(&self.source_factory);
+        let muted = Arc::clone(&self.muted);
+        let error = Arc::clone(&self.error);
+        let handle = std::thread::spawn(move || {
+            let result = source_factory(&user_options).and_then(|mut source| {
+                let mut sink: Box<dyn FrameSink> = match user_options.encode_mode {
+                    EncodeMode::Cached => Box::new(CacheSink::create(&cache_path)?),
+                    EncodeMode::Streaming => Box::new(StreamingSink::new(
+                        output_path.clone(),
+                        user_options.frame_rate,
+                        user_options.resolution,
+                        encoder_config(
+                            user_options.codec,
+                            user_options.container,
+                            user_options.quality.clone(),
+                            user_options.audio_tracks(),
+                        ),
+                    )),
+                };
+                let mut audio = match audio_inputs.is_empty() {
+                    true => None,
+                    false => Some(AudioCapture::start(
+                        audio_inputs,
+                        &user_options,
+                        Arc::clone(&record_options_mtx),
+                        muted,
+                    )?),
+                };
+                // Cached frames get their pointer when saved, from the cursor track
+                let pointer = match user_options.encode_mode {
+                    EncodeMode::Cached => None,
+                    EncodeMode::Streaming => Some(user_options.pointer),
+                };
+                capture_frames(
+                    &record_options_mtx,
+                    source.as_mut(),
+                    sink.as_mut(),
+                    audio.as_mut(),
+                    pointer,
+                    user_options.frame_rate,
+                )?;
+                if let Some(audio) = audio {
+                    audio.finish(sink.as_mut())?;
+                }
+                sink.finish()
+            });
+            if let Err(err) = result {
+                // A failed capture cannot be saved, so the session is abandoned
+                *record_options_mtx
+                    .lock()
+                    .unwrap()
+                    .recording_state
+                    .lock()
+                    .unwrap() = RecordingState::Idle;
+                std::fs::remove_file(&cache_path).ok();
+                std::fs::remove_file(audio_cache_path(&cache_path)).ok();
+                std::fs::remove_file(cursor::track_path(&cache_path)).ok();
+                std::fs::remove_file(&output_path).ok();
+                error.lock().unwrap().replace(err);
+            }
+        });
+        self.record_handle.lock().unwrap().replace(handle);
+        Ok(())
+    }
 
-            process(
-                image_dir,
-                pointer,
-                screen,
-                pointer_position,
-                target_resolution,
-            );
+    pub fn stop(&self) -> Result<()> {
+        let encode_mode = {
+            let ro = self.options.lock().unwrap();
+            if !ro.is_recording() && !ro.is_paused() {
+                return Err(XlabError::State("there is no recording to stop".into()));
+            }
+            ro.end_recording();
+            ro.get_encode_mode()
+        };
 
-            std::thread::sleep(
-                wait_duration
-                    .checked_sub(start.elapsed())
-                    .unwrap_or_default(),
-            );
+        if encode_mode == EncodeMode::Streaming {
+            // The video is complete once the capture thread has flushed the encoder
+            if let Some(handle) = self.record_handle.lock().unwrap().take() {
+                handle.join().ok();
+            }
+            if let Some(err) = self.take_error() {
+                return Err(err);
+            }
         }
-    });
-    let old_handle = get_record_handle().lock().unwrap().replace(handle);
-    if let Some(old_handle) = old_handle {
-        old_handle.join().unwrap();
+        Ok(())
     }
-}
 
-pub fn save_video<F>(save_file_at_loc: F)
-where
-    F: FnOnce(Box<dyn FnOnce(Option<PathBuf>) + Send + 'static>) + Send + 'static,
-{
-    if !matches!(
-        get_save_progress().lock().unwrap().as_ref(),
-        None | Some(SaveProgress::Done)
-    ) {
-        return;
+    /// Pauses the recording; no frames are captured until it is resumed
+    pub fn pause(&self) -> Result<()> {
+        let options = self.options.lock().unwrap();
+        if !options.is_recording() {
+            return Err(XlabError::State("there is no recording to pause".into()));
+        }
+        options.pause_recording();
+        Ok(())
     }
 
-    let options_lock = get_options().lock().unwrap();
-    if !options_lock.is_done_recording() {
-        return;
-    };
+    pub fn resume(&self) -> Result<()> {
+        let options = self.options.lock().unwrap();
+        if !options.is_paused() {
+            return Err(XlabError::State("the recording is not paused".into()));
+        }
+        options.resume_recording();
+        Ok(())
+    }
 
-    let recording_duration = options_lock.recording_state().duration();
+    /// Mutes or unmutes `input`, also in the middle of a recording. Muted inputs
+    /// record silence, so the audio stays in sync with the video.
+    pub fn set_muted(&self, input: AudioInput, muted: bool) {
+        self.muted[input as usize].store(muted, Ordering::Relaxed);
+    }
 
-    *options_lock.recording_state.lock().unwrap() = RecordingState::Idle;
+    pub fn is_muted(&self, input: AudioInput) -> bool {
+        self.muted[input as usize].load(Ordering::Relaxed)
+    }
 
-    get_save_progress()
-        .lock()
-        .unwrap()
-        .replace(SaveProgress::Initializing);
-
-    let handle = std::thread::spawn(move || {
-        get_record_handle().lock().unwrap().take().map(|u| u.join());
-        let record_options_mtx = get_options();
-        let record_options_lock = record_options_mtx.lock().unwrap();
-        let cache_dir = record_options_lock.cache_dir().clone();
-        let output_dir = record_options_lock.output_dir().clone();
-        let last_idx = record_options_lock.cache_count();
-        let session_name = record_options_lock.session_name.clone();
-        let frame_rate = record_options_lock.get_rate();
-        let resolution = record_options_lock.get_resolution();
-        std::mem::drop(record_options_lock);
-        if !output_dir.exists() {
-            std::fs::create_dir_all(&output_dir).unwrap();
-        }
-        let mut output_path = generate_output_path(&output_dir, &session_name);
-
-        let mut video_encoder = super::video::VideoEncoder::new(
-            output_path.clone(),
-            frame_rate,
-            resolution,
-            Default::default(),
-        )
-        .unwrap();
-        for cache_count in 1..=last_idx {
-            get_save_progress()
-                .lock()
-                .unwrap()
-                .replace(SaveProgress::Saving(cache_count, last_idx));
-            let image_path = generate_cached_image_path(&cache_dir, &session_name, cache_count);
-            let image = xcap::image::open(image_path).unwrap().into_rgba8();
-            video_encoder.append_image(image, cache_count).unwrap();
-        }
-
-        get_save_progress()
+    /// Saves the finished recording as `format`. `save_file_at_loc` is called once the
+    /// output is ready, with a function that moves it to the chosen location and logs it.
+    /// Cached recordings get the pointer that is chosen at this point, streamed ones
+    /// keep the one they were recorded with.
+    pub fn save<F>(&self, format: OutputFormat, save_file_at_loc: F) -> Result<()>
+    where
+        F: FnOnce(Box<dyn FnOnce(Option<PathBuf>) + Send + 'static>) + Send + 'static,
+    {
+        format.validate()?;
+        if !matches!(
+            self.save_progress.lock().unwrap().as_ref(),
+            None | Some(SaveProgress::Done)
+        ) {
+            return Err(XlabError::State(
+                "another recording is still being saved".into(),
+            ));
+        }
+
+        // Everything the save thread needs is read now, so that a new recording
+        // started while saving cannot change it
+        let pointer = match &self.user_options {
+            Some(user_options) => user_options.pointer,
+            None => get_user_options().lock().unwrap().pointer,
+        };
+        let options_lock = self.options.lock().unwrap();
+        if !options_lock.is_done_recording() {
+            return Err(XlabError::State(
+                "there is no finished recording to save".into(),
+            ));
+        };
+        // A timelapse lasts as long as its frames play, not as long as it was recorded
+        let recording_duration = match options_lock.get_timelapse_interval() {
+            Some(_) => Duration::from_secs_f64(
+                options_lock.cache_count() as f64 / options_lock.get_rate() as f64,
+            ),
+            None => options_lock.recording_state().duration(),
+        };
+        let session = FinishedSession {
+            encode_mode: options_lock.get_encode_mode(),
+            cache_path: options_lock.cache_path().clone(),
+            output_dir: options_lock.output_dir().clone(),
+            session_name: options_lock.session_name.clone(),
+            frame_rate: options_lock.get_rate(),
+            resolution: options_lock.get_resolution(),
+            codec: options_lock.get_codec(),
+            container: options_lock.get_container(),
+            quality: options_lock.get_quality(),
+            audio_tracks: options_lock.get_audio_tracks(),
+            frame_count: options_lock.cache_count(),
+            pointer,
+        };
+        *options_lock.recording_state.lock().unwrap() = RecordingState::Idle;
+        std::mem::drop(options_lock);
+
+        self.save_progress
             .lock()
             .unwrap()
-            .replace(SaveProgress::Finalizing);
+            .replace(SaveProgress::Initializing);
 
-        video_encoder.finalize().unwrap();
+        let record_handle = self.record_handle.lock().unwrap().take();
+        let save_progress = Arc::clone(&self.save_progress);
+        let error = Arc::clone(&self.error);
+        let handle = std::thread::spawn(move || {
+            if let Some(record_handle) = record_handle {
+                record_handle.join().ok();
+            }
 
-        if cache_dir.exists() {
-            std::fs::remove_dir_all(cache_dir).unwrap();
+            let kind = match format {
+                OutputFormat::Video => RecordingKind::Video,
+                _ => RecordingKind::Animation,
+            };
+            let result = match (format, session.encode_mode) {
+                (OutputFormat::Video, EncodeMode::Cached) => {
+                    encode_cached_frames(&session, &save_progress)
+                        .map(|output_path| (output_path, session.resolution))
+                }
+                // Streamed recordings were encoded while capturing
+                (OutputFormat::Video, EncodeMode::Streaming) => Ok((
+                    generate_output_path(
+                        &session.output_dir,
+                        &session.session_name,
+                        session.container.extension(),
+                    ),
+                    session.resolution,
+                )),
+                (format, _) => export_animation(&session, &format, &save_progress),
+            };
+            if session.cache_path.exists() {
+                std::fs::remove_file(&session.cache_path).ok();
+            }
+            std::fs::remove_file(audio_cache_path(&session.cache_path)).ok();
+            let cursor_cache_path = cursor::track_path(&session.cache_path);
+            let (default_output_path, resolution) = match result {
+                Ok(output) => output,
+                Err(err) => {
+                    std::fs::remove_file(&cursor_cache_path).ok();
+                    save_progress.lock().unwrap().take();
+                    error.lock().unwrap().replace(err);
+                    return;
+                }
+            };
+            match kind {
+                // The cursor track stays with videos, for effects applied later
+                RecordingKind::Video => {
+                    move_file(
+                        &cursor_cache_path,
+                        &cursor::track_path(&default_output_path),
+                    )
+                    .ok();
+                }
+                // Animated images can't be edited, so their track isn't needed
+                RecordingKind::Animation => {
+                    std::fs::remove_file(&cursor_cache_path).ok();
+                }
+            }
+
+            let save_fn = Box::new(move |save_path: Option<PathBuf>| {
+                let result = finish_save(
+                    default_output_path,
+                    save_path,
+                    recording_duration,
+                    resolution,
+                    kind,
+                );
+                match result {
+                    Ok(()) => {
+                        save_progress.lock().unwrap().replace(SaveProgress::Done);
+                    }
+                    Err(err) => {
+                        save_progress.lock().unwrap().take();
+                        error.lock().unwrap().replace(err);
+                    }
+                }
+            });
+
+            save_file_at_loc(save_fn)
+        });
+        if let Some(old_handle) = self.save_handle.lock().unwrap().replace(handle) {
+            old_handle.join().ok();
         }
+        Ok(())
+    }
 
-        let save_fn = Box::new(move |save_path| {
-            if let Some(save_path) = save_path {
-                output_path = save_path;
-                move_recording(&output_path);
+    pub fn discard(&self) -> Result<()> {
+        let options = self.options.lock().unwrap();
+        if !matches!(options.recording_state(), RecordingState::Done(_)) {
+            return Err(XlabError::State(
+                "there is no finished recording to discard".into(),
+            ));
+        }
+        *options.recording_state.lock().unwrap() = RecordingState::Idle;
+        let cache_path = options.cache_path().clone();
+        let output_path = match options.get_encode_mode() {
+            EncodeMode::Cached => None,
+            EncodeMode::Streaming => Some(generate_output_path(
+                options.output_dir(),
+                &options.session_name,
+                options.get_container().extension(),
+            )),
+        };
+        std::thread::spawn(move || {
+            std::fs::remove_file(audio_cache_path(&cache_path)).ok();
+            std::fs::remove_file(cursor::track_path(&cache_path)).ok();
+            if cache_path.exists() {
+                std::fs::remove_file(cache_path).ok();
+            }
+            if let Some(output_path) = output_path {
+                std::fs::remove_file(output_path).ok();
             }
-            log_new_recording(output_path, recording_duration.as_secs());
+        });
+        Ok(())
+    }
+
+    /// Blocks until the capture and save threads of this recorder have finished
+    pub fn wait(&self) {
+        if let Some(handle) = self.record_handle.lock().unwrap().take() {
+            handle.join().ok();
+        }
+        if let Some(handle) = self.save_handle.lock().unwrap().take() {
+            handle.join().ok();
+        }
+    }
+
+    /// Moves the last saved recording of this recorder to `new_path`
+    pub fn move_recording(&self, new_path: &PathBuf) -> Result<()> {
+        let output_path = {
+            let ro = self.options.lock().unwrap();
+            generate_output_path(
+                ro.output_dir(),
+                &ro.session_name,
+                ro.get_container().extension(),
+            )
+        };
+        relocate_recording(&output_path, new_path)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop().ok();
+        self.wait();
+    }
+}
+
+/// Snapshot of a finished recording that is waiting to be saved
+struct FinishedSession {
+    encode_mode: EncodeMode,
+    cache_path: PathBuf,
+    output_dir: PathBuf,
+    session_name: String,
+    frame_rate: u32,
+    resolution: (u32, u32),
+    codec: VideoCodec,
+    container: Container,
+    quality: QualitySettings,
+    audio_tracks: Vec<AudioTrack>,
+    frame_count: u64,
+    /// Pointer drawn on cached frames, the one chosen when saving rather than when
+    /// recording. The system pointer is drawn with the shape the cursor has now.
+    pointer: &'static (dyn Pointer + Send + Sync),
+}
+
+/// Destination of the processed frames of a recording
+trait FrameSink {
+    /// Writes a frame captured `timestamp` after the recording started
+    fn write_frame(&mut self, timestamp: Duration, frame: RgbaImage) -> Result<()>;
+
+    /// Writes interleaved audio samples of `track` captured `timestamp` after the
+    /// recording started
+    fn write_audio(&mut self, track: usize, timestamp: Duration, samples: Vec<f32>) -> Result<()>;
+
+    /// Called once after the last frame was written
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Caches every frame in a frame store, and the audio in an audio store next to it,
+/// to be encoded when the recording is saved
+struct CacheSink {
+    writer: FrameStoreWriter,
+    audio_path: PathBuf,
+    audio_writer: Option<AudioStoreWriter>,
+}
+
+impl CacheSink {
+    fn create(cache_path: &Path) -> Result<Self> {
+        Ok(Self {
+            writer: FrameStoreWriter::create(cache_path)?,
+            audio_path: audio_cache_path(cache_path),
+            audio_writer: None,
+        })
+    }
+}
+
+impl FrameSink for CacheSink {
+    fn write_frame(&mut self, timestamp: Duration, frame: RgbaImage) -> Result<()> {
+        self.writer.append(timestamp, &frame)
+    }
+
+    fn write_audio(&mut self, track: usize, timestamp: Duration, samples: Vec<f32>) -> Result<()> {
+        let audio_writer = match self.audio_writer.as_mut() {
+            Some(audio_writer) => audio_writer,
+            None => self
+                .audio_writer
+                .insert(AudioStoreWriter::create(&self.audio_path)?),
+        };
+        audio_writer.append(track, timestamp, &samples)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        if let Some(audio_writer) = self.audio_writer {
+            audio_writer.finish()?;
+        }
+        self.writer.finish()
+    }
+}
+
+/// What the capture thread hands to the encoder thread
+enum Captured {
+    Frame(Duration, RgbaImage),
+    Audio(usize, Duration, Vec<f32>),
+}
+
+/// Sends frames through a bounded channel into an encoder running on its own thread
+struct StreamingSink {
+    sender: Option<SyncSender<Captured>>,
+    encoder_handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl StreamingSink {
+    /// Number of frames and audio chunks that may wait for the encoder before
+    /// capturing blocks
+    const QUEUE_LENGTH: usize = 8;
 
-            get_save_progress()
-                .lock()
-                .unwrap()
-                .replace(SaveProgress::Done);
+    fn new(
+        output_path: PathBuf,
+        frame_rate: u32,
+        resolution: (u32, u32),
+        config: EncoderConfig,
+    ) -> Self {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(Self::QUEUE_LENGTH);
+        let encoder_handle = std::thread::spawn(move || -> Result<()> {
+            let mut video_encoder = VideoEncoder::new(output_path, frame_rate, resolution, config)?;
+            for captured in receiver {
+                match captured {
+                    Captured::Frame(timestamp, frame) => {
+                        video_encoder.append_image(frame, timestamp)?
+                    }
+                    Captured::Audio(track, timestamp, samples) => {
+                        video_encoder.append_audio(track, &samples, timestamp)?
+                    }
+                }
+            }
+            video_encoder.finalize()
         });
+        Self {
+            sender: Some(sender),
+            encoder_handle: Some(encoder_handle),
+        }
+    }
+
+    /// Closes the channel and waits for the encoder to finalize the video
+    fn join_encoder(&mut self) -> Result<()> {
+        self.sender.take();
+        match self.encoder_handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| XlabError::Encode("the encoder thread panicked".into()))?,
+            None => Ok(()),
+        }
+    }
+
+    /// Hands `captured` to the encoder thread
+    fn send(&mut self, captured: Captured) -> Result<()> {
+        let sent = match &self.sender {
+            Some(sender) => sender.send(captured).is_ok(),
+            None => false,
+        };
+        if !sent {
+            // The encoder only hangs up after failing, its error explains why
+            self.join_encoder()?;
+            return Err(XlabError::Encode("the encoder stopped unexpectedly".into()));
+        }
+        Ok(())
+    }
+}
+
+impl FrameSink for StreamingSink {
+    fn write_frame(&mut self, timestamp: Duration, frame: RgbaImage) -> Result<()> {
+        self.send(Captured::Frame(timestamp, frame))
+    }
+
+    fn write_audio(&mut self, track: usize, timestamp: Duration, samples: Vec<f32>) -> Result<()> {
+        self.send(Captured::Audio(track, timestamp, samples))
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.join_encoder()
+    }
+}
+
+fn capture_frames(
+    record_options_mtx: &Mutex<RecordOptions>,
+    source: &mut dyn CaptureSource,
+    sink: &mut dyn FrameSink,
+    mut audio: Option<&mut AudioCapture>,
+    pointer: Option<&'static (dyn Pointer + Send + Sync)>,
+    frame_rate: u32,
+) -> Result<()> {
+    const ONE_NANO: u64 = 1_000_000_000;
+    let wait_duration = Duration::from_nanos(ONE_NANO / frame_rate as u64);
+
+    // Calling start recording again will update the start time to the current time
+    // Improves accuracy of the recording duration by nanoseconds (not really needed)
+    // But it's good in case the source took a long time to set up
+    let (region, timelapse_interval, auto_zoom, track_path, resolution) = {
+        let options = record_options_mtx.lock().unwrap();
+        if options.is_recording() {
+            options.start_recording();
+        }
+        (
+            options.get_region(),
+            options.get_timelapse_interval(),
+            options.get_auto_zoom(),
+            cursor::track_path(options.cache_path()),
+            options.get_resolution(),
+        )
+    };
+    // A timelapse captures a frame once per interval, but plays them at the frame rate
+    let capture_interval = timelapse_interval.unwrap_or(wait_duration);
+    if let Some(region) = region {
+        region.validate(source.dimensions())?;
+    }
+    let dimensions = region.map_or(source.dimensions(), |region| region.dimensions());
+    let mut zoom = auto_zoom.map(|settings| AutoZoom::new(settings, dimensions));
+    let mut cursor_track = CursorTrackWriter::create(&track_path, resolution)?;
+
+    loop {
+        let start = Instant::now();
+        if let Some(audio) = audio.as_deref_mut() {
+            audio.drain(sink)?;
+        }
+
+        // Reduce mutex lock contention by acquiring once per frame
+        let (recording_state, frame_count, target_resolution) = {
+            let options = record_options_mtx.lock().unwrap();
+            let recording_state = options.recording_state();
+            let frame_count = match recording_state {
+                RecordingState::Recording(_) => options.next_cache_count(),
+                _ => options.cache_count(),
+            };
+            (recording_state, frame_count, options.get_resolution())
+        };
+        // Frames are timestamped when their capture starts. The start of a resumed
+        // recording excludes the pauses, so timestamps continue where they stopped.
+        // Timelapse frames follow each other at the frame rate instead.
+        let timestamp = match recording_state {
+            RecordingState::Recording(_) if timelapse_interval.is_some() => {
+                Duration::from_nanos((frame_count - 1) * ONE_NANO / frame_rate as u64)
+            }
+            RecordingState::Recording(started) => start.saturating_duration_since(started),
+            RecordingState::Paused(_) => {
+                std::thread::sleep(wait_duration);
+                continue;
+            }
+            RecordingState::Idle | RecordingState::Done(_) => break,
+        };
+
+        let mut screen = source.next_frame()?;
+        let mut pointer_position = source.cursor_position();
+        let pressed = source.cursor_pressed();
+        if let Some(region) = region {
+            screen = region.crop(&screen);
+            pointer_position = pointer_position.and_then(|position| region.translate(position));
+        }
+        // The part of the frame that is zoomed in on is scaled up to the resolution
+        if let Some(zoom) = &mut zoom {
+            let view = zoom.update(timestamp, pointer_position, pressed);
+            screen = view.crop(&screen);
+            pointer_position = pointer_position.and_then(|position| view.translate(position));
+        }
+
+        let screen_dimensions = screen.dimensions();
+        let mut frame = process(screen, target_resolution)?;
+        // The track is in pixels of the frames as they are stored
+        let position = pointer_position
+            .map(|position| scale_position(position, screen_dimensions, frame.dimensions()));
+        cursor_track.append(&CursorSample {
+            timestamp,
+            position,
+            pressed,
+        })?;
+        if let (Some(pointer), Some(position)) = (pointer, position) {
+            pointer.resolve(&mut frame, position);
+        }
+        sink.write_frame(timestamp, frame)?;
 
-        save_file_at_loc(save_fn)
-    });
-    get_save_handle()
+        sleep_while_recording(
+            record_options_mtx,
+            capture_interval
+                .checked_sub(start.elapsed())
+                .unwrap_or_default(),
+        );
+    }
+    cursor_track.finish()
+}
+
+/// Sleeps for `duration`, waking up early once the recording is paused or stopped so
+/// that long timelapse intervals don't hold up either
+fn sleep_while_recording(record_options_mtx: &Mutex<RecordOptions>, duration: Duration) {
+    const MAX_SLEEP: Duration = Duration::from_millis(100);
+    let deadline = Instant::now() + duration;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining <= MAX_SLEEP {
+            std::thread::sleep(remaining);
+            return;
+        }
+        std::thread::sleep(MAX_SLEEP);
+        if !record_options_mtx.lock().unwrap().is_recording() {
+            return;
+        }
+    }
+}
+
+/// Audio captured on a thread per input, handed to the sink by the capture thread
+struct AudioCapture {
+    /// Samples with the index of the input they were captured from
+    receiver: Receiver<(usize, Duration, Vec<f32>)>,
+    handles: Vec<JoinHandle<Result<()>>>,
+    /// Mixes the inputs into the first track, `None` gives each input its own track
+    mixer: Option<AudioMixer>,
+}
+
+impl AudioCapture {
+    /// Starts capturing from the source each input's factory creates, returning once
+    /// all of them are open
+    fn start(
+        inputs: Vec<(AudioInput, Arc<AudioSourceFactory>)>,
+        user_options: &UserOptions,
+        record_options_mtx: Arc<Mutex<RecordOptions>>,
+        muted: Arc<[AtomicBool; 2]>,
+    ) -> Result<Self> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mixer = (user_options.audio_mix == AudioMix::Mixed && inputs.len() > 1)
+            .then(|| AudioMixer::new(inputs.len()));
+        let mut handles = Vec::with_capacity(inputs.len());
+        for (index, (input, factory)) in inputs.into_iter().enumerate() {
+            let (ready_sender, ready_receiver) = std::sync::mpsc::sync_channel(1);
+            let sender = sender.clone();
+            let user_options = user_options.clone();
+            let record_options_mtx = Arc::clone(&record_options_mtx);
+            let muted = Arc::clone(&muted);
+            let handle = std::thread::spawn(move || -> Result<()> {
+                let mut source = match factory(&user_options) {
+                    Ok(source) => source,
+                    Err(err) => {
+                        ready_sender.send(Err(err)).ok();
+                        return Ok(());
+                    }
+                };
+                ready_sender.send(Ok(())).ok();
+                capture_audio(
+                    &record_options_mtx,
+                    source.as_mut(),
+                    user_options.audio_gain(input),
+                    &muted[input as usize],
+                    index,
+                    &sender,
+                )
+            });
+            // Threads that were already started stop once the receiver is dropped
+            match ready_receiver.recv() {
+                Ok(Ok(())) => handles.push(handle),
+                Ok(Err(err)) => return Err(err),
+                Err(_) => return Err(XlabError::Capture("the audio thread panicked".into())),
+            }
+        }
+        Ok(Self {
+            receiver,
+            handles,
+            mixer,
+        })
+    }
+
+    /// Hands samples of the input at `index` to `sink`, through the mixer if there is one
+    fn write(
+        &mut self,
+        sink: &mut dyn FrameSink,
+        index: usize,
+        timestamp: Duration,
+        samples: Vec<f32>,
+    ) -> Result<()> {
+        match &mut self.mixer {
+            Some(mixer) => match mixer.push(index, timestamp, &samples) {
+                Some((timestamp, mixed)) => sink.write_audio(0, timestamp, mixed),
+                None => Ok(()),
+            },
+            None => sink.write_audio(index, timestamp, samples),
+        }
+    }
+
+    /// Hands the audio captured so far to `sink`
+    fn drain(&mut self, sink: &mut dyn FrameSink) -> Result<()> {
+        while let Ok((index, timestamp, samples)) = self.receiver.try_recv() {
+            self.write(sink, index, timestamp, samples)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the audio threads to stop with the recording and hands the rest of
+    /// the audio to `sink`
+    fn finish(mut self, sink: &mut dyn FrameSink) -> Result<()> {
+        let mut result = Ok(());
+        for handle in std::mem::take(&mut self.handles) {
+            let joined = handle
+                .join()
+                .map_err(|_| XlabError::Capture("the audio thread panicked".into()))?;
+            result = result.and(joined);
+        }
+        self.drain(sink)?;
+        if let Some((timestamp, mixed)) = self.mixer.as_mut().and_then(AudioMixer::finish) {
+            sink.write_audio(0, timestamp, mixed)?;
+        }
+        result
+    }
+}
+
+/// Reads audio from `source` until the recording ends, timestamped like the frames,
+/// and sends it with `index`. Audio captured while the recording is paused is dropped.
+fn capture_audio(
+    record_options_mtx: &Mutex<RecordOptions>,
+    source: &mut dyn AudioSource,
+    gain: f32,
+    muted: &AtomicBool,
+    index: usize,
+    sender: &Sender<(usize, Duration, Vec<f32>)>,
+) -> Result<()> {
+    loop {
+        let mut samples = source.next_samples()?;
+        let captured = Instant::now();
+        let recording_state = record_options_mtx.lock().unwrap().recording_state();
+        let timestamp = match recording_state {
+            // The samples were captured over the time it takes to play them
+            RecordingState::Recording(started) => captured
+                .saturating_duration_since(started)
+                .saturating_sub(samples_duration(samples.len())),
+            RecordingState::Paused(_) => continue,
+            RecordingState::Idle | RecordingState::Done(_) => break,
+        };
+        if muted.load(Ordering::Relaxed) {
+            samples.fill(0.0);
+        } else if gain != 1.0 {
+            samples.iter_mut().for_each(|sample| *sample *= gain);
+        }
+        if sender.send((index, timestamp, samples)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Encodes the cached frames of a session and returns the path of the encoded video
+fn encode_cached_frames(
+    session: &FinishedSession,
+    save_progress: &Mutex<Option<SaveProgress>>,
+) -> Result<PathBuf> {
+    if !session.output_dir.exists() {
+        std::fs::create_dir_all(&session.output_dir)?;
+    }
+    let output_path = generate_output_path(
+        &session.output_dir,
+        &session.session_name,
+        session.container.extension(),
+    );
+
+    let mut video_encoder = VideoEncoder::new(
+        output_path.clone(),
+        session.frame_rate,
+        session.resolution,
+        encoder_config(
+            session.codec,
+            session.container,
+            session.quality.clone(),
+            session.audio_tracks.clone(),
+        ),
+    )?;
+    let mut frames = FrameStoreReader::open(&session.cache_path)?;
+    let cursor_track = CursorTrack::read(&cursor::track_path(&session.cache_path))?;
+    // Recordings with audio have no audio store if no audio was captured
+    let audio_path = audio_cache_path(&session.cache_path);
+    let mut audio = match !session.audio_tracks.is_empty() && audio_path.exists() {
+        true => Some(AudioStoreReader::open(&audio_path)?.peekable()),
+        false => None,
+    };
+    let last_idx = frames.len() as u64;
+    for (cache_count, frame) in (1..=last_idx).zip(frames.by_ref()) {
+        save_progress
+            .lock()
+            .unwrap()
+            .replace(SaveProgress::Saving(cache_count, last_idx));
+        let (timestamp, mut image) = frame?;
+        draw_pointer(session.pointer, &cursor_track, timestamp, &mut image);
+        if let Some(audio) = &mut audio {
+            append_cached_audio(&mut video_encoder, audio, Some(timestamp))?;
+        }
+        video_encoder.append_image(image, timestamp)?;
+    }
+    if let Some(audio) = &mut audio {
+        append_cached_audio(&mut video_encoder, audio, None)?;
+    }
+
+    save_progress
         .lock()
         .unwrap()
-        .replace(handle)
-        .map(|v| v.join());
+        .replace(SaveProgress::Finalizing);
+
+    video_encoder.finalize()?;
+    Ok(output_path)
+}
+
+/// Appends the cached audio captured up to `until` to `video_encoder`, or all of the
+/// remaining audio without a limit
+fn append_cached_audio(
+    video_encoder: &mut VideoEncoder,
+    audio: &mut Peekable<AudioStoreReader>,
+    until: Option<Duration>,
+) -> Result<()> {
+    while let Some(chunk) = audio.next_if(|chunk| match (chunk, until) {
+        (Ok((_, timestamp, _)), Some(until)) => *timestamp <= until,
+        _ => true,
+    }) {
+        let (track, timestamp, samples) = chunk?;
+        video_encoder.append_audio(track, &samples, timestamp)?;
+    }
+    Ok(())
 }
 
-pub fn discard_video() {
-    let options = get_options().lock().unwrap();
-    if !matches!(options.recording_state(), RecordingState::Done(_)) {
-        return;
+/// Exports a finished session as an animated image and returns its path and dimensions
+fn export_animation(
+    session: &FinishedSession,
+    format: &OutputFormat,
+    save_progress: &Mutex<Option<SaveProgress>>,
+) -> Result<(PathBuf, (u32, u32))> {
+    if !session.output_dir.exists() {
+        std::fs::create_dir_all(&session.output_dir)?;
     }
-    *options.recording_state.lock().unwrap() = RecordingState::Idle;
-    let cache_dir = options.cache_dir().clone();
-    std::thread::spawn(move || {
-        if cache_dir.exists() {
-            std::fs::remove_dir_all(cache_dir).ok();
+    let output_path = generate_output_path(
+        &session.output_dir,
+        &session.session_name,
+        format.extension(session.container),
+    );
+    let on_frame = |cache_count| {
+        save_progress
+            .lock()
+            .unwrap()
+            .replace(SaveProgress::Saving(cache_count, session.frame_count));
+    };
+
+    let dimensions = match session.encode_mode {
+        EncodeMode::Cached => {
+            let cursor_track = &CursorTrack::read(&cursor::track_path(&session.cache_path))?;
+            let pointer = session.pointer;
+            encode_animation(
+                &output_path,
+                format,
+                move || {
+                    Ok(
+                        FrameStoreReader::open(&session.cache_path)?.map(move |frame| {
+                            let (timestamp, mut image) = frame?;
+                            draw_pointer(pointer, cursor_track, timestamp, &mut image);
+                            Ok((timestamp, image))
+                        }),
+                    )
+                },
+                on_frame,
+            )
+        }
+        EncodeMode::Streaming => {
+            // The animation is made from the streamed video, which isn't needed afterwards
+            let video_path = generate_output_path(
+                &session.output_dir,
+                &session.session_name,
+                session.container.extension(),
+            );
+            let dimensions = encode_animation(
+                &output_path,
+                format,
+                || VideoDecoder::open(&video_path),
+                on_frame,
+            );
+            std::fs::remove_file(&video_path).ok();
+            dimensions
         }
-    });
+    }?;
+    Ok((output_path, dimensions))
 }
 
-pub fn stop() {
-    let mut ro = get_options().lock().unwrap();
-    let video_duration = ro.end_recording().unwrap();
-    if video_duration.as_secs() > 10 {
-        let corrected_frame_rate = ro.cache_count() / video_duration.as_secs();
-        ro.frame_rate = corrected_frame_rate as u32;
+fn encoder_config(
+    codec: VideoCodec,
+    container: Container,
+    quality: QualitySettings,
+    audio: Vec<AudioTrack>,
+) -> EncoderConfig {
+    EncoderConfig {
+        codec,
+        container,
+        quality,
+        audio,
+        ..Default::default()
     }
 }
 
-fn process(
-    image_path: PathBuf,
-    pointer: &'static dyn Pointer,
-    mut screen: RgbaImage,
-    pointer_position: (u32, u32),
-    target_resolution: (u32, u32),
+/// Moves the encoded video to the location chosen by the user and logs it
+fn finish_save(
+    default_output_path: PathBuf,
+    save_path: Option<PathBuf>,
+    recording_duration: Duration,
+    resolution: (u32, u32),
+    kind: RecordingKind,
+) -> Result<()> {
+    let output_path = match save_path {
+        Some(save_path) => {
+            relocate_recording(&default_output_path, &save_path)?;
+            save_path
+        }
+        None => default_output_path,
+    };
+    log_new_recording(output_path, recording_duration.as_secs(), resolution, kind)
+}
+
+/// Draws `pointer` on a cached frame where the cursor was when it was captured
+fn draw_pointer(
+    pointer: &(dyn Pointer + Send + Sync),
+    cursor_track: &CursorTrack,
+    timestamp: Duration,
+    frame: &mut RgbaImage,
 ) {
-    pointer.resolve(&mut screen, pointer_position);
+    if let Some(position) = cursor_track
+        .sample_at(timestamp)
+        .and_then(|sample| sample.position)
+    {
+        pointer.resolve(frame, position);
+    }
+}
 
+/// Where `position` in a frame of `from` ends up once it is scaled to `to`
+fn scale_position(position: (u32, u32), from: (u32, u32), to: (u32, u32)) -> (u32, u32) {
+    let scale = |value: u32, from: u32, to: u32| match from {
+        0 => 0,
+        _ => ((value as u64 * to as u64 / from as u64) as u32).min(to.saturating_sub(1)),
+    };
+    (
+        scale(position.0, from.0, to.0),
+        scale(position.1, from.1, to.1),
+    )
+}
+
+fn process(mut screen: RgbaImage, target_resolution: (u32, u32)) -> Result<RgbaImage> {
     // Resize image during recording to optimize release stage
     let current_dimensions = screen.dimensions();
     if current_dimensions != target_resolution {
-        crate::resize_image(&mut screen, target_resolution);
+        crate::resize_image(&mut screen, target_resolution)?;
     }
 
-    screen.save(image_path).ok();
-}
-
-fn get_mouse_position() -> (u32, u32) {
-    match mouse_position::Mouse::get_mouse_position() {
-        mouse_position::Mouse::Position { x, y } => (x as u32, y as u32),
-        mouse_position::Mouse::Error => (0, 0),
-    }
+    Ok(screen)
 }
 
 pub fn generate_random_string(length: usize) -> String {
     use rand::distr::Alphanumeric;
     use rand::{rng, Rng};
 
-    let bytes = rng().sample_iter(&Alphanumeric).take(length).collect();
-    String::from_utf8(bytes).unwrap()
+    rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
 }
 
-fn generate_cached_image_path(
-    cache_dir: &PathBuf,
-    session_name: &str,
-    cache_count: u64,
-) -> PathBuf {
-    cache_dir.join(format!("{session_name}_{:07}.png", cache_count))
+fn generate_session_cache_path(session_name: &str) -> Result<PathBuf> {
+    Ok(app_cache_dir()?.join(format!("cache_{session_name}.frames")))
 }
 
-fn generate_session_cache_dir(session_name: &str) -> PathBuf {
-    get_app_cache_dir()
-        .unwrap()
-        .join(format!("cache_{session_name}"))
+/// Path of the audio store kept next to the frame store at `cache_path`
+fn audio_cache_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_extension("audio")
 }
 
-fn generate_output_path(output_dir: &PathBuf, session_name: &str) -> PathBuf {
-    output_dir.join(format!("__{session_name}__.mp4"))
+fn generate_output_path(output_dir: &PathBuf, session_name: &str, extension: &str) -> PathBuf {
+    output_dir.join(format!("__{session_name}__.{extension}"))
 }
 
-pub fn move_recording(new_path: &PathBuf) {
-    let session_name = get_options().lock().unwrap().session_name.clone();
-    let output_path = get_options()
-        .lock()
-        .map(|ro| generate_output_path(ro.output_dir(), &session_name))
-        .unwrap();
-    if &output_path == new_path {
-        return;
+pub fn move_recording(new_path: &PathBuf) -> Result<()> {
+    default_recorder().move_recording(new_path)
+}
+
+fn relocate_recording(output_path: &PathBuf, new_path: &PathBuf) -> Result<()> {
+    if output_path == new_path {
+        return Ok(());
     };
-    if !new_path.exists() {
-        std::fs::create_dir_all(new_path.parent().unwrap()).unwrap();
+    if let Some(parent) = new_path.parent() {
+        std::fs::create_dir_all(parent)?;
     }
-    std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .read(true)
-        .open(&new_path)
-        .unwrap();
-    if let Err(_) = std::fs::rename(&output_path, &new_path) {
-        std::fs::copy(output_path, new_path).ok();
+    move_file(output_path, new_path)?;
+    // The cursor track moves along with the recording
+    let track_path = cursor::track_path(output_path);
+    let new_track_path = cursor::track_path(new_path);
+    match track_path.exists() {
+        true => move_file(&track_path, &new_track_path)?,
+        // The track of a recording that was replaced doesn't fit this one
+        false => {
+            std::fs::remove_file(&new_track_path).ok();
+        }
     }
+    Ok(())
+}
+
+/// Moves a file, copying it where it can't be renamed, such as to another drive
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from).ok();
+    }
+    Ok(())
 }
 
 #[derive(serde::Serialize, Clone, Copy)]
@@ -319,3 +1263,22 @@ pub enum SaveProgress {
     Finalizing,
     Done,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_path_has_container_extension() {
+        let output_dir = PathBuf::from("recordings");
+        for container in Container::ALL {
+            let path = generate_output_path(&output_dir, "session", container.extension());
+            assert_eq!(
+                path.file_name().unwrap(),
+                format!("__session__.{}", container.extension()).as_str()
+            );
+            let extension = path.extension().unwrap().to_str().unwrap();
+            assert_eq!(Container::from_extension(extension), Some(container));
+        }
+    }
+}
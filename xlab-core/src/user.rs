@@ -1,17 +1,53 @@
 use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
 use xcap::image::{Rgba, RgbaImage};
 
-use super::options::{InvisiblePointer, Pointer, SolidPointer, SystemPointer};
+use super::options::{EncodeMode, InvisiblePointer, Pointer, SolidPointer, SystemPointer};
+use crate::{
+    audio::{available_microphones, AudioCodec, AudioInput, AudioMix, AudioTrack, MAX_GAIN},
+    capture::CaptureRegion,
+    video::{Container, QualityPreset, QualitySettings, VideoCodec},
+    zoom::ZoomSettings,
+    Result, XlabError,
+};
 
 static OPTIONS: OnceLock<Mutex<UserOptions>> = OnceLock::new();
 
 static POINTERS: OnceLock<Vec<Box<dyn Pointer + Send + Sync>>> = OnceLock::new();
 
+#[derive(Clone)]
 pub struct UserOptions {
     pub pointer: &'static (dyn Pointer + Send + Sync),
     pub frame_rate: u32,
     pub resolution: (u32, u32),
+    /// Captures a frame once per interval and plays the frames at `frame_rate`, so the
+    /// recording runs faster than real time. `None` records in real time.
+    pub timelapse_interval: Option<Duration>,
+    /// Zooms in on the cursor while recording, `None` records the whole frame
+    pub auto_zoom: Option<ZoomSettings>,
+    /// Id of the monitor to record, `None` records the primary monitor
+    pub monitor: Option<u32>,
+    /// Id of a window to record instead of the monitor
+    pub window: Option<u32>,
+    /// Part of the monitor or window to record, `None` records all of it
+    pub region: Option<CaptureRegion>,
+    pub encode_mode: EncodeMode,
+    pub codec: VideoCodec,
+    pub container: Container,
+    pub quality: QualitySettings,
+    /// Records what the system plays, from the monitor of the default output
+    pub system_audio: bool,
+    /// Records narration from `microphone_device`
+    pub microphone: bool,
+    /// Name of the audio device to record narration from, `None` records the default input
+    pub microphone_device: Option<String>,
+    /// Volume of the system audio, 1.0 keeps it as captured
+    pub system_audio_gain: f32,
+    /// Volume of the microphone, 1.0 keeps it as captured
+    pub microphone_gain: f32,
+    pub audio_mix: AudioMix,
+    pub audio_codec: AudioCodec,
 }
 
 impl UserOptions {
@@ -24,20 +60,76 @@ impl UserOptions {
             pointer,
             frame_rate,
             resolution,
+            timelapse_interval: None,
+            auto_zoom: None,
+            monitor: None,
+            window: None,
+            region: None,
+            encode_mode: EncodeMode::default(),
+            codec: VideoCodec::default(),
+            container: Container::default(),
+            quality: QualitySettings::default(),
+            system_audio: false,
+            microphone: false,
+            microphone_device: None,
+            system_audio_gain: 1.0,
+            microphone_gain: 1.0,
+            audio_mix: AudioMix::default(),
+            audio_codec: AudioCodec::default(),
+        }
+    }
+
+    /// Audio inputs that are recorded, in the order of their tracks. Timelapses are
+    /// recorded without sound.
+    pub fn audio_inputs(&self) -> Vec<AudioInput> {
+        if self.timelapse_interval.is_some() {
+            return Vec::new();
+        }
+        AudioInput::ALL
+            .into_iter()
+            .filter(|input| match input {
+                AudioInput::System => self.system_audio,
+                AudioInput::Microphone => self.microphone,
+            })
+            .collect()
+    }
+
+    pub fn audio_gain(&self, input: AudioInput) -> f32 {
+        match input {
+            AudioInput::System => self.system_audio_gain,
+            AudioInput::Microphone => self.microphone_gain,
+        }
+    }
+
+    /// Audio tracks of the recordings: one per input when they are kept separate,
+    /// otherwise a single track with all inputs mixed into it
+    pub fn audio_tracks(&self) -> Vec<AudioTrack> {
+        let inputs = self.audio_inputs();
+        match self.audio_mix {
+            AudioMix::Separate if inputs.len() > 1 => inputs
+                .into_iter()
+                .map(|input| AudioTrack {
+                    codec: self.audio_codec,
+                    title: Some(input.to_string()),
+                })
+                .collect(),
+            _ if inputs.is_empty() => Vec::new(),
+            _ => vec![AudioTrack {
+                codec: self.audio_codec,
+                title: None,
+            }],
         }
     }
 }
 
 pub fn get_user_options() -> &'static Mutex<UserOptions> {
     OPTIONS.get_or_init(move || {
-        let pointer = get_pointers().get(0).unwrap().as_ref();
+        let pointer = get_pointers()[0].as_ref();
         let frame_rate = 30; // Default to 30 FPS (matches available_frame_rates)
                              // Default resolution: 720p with current screen aspect ratio
-        let (screen_width, screen_height) = crate::screen_resolution();
-        let aspect_ratio = screen_width as f32 / screen_height as f32;
-        let default_height = 720;
-        let default_width = (default_height as f32 * aspect_ratio).round() as u32 & !1; // Make even
-        let resolution = (default_width, default_height);
+                             // Falls back to 16:9 when no monitor can be queried
+        let screen_resolution = crate::monitor_resolution(None).unwrap_or((1280, 720));
+        let resolution = scale_to_height(screen_resolution, 720);
         Mutex::new(UserOptions::new(pointer, frame_rate, resolution))
     })
 }
@@ -92,27 +184,256 @@ pub fn get_pointers() -> &'static Vec<Box<dyn Pointer + Send + Sync>> {
     POINTERS.get_or_init(move || pointers)
 }
 
-pub fn update_resolution(mut width: u32, height: u32) {
+pub fn update_resolution(mut width: u32, height: u32) -> Result<()> {
     // Making sure the width is divisible by 2 (bit manipulation)
     // important for ffmpeg
-    width = width & !1;
+    width &= !1;
+    if width == 0 || height == 0 {
+        return Err(XlabError::Config(format!(
+            "invalid resolution {width}x{height}"
+        )));
+    }
     let options = get_user_options();
     let mut options = options.lock().unwrap();
     options.resolution = (width, height);
+    Ok(())
 }
 
-pub fn update_pointer(index: usize) {
+pub fn update_pointer(index: usize) -> Result<()> {
     let pointers = get_pointers();
-    let pointer = pointers.get(index).unwrap().as_ref();
+    let pointer = pointers
+        .get(index)
+        .ok_or_else(|| XlabError::Config(format!("no pointer at index {index}")))?
+        .as_ref();
     let options = get_user_options();
     let mut options = options.lock().unwrap();
     options.pointer = pointer;
+    Ok(())
+}
+
+/// Selects the monitor to record, `None` selects the primary monitor.
+/// The output resolution keeps its height and follows the aspect ratio of the monitor.
+/// Any window or region is cleared since it belonged to the previous source.
+pub fn update_monitor(monitor: Option<u32>) -> Result<()> {
+    let screen_resolution = crate::monitor_resolution(monitor)?;
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.resolution = scale_to_height(screen_resolution, options.resolution.1);
+    options.monitor = monitor;
+    options.window = None;
+    options.region = None;
+    Ok(())
+}
+
+/// Records a single window instead of a monitor, `None` goes back to the monitor.
+/// The output resolution keeps its height and follows the aspect ratio of the window
+/// as it is now; later resizes of the window are letterboxed.
+/// Any region is cleared since it belonged to the previous source.
+pub fn update_window(window: Option<u32>) -> Result<()> {
+    let source_resolution = match window {
+        Some(id) => {
+            let window = crate::capture::find_window(id)?;
+            (window.width()?, window.height()?)
+        }
+        None => crate::screen_resolution()?,
+    };
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.resolution = scale_to_height(source_resolution, options.resolution.1);
+    options.window = window;
+    options.region = None;
+    Ok(())
+}
+
+/// Restricts recording to a region of the selected monitor or window, `None` records
+/// all of it. The output resolution keeps its height and follows the aspect ratio of
+/// the region.
+pub fn update_region(region: Option<CaptureRegion>) -> Result<()> {
+    let source_resolution = crate::source_resolution()?;
+    let dimensions = match region {
+        Some(region) => {
+            region.validate(source_resolution)?;
+            region.dimensions()
+        }
+        None => source_resolution,
+    };
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.resolution = scale_to_height(dimensions, options.resolution.1);
+    options.region = region;
+    Ok(())
+}
+
+/// Scales `(width, height)` to the given height, keeping the width even for ffmpeg
+fn scale_to_height((width, height): (u32, u32), new_height: u32) -> (u32, u32) {
+    let aspect_ratio = width as f32 / height as f32;
+    let new_width = (new_height as f32 * aspect_ratio).round() as u32 & !1;
+    (new_width, new_height)
+}
+
+pub fn update_encode_mode(encode_mode: EncodeMode) {
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.encode_mode = encode_mode;
+}
+
+/// Selects the codec recordings are encoded with, failing if ffmpeg has no encoder for it
+/// or the selected container can't store it
+pub fn update_codec(codec: VideoCodec) -> Result<()> {
+    if !codec.is_available() {
+        return Err(XlabError::Config(format!(
+            "no {codec} encoder is available"
+        )));
+    }
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.container.validate(codec)?;
+    options.quality.validate(codec)?;
+    options.codec = codec;
+    Ok(())
+}
+
+/// Selects the file format of recordings, failing if it can't store the selected codec
+pub fn update_container(container: Container) -> Result<()> {
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    container.validate(options.codec)?;
+    if !options.audio_inputs().is_empty() {
+        container.validate_audio(options.audio_codec)?;
+    }
+    options.container = container;
+    Ok(())
+}
+
+/// Replaces the quality settings with those of `preset`
+pub fn update_quality_preset(preset: QualityPreset) -> Result<()> {
+    update_quality(preset.settings())
+}
+
+/// Replaces the quality settings, failing if the selected codec doesn't support them
+pub fn update_quality(quality: QualitySettings) -> Result<()> {
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    quality.validate(options.codec)?;
+    options.quality = quality;
+    Ok(())
+}
+
+/// Turns recording the system audio on or off, failing if system audio can't be
+/// captured here or the selected container can't store the selected audio codec
+pub fn update_system_audio(enabled: bool) -> Result<()> {
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    if enabled {
+        crate::audio::ensure_capture_available()?;
+        options.container.validate_audio(options.audio_codec)?;
+    }
+    options.system_audio = enabled;
+    Ok(())
+}
+
+/// Turns recording the microphone on or off, failing if audio can't be captured here
+/// or the selected container can't store the selected audio codec
+pub fn update_microphone(enabled: bool) -> Result<()> {
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    if enabled {
+        crate::audio::ensure_capture_available()?;
+        options.container.validate_audio(options.audio_codec)?;
+    }
+    options.microphone = enabled;
+    Ok(())
+}
+
+/// Selects the device narration is recorded from, one of the
+/// [`available_microphones`] or `None` for the default input
+pub fn update_microphone_device(device: Option<String>) -> Result<()> {
+    if let Some(device) = &device {
+        if !available_microphones()?
+            .iter()
+            .any(|microphone| &microphone.name == device)
+        {
+            return Err(XlabError::Config(format!("no audio device named {device}")));
+        }
+    }
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.microphone_device = device;
+    Ok(())
+}
+
+/// Sets the volume `input` is recorded with, from 0.0 for silence to [`MAX_GAIN`]
+pub fn update_audio_gain(input: AudioInput, gain: f32) -> Result<()> {
+    if !(0.0..=MAX_GAIN).contains(&gain) {
+        return Err(XlabError::Config(format!(
+            "audio gain must be between 0 and {MAX_GAIN}"
+        )));
+    }
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    match input {
+        AudioInput::System => options.system_audio_gain = gain,
+        AudioInput::Microphone => options.microphone_gain = gain,
+    }
+    Ok(())
+}
+
+pub fn update_audio_mix(mix: AudioMix) -> Result<()> {
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.audio_mix = mix;
+    Ok(())
+}
+
+/// Selects the codec audio is encoded with, failing if ffmpeg has no encoder for it or
+/// the selected container can't store it
+pub fn update_audio_codec(codec: AudioCodec) -> Result<()> {
+    if !codec.is_available() {
+        return Err(XlabError::Config(format!(
+            "no {codec} encoder is available"
+        )));
+    }
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.container.validate_audio(codec)?;
+    options.audio_codec = codec;
+    Ok(())
 }
 
-pub fn update_frame_rate(new_rate: u32) {
+pub fn update_frame_rate(new_rate: u32) -> Result<()> {
+    if new_rate == 0 {
+        return Err(XlabError::Config("frame rate must be positive".into()));
+    }
     let options = get_user_options();
     let mut options = options.lock().unwrap();
     options.frame_rate = new_rate;
+    Ok(())
+}
+
+/// Records a timelapse that captures a frame once per `interval`, or in real time
+/// when `None`
+pub fn update_timelapse_interval(interval: Option<Duration>) -> Result<()> {
+    if interval.is_some_and(|interval| interval.is_zero()) {
+        return Err(XlabError::Config(
+            "timelapse interval must be positive".into(),
+        ));
+    }
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.timelapse_interval = interval;
+    Ok(())
+}
+
+/// Zooms in on the cursor while recording as `settings` say, or turns zooming off
+/// when `None`
+pub fn update_auto_zoom(settings: Option<ZoomSettings>) -> Result<()> {
+    if let Some(settings) = &settings {
+        settings.validate()?;
+    }
+    let options = get_user_options();
+    let mut options = options.lock().unwrap();
+    options.auto_zoom = settings;
+    Ok(())
 }
 
 /// Generates a 20x20 pointer with two concentric circles.
@@ -142,7 +463,7 @@ fn draw_pointer_1(size: u32) -> RgbaImage {
         }
     }
 
-    super::resize_image(&mut image, (size, size));
+    super::resize_image(&mut image, (size, size)).expect("pointer images have a fixed size");
 
     image
 }
@@ -175,7 +496,7 @@ fn draw_pointer_2(size: u32) -> RgbaImage {
         }
     }
 
-    super::resize_image(&mut image, (size, size));
+    super::resize_image(&mut image, (size, size)).expect("pointer images have a fixed size");
 
     image
 }
@@ -206,7 +527,7 @@ fn draw_pointer_3(size: u32) -> RgbaImage {
         }
     }
 
-    super::resize_image(&mut image, (size, size));
+    super::resize_image(&mut image, (size, size)).expect("pointer images have a fixed size");
 
     image
 }
@@ -241,7 +562,7 @@ fn draw_pointer_4(size: u32) -> RgbaImage {
         }
     }
 
-    super::resize_image(&mut image, (size, size));
+    super::resize_image(&mut image, (size, size)).expect("pointer images have a fixed size");
 
     image
 }
@@ -0,0 +1,251 @@
+use std::ffi::{CStr, CString};
+
+use ffmpeg_sys_next::*;
+
+use super::quality::{self, ChromaSubsampling, QualitySettings, MAX_X264_CRF};
+use crate::{Result, XlabError};
+
+/// Video codecs recordings can be encoded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    H265,
+    Vp9,
+    Av1,
+}
+
+impl VideoCodec {
+    pub const ALL: [VideoCodec; 4] = [Self::H264, Self::H265, Self::Vp9, Self::Av1];
+
+    pub fn codec_id(self) -> AVCodecID {
+        match self {
+            Self::H264 => AVCodecID::AV_CODEC_ID_H264,
+            Self::H265 => AVCodecID::AV_CODEC_ID_HEVC,
+            Self::Vp9 => AVCodecID::AV_CODEC_ID_VP9,
+            Self::Av1 => AVCodecID::AV_CODEC_ID_AV1,
+        }
+    }
+
+    /// Encoders of the codec that the quality settings can be mapped onto,
+    /// in order of preference
+    fn encoder_names(self) -> &'static [&'static CStr] {
+        match self {
+            Self::H264 => &[c"libx264"],
+            Self::H265 => &[c"libx265"],
+            Self::Vp9 => &[c"libvpx-vp9"],
+            Self::Av1 => &[c"libsvtav1", c"libaom-av1", c"librav1e"],
+        }
+    }
+
+    /// Finds the preferred encoder of the codec that ffmpeg was built with
+    pub(crate) fn find_encoder(self) -> Result<*const AVCodec> {
+        self.encoder_names()
+            .iter()
+            .map(|name| unsafe { avcodec_find_encoder_by_name(name.as_ptr()) })
+            .find(|codec| !codec.is_null())
+            .ok_or_else(|| XlabError::Encode(format!("no {self} encoder is available")))
+    }
+
+    /// Finds the preferred encoder that can encode video with `quality`. Of the AV1
+    /// encoders only libaom-av1 has a lossless mode, so lossless AV1 fails without it
+    /// rather than turning out lossy.
+    pub(crate) fn find_encoder_for(self, quality: &QualitySettings) -> Result<*const AVCodec> {
+        if !(self == Self::Av1 && quality.is_lossless()) {
+            return self.find_encoder();
+        }
+        let codec = unsafe { avcodec_find_encoder_by_name(c"libaom-av1".as_ptr()) };
+        match codec.is_null() {
+            true => Err(XlabError::Encode(
+                "lossless AV1 needs the libaom-av1 encoder, which is not available".into(),
+            )),
+            false => Ok(codec),
+        }
+    }
+
+    /// Returns whether ffmpeg provides an encoder for the codec
+    pub fn is_available(self) -> bool {
+        self.find_encoder().is_ok()
+    }
+}
+
+impl std::fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::H264 => "H.264",
+            Self::H265 => "H.265",
+            Self::Vp9 => "VP9",
+            Self::Av1 => "AV1",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Lists the codecs that can be encoded with the ffmpeg build in use
+pub fn available_codecs() -> Vec<VideoCodec> {
+    VideoCodec::ALL
+        .into_iter()
+        .filter(|codec| codec.is_available())
+        .collect()
+}
+
+/// Maps x264 style quality settings onto the options of the encoder of `codec_ctx`
+pub(crate) unsafe fn apply_quality(
+    codec_ctx: *mut AVCodecContext,
+    codec: VideoCodec,
+    quality: &QualitySettings,
+) -> Result<()> {
+    let speed = quality::preset_speed(&quality.preset)?;
+    let crf = quality.crf as i32;
+    // AV1 and VP9 encoders use a 0-63 scale for their CRF
+    let crf_63 = (crf * 63 + MAX_X264_CRF as i32 / 2) / MAX_X264_CRF as i32;
+    let lossless = quality.is_lossless();
+
+    if let Some(interval) = quality.keyframe_interval {
+        (*codec_ctx).gop_size = interval.min(i32::MAX as u32) as i32;
+    }
+    // A bitrate replaces the constant quality; a maximum bitrate caps either of them
+    let bitrate = quality.bitrate.map(|kbps| kbps as i64 * 1000);
+    let max_bitrate = quality
+        .max_bitrate
+        .map(|kbps| kbps as i64 * 1000)
+        .or(bitrate);
+    if let Some(bitrate) = bitrate {
+        (*codec_ctx).bit_rate = bitrate;
+        if quality.max_bitrate.is_none() {
+            (*codec_ctx).rc_min_rate = bitrate;
+        }
+    }
+    if let Some(max_bitrate) = max_bitrate {
+        (*codec_ctx).rc_max_rate = max_bitrate;
+        // Two seconds of buffer lets the rate vary within the cap
+        (*codec_ctx).rc_buffer_size = (max_bitrate * 2).min(i32::MAX as i64) as i32;
+    }
+
+    let encoder = CStr::from_ptr((*(*codec_ctx).codec).name).to_string_lossy();
+    match encoder.as_ref() {
+        "libx264" => {
+            if bitrate.is_none() {
+                set_option(codec_ctx, c"crf", &crf.to_string())?;
+            } else if quality.max_bitrate.is_none() {
+                set_option(codec_ctx, c"nal-hrd", "cbr")?;
+            }
+            set_option(codec_ctx, c"preset", &quality.preset)?;
+            if let Some(tune) = quality.tune {
+                set_option(codec_ctx, c"tune", &tune.to_string())?;
+            }
+            if let Some(profile) = &quality.profile {
+                set_option(codec_ctx, c"profile", profile)?;
+            }
+            if let Some(level) = &quality.level {
+                set_option(codec_ctx, c"level", level)?;
+            }
+        }
+        "libx265" => {
+            let mut params = Vec::new();
+            if bitrate.is_none() {
+                set_option(codec_ctx, c"crf", &crf.to_string())?;
+            }
+            // x265 treats a CRF of 0 as a very high quality rather than lossless
+            if lossless {
+                params.push("lossless=1".to_owned());
+            }
+            if let Some(level) = &quality.level {
+                params.push(format!("level-idc={level}"));
+            }
+            set_option(codec_ctx, c"preset", &quality.preset)?;
+            if let Some(tune) = quality.tune {
+                set_option(codec_ctx, c"tune", &tune.to_string())?;
+            }
+            if let Some(profile) = &quality.profile {
+                set_option(codec_ctx, c"profile", profile)?;
+            }
+            if !params.is_empty() {
+                set_option(codec_ctx, c"x265-params", &params.join(":"))?;
+            }
+        }
+        "libvpx-vp9" => {
+            if bitrate.is_none() {
+                // Constant quality mode needs the bitrate to be unset, a cap turns it
+                // into constrained quality
+                (*codec_ctx).bit_rate = max_bitrate.unwrap_or(0);
+                set_option(codec_ctx, c"crf", &crf_63.to_string())?;
+            }
+            if lossless {
+                set_option(codec_ctx, c"lossless", "1")?;
+            }
+            let deadline = if speed <= 2 { "realtime" } else { "good" };
+            let cpu_used = [8, 7, 6, 5, 4, 3, 2, 1, 0, 0][speed];
+            set_option(codec_ctx, c"deadline", deadline)?;
+            set_option(codec_ctx, c"cpu-used", &cpu_used.to_string())?;
+            set_option(codec_ctx, c"row-mt", "1")?;
+        }
+        "libsvtav1" => {
+            if quality.chroma != ChromaSubsampling::Yuv420 {
+                return Err(XlabError::Config(format!(
+                    "the SVT-AV1 encoder can't encode {} video",
+                    quality.chroma
+                )));
+            }
+            if bitrate.is_none() {
+                // SVT-AV1 has no lossless mode, its CRF starts at 1. Lossless video is
+                // encoded with libaom-av1 instead.
+                set_option(codec_ctx, c"crf", &crf_63.max(1).to_string())?;
+            }
+            let svt_preset = [12, 11, 10, 9, 8, 7, 5, 4, 2, 1][speed];
+            set_option(codec_ctx, c"preset", &svt_preset.to_string())?;
+        }
+        "libaom-av1" => {
+            if bitrate.is_none() {
+                (*codec_ctx).bit_rate = max_bitrate.unwrap_or(0);
+                set_option(codec_ctx, c"crf", &crf_63.to_string())?;
+            }
+            // libaom treats a CRF of 0 as its highest quality rather than lossless
+            if lossless {
+                set_option(codec_ctx, c"aom-params", "lossless=1")?;
+            }
+            let cpu_used = [8, 8, 7, 6, 5, 4, 3, 2, 1, 0][speed];
+            set_option(codec_ctx, c"cpu-used", &cpu_used.to_string())?;
+            set_option(codec_ctx, c"row-mt", "1")?;
+        }
+        "librav1e" => {
+            if bitrate.is_none() {
+                let qp = crf * 255 / MAX_X264_CRF as i32;
+                set_option(codec_ctx, c"qp", &qp.to_string())?;
+            }
+            let rav1e_speed = [10, 10, 9, 8, 7, 6, 5, 4, 3, 2][speed];
+            set_option(codec_ctx, c"speed", &rav1e_speed.to_string())?;
+        }
+        _ => {}
+    }
+
+    // VP9 and AV1 encoders read their profile and level from the codec context
+    if let Some(profile) = quality.profile.as_deref() {
+        if let Some(number) = quality::profile_number(codec, profile) {
+            (*codec_ctx).profile = number;
+        }
+    }
+    if codec == VideoCodec::Av1 {
+        if let Some(level) = &quality.level {
+            (*codec_ctx).level = quality::level_number(codec, level)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets a private option of the encoder of `codec_ctx`
+pub(crate) unsafe fn set_option(
+    codec_ctx: *mut AVCodecContext,
+    name: &CStr,
+    value: &str,
+) -> Result<()> {
+    let value_c = CString::new(value)
+        .map_err(|_| XlabError::Config(format!("invalid encoder option value {value}")))?;
+    if av_opt_set((*codec_ctx).priv_data, name.as_ptr(), value_c.as_ptr(), 0) < 0 {
+        return Err(XlabError::Config(format!(
+            "the encoder does not accept {}={value}",
+            name.to_string_lossy()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::ffi::CStr;
+
+use super::VideoCodec;
+use crate::{audio::AudioCodec, Result, XlabError};
+
+/// File formats recordings can be written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Container {
+    #[default]
+    Mp4,
+    Mkv,
+    WebM,
+    Mov,
+}
+
+impl Container {
+    pub const ALL: [Container; 4] = [Self::Mp4, Self::Mkv, Self::WebM, Self::Mov];
+
+    /// Finds the container files with `extension` are written in, ignoring case
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|container| container.extension().eq_ignore_ascii_case(extension))
+    }
+
+    /// File extension of the container, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Mkv => "mkv",
+            Self::WebM => "webm",
+            Self::Mov => "mov",
+        }
+    }
+
+    /// Name of the ffmpeg muxer writing the container
+    pub(crate) fn format_name(self) -> &'static CStr {
+        match self {
+            Self::Mp4 => c"mp4",
+            Self::Mkv => c"matroska",
+            Self::WebM => c"webm",
+            Self::Mov => c"mov",
+        }
+    }
+
+    /// Returns whether players can be expected to play `codec` from this container
+    pub fn supports(self, codec: VideoCodec) -> bool {
+        match self {
+            Self::Mp4 | Self::Mkv => true,
+            Self::WebM => matches!(codec, VideoCodec::Vp9 | VideoCodec::Av1),
+            Self::Mov => matches!(codec, VideoCodec::H264 | VideoCodec::H265),
+        }
+    }
+
+    /// Fails unless the container supports `codec`
+    pub fn validate(self, codec: VideoCodec) -> Result<()> {
+        if !self.supports(codec) {
+            return Err(XlabError::Config(format!(
+                "{codec} video can't be stored in {self} files"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns whether players can be expected to play `codec` audio from this container
+    pub fn supports_audio(self, codec: AudioCodec) -> bool {
+        match self {
+            Self::Mp4 | Self::Mkv => true,
+            Self::WebM => codec == AudioCodec::Opus,
+            Self::Mov => codec == AudioCodec::Aac,
+        }
+    }
+
+    /// Fails unless the container supports `codec` audio
+    pub fn validate_audio(self, codec: AudioCodec) -> Result<()> {
+        if !self.supports_audio(codec) {
+            return Err(XlabError::Config(format!(
+                "{codec} audio can't be stored in {self} files"
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Mp4 => "MP4",
+            Self::Mkv => "MKV",
+            Self::WebM => "WebM",
+            Self::Mov => "MOV",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codecs_in_containers() {
+        for codec in [VideoCodec::H264, VideoCodec::H265] {
+            assert!(Container::WebM.validate(codec).is_err());
+            Container::Mov.validate(codec).unwrap();
+        }
+        for codec in [VideoCodec::Vp9, VideoCodec::Av1] {
+            Container::WebM.validate(codec).unwrap();
+            assert!(Container::Mov.validate(codec).is_err());
+        }
+        for codec in VideoCodec::ALL {
+            Container::Mp4.validate(codec).unwrap();
+            Container::Mkv.validate(codec).unwrap();
+        }
+
+        Container::Mp4.validate_audio(AudioCodec::Opus).unwrap();
+        assert!(Container::Mov.validate_audio(AudioCodec::Opus).is_err());
+        Container::Mov.validate_audio(AudioCodec::Aac).unwrap();
+        assert!(Container::WebM.validate_audio(AudioCodec::Aac).is_err());
+        Container::WebM.validate_audio(AudioCodec::Opus).unwrap();
+    }
+
+    #[test]
+    fn containers_from_extensions() {
+        assert_eq!(Container::from_extension("MKV"), Some(Container::Mkv));
+        assert_eq!(Container::from_extension("webm"), Some(Container::WebM));
+        assert_eq!(Container::from_extension("Mov"), Some(Container::Mov));
+        assert_eq!(Container::from_extension("avi"), None);
+        assert_eq!(Container::from_extension(""), None);
+        for container in Container::ALL {
+            assert_eq!(
+                Container::from_extension(container.extension()),
+                Some(container)
+            );
+        }
+    }
+}
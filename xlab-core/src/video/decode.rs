@@ -0,0 +1,241 @@
+use std::path::Path;
+use std::ptr;
+use std::time::Duration;
+
+use ffmpeg_sys_next::AVPixelFormat::AV_PIX_FMT_RGBA;
+use ffmpeg_sys_next::*;
+use xcap::image::RgbaImage;
+
+use super::{path_to_cstring, TIME_BASE};
+use crate::{Result, XlabError};
+
+/// Reads the video stream of a file as RGBA frames, timestamped from the first frame
+pub struct VideoDecoder {
+    fmt_ctx: *mut AVFormatContext,
+    codec_ctx: *mut AVCodecContext,
+    sws_ctx: *mut SwsContext,
+    frame: *mut AVFrame,
+    packet: *mut AVPacket,
+    stream_index: i32,
+    time_base: AVRational,
+    first_pts: Option<i64>,
+    last_pts: i64,
+    draining: bool,
+    finished: bool,
+}
+
+impl VideoDecoder {
+    pub fn open(input_path: &Path) -> Result<Self> {
+        let input_path_c = path_to_cstring(input_path)?;
+        // Everything allocated so far is released by `drop` when a step fails
+        let mut decoder = Self {
+            fmt_ctx: ptr::null_mut(),
+            codec_ctx: ptr::null_mut(),
+            sws_ctx: ptr::null_mut(),
+            frame: ptr::null_mut(),
+            packet: ptr::null_mut(),
+            stream_index: 0,
+            time_base: TIME_BASE,
+            first_pts: None,
+            last_pts: 0,
+            draining: false,
+            finished: false,
+        };
+        unsafe {
+            // 1. Open the file and probe its streams
+            if avformat_open_input(
+                &mut decoder.fmt_ctx,
+                input_path_c.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+            ) < 0
+            {
+                return Err(XlabError::Encode(format!(
+                    "Failed to open {}",
+                    input_path.display()
+                )));
+            }
+            if avformat_find_stream_info(decoder.fmt_ctx, ptr::null_mut()) < 0 {
+                return Err(XlabError::Encode("Failed to read stream info".into()));
+            }
+
+            // 2. Find the video stream and its decoder
+            let mut codec = ptr::null();
+            let stream_index = av_find_best_stream(
+                decoder.fmt_ctx,
+                AVMediaType::AVMEDIA_TYPE_VIDEO,
+                -1,
+                -1,
+                &mut codec,
+                0,
+            );
+            if stream_index < 0 || codec.is_null() {
+                return Err(XlabError::Encode(format!(
+                    "{} has no decodable video stream",
+                    input_path.display()
+                )));
+            }
+            let stream = *(*decoder.fmt_ctx).streams.add(stream_index as usize);
+            decoder.stream_index = stream_index;
+            decoder.time_base = (*stream).time_base;
+
+            // 3. Open the decoder
+            decoder.codec_ctx = avcodec_alloc_context3(codec);
+            if decoder.codec_ctx.is_null() {
+                return Err(XlabError::Encode("Failed to allocate codec context".into()));
+            }
+            if avcodec_parameters_to_context(decoder.codec_ctx, (*stream).codecpar) < 0 {
+                return Err(XlabError::Encode("Failed to copy codec parameters".into()));
+            }
+            (*decoder.codec_ctx).thread_count = 0; // 0 = auto-detect
+            if avcodec_open2(decoder.codec_ctx, codec, ptr::null_mut()) < 0 {
+                return Err(XlabError::Encode("Failed to open codec".into()));
+            }
+
+            // 4. Allocate frame and packet
+            decoder.frame = av_frame_alloc();
+            decoder.packet = av_packet_alloc();
+            if decoder.frame.is_null() || decoder.packet.is_null() {
+                return Err(XlabError::Encode("Failed to allocate frame".into()));
+            }
+        }
+        Ok(decoder)
+    }
+
+    /// Dimensions of the decoded frames
+    pub fn dimensions(&self) -> (u32, u32) {
+        unsafe {
+            (
+                (*self.codec_ctx).width as u32,
+                (*self.codec_ctx).height as u32,
+            )
+        }
+    }
+
+    /// Decodes the next frame, returning `None` once the stream is exhausted
+    fn next_frame(&mut self) -> Result<Option<(Duration, RgbaImage)>> {
+        unsafe {
+            loop {
+                let received = avcodec_receive_frame(self.codec_ctx, self.frame);
+                if received >= 0 {
+                    let frame = self.convert_frame();
+                    av_frame_unref(self.frame);
+                    return frame.map(Some);
+                }
+                if received == AVERROR_EOF || self.draining {
+                    return Ok(None);
+                }
+                if received != AVERROR(EAGAIN) {
+                    return Err(XlabError::Encode("Failed to decode frame".into()));
+                }
+
+                // The decoder needs more input
+                if av_read_frame(self.fmt_ctx, self.packet) < 0 {
+                    // End of file, flush the frames the decoder still holds
+                    self.draining = true;
+                    avcodec_send_packet(self.codec_ctx, ptr::null());
+                    continue;
+                }
+                let sent = if (*self.packet).stream_index == self.stream_index {
+                    avcodec_send_packet(self.codec_ctx, self.packet)
+                } else {
+                    0
+                };
+                av_packet_unref(self.packet);
+                if sent < 0 {
+                    return Err(XlabError::Encode("Failed to send packet to decoder".into()));
+                }
+            }
+        }
+    }
+
+    /// Converts the decoded frame to RGBA and computes its timestamp
+    unsafe fn convert_frame(&mut self) -> Result<(Duration, RgbaImage)> {
+        let width = (*self.frame).width;
+        let height = (*self.frame).height;
+        self.sws_ctx = sws_getCachedContext(
+            self.sws_ctx,
+            width,
+            height,
+            (*self.codec_ctx).pix_fmt,
+            width,
+            height,
+            AV_PIX_FMT_RGBA,
+            SWS_BILINEAR,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null(),
+        );
+        if self.sws_ctx.is_null() {
+            return Err(XlabError::Encode("Failed to create scaling context".into()));
+        }
+        // Untagged videos get the BT.601 default of swscale
+        let colorspace = match (*self.frame).colorspace {
+            AVColorSpace::AVCOL_SPC_BT709 => SWS_CS_ITU709,
+            AVColorSpace::AVCOL_SPC_BT2020_NCL => SWS_CS_BT2020,
+            _ => SWS_CS_DEFAULT,
+        };
+        let full_range = ((*self.frame).color_range == AVColorRange::AVCOL_RANGE_JPEG) as i32;
+        sws_setColorspaceDetails(
+            self.sws_ctx,
+            sws_getCoefficients(colorspace),
+            full_range,
+            sws_getCoefficients(SWS_CS_DEFAULT),
+            1,
+            0,
+            1 << 16,
+            1 << 16,
+        );
+
+        let mut image = RgbaImage::new(width as u32, height as u32);
+        let dst_slice = [image.as_mut_ptr()];
+        let dst_stride = [width * 4];
+        let result = sws_scale(
+            self.sws_ctx,
+            (*self.frame).data.as_ptr() as *const *const u8,
+            (*self.frame).linesize.as_ptr(),
+            0,
+            height,
+            dst_slice.as_ptr(),
+            dst_stride.as_ptr(),
+        );
+        if result < 0 {
+            return Err(XlabError::Encode("Failed to convert image format".into()));
+        }
+
+        // Frames without a timestamp follow the previous one
+        let pts = match (*self.frame).best_effort_timestamp {
+            AV_NOPTS_VALUE => self.last_pts + 1,
+            pts => pts,
+        };
+        self.last_pts = pts;
+        let first_pts = *self.first_pts.get_or_insert(pts);
+        let micros = av_rescale_q(pts - first_pts, self.time_base, TIME_BASE);
+        Ok((Duration::from_micros(micros.max(0) as u64), image))
+    }
+}
+
+impl Iterator for VideoDecoder {
+    type Item = Result<(Duration, RgbaImage)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let frame = self.next_frame().transpose();
+        self.finished = !matches!(frame, Some(Ok(_)));
+        frame
+    }
+}
+
+impl Drop for VideoDecoder {
+    fn drop(&mut self) {
+        unsafe {
+            av_packet_free(&mut self.packet);
+            av_frame_free(&mut self.frame);
+            sws_freeContext(self.sws_ctx);
+            avcodec_free_context(&mut self.codec_ctx);
+            avformat_close_input(&mut self.fmt_ctx);
+        }
+    }
+}
@@ -1,30 +1,47 @@
 use ffmpeg_sys_next::*;
-use std::ffi::{CStr, CString};
-use std::path::PathBuf;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::time::Duration;
 use xcap::image::RgbaImage;
 
-use ffmpeg_sys_next::AVCodecID::AV_CODEC_ID_H264;
+use crate::{
+    audio::{AudioEncoder, AudioTrack},
+    Result, XlabError,
+};
+
 use ffmpeg_sys_next::AVPixelFormat::AV_PIX_FMT_RGBA;
 
+mod codec;
+mod container;
+mod decode;
+mod quality;
+
+pub(crate) use codec::apply_quality;
+pub use codec::{available_codecs, VideoCodec};
+pub use container::Container;
+pub use decode::VideoDecoder;
+pub use quality::{ChromaSubsampling, ColorRange, QualityPreset, QualitySettings, Tune};
+
+/// Frames are timestamped in microseconds, so that their pts follow the moment they
+/// were captured rather than a fixed frame rate
+const TIME_BASE: AVRational = AVRational {
+    num: 1,
+    den: 1_000_000,
+};
+
+#[derive(Default)]
 pub struct EncoderConfig {
-    pub pix_fmt: AVPixelFormat,
-    pub preset: String,
-    pub crf: i32,
+    pub codec: VideoCodec,
+    pub container: Container,
+    pub quality: QualitySettings,
+    /// Audio streams in the order of their track numbers, none writes a video
+    /// without audio
+    pub audio: Vec<AudioTrack>,
+    /// 0 = auto-detect
     pub thread_count: i32,
 }
 
-impl Default for EncoderConfig {
-    fn default() -> Self {
-        Self {
-            pix_fmt: AVPixelFormat::AV_PIX_FMT_YUV420P,
-            preset: "medium".to_owned(),
-            crf: 18,
-            thread_count: 0, // 0 = auto-detect
-        }
-    }
-}
-
 pub struct VideoEncoder {
     fmt_ctx: *mut AVFormatContext,
     codec_ctx: *mut AVCodecContext,
@@ -33,146 +50,215 @@ pub struct VideoEncoder {
     frame: *mut AVFrame,
     packet: *mut AVPacket,
     time_base: AVRational,
+    last_pts: Option<i64>,
+    audio: Vec<AudioEncoder>,
 }
 
 impl VideoEncoder {
+    /// Creates an encoder writing to `output_path`. `fps` is the nominal frame rate,
+    /// used as a rate control hint; frame timing comes from the appended timestamps.
     pub fn new(
         output_path: PathBuf,
         fps: u32,
         dimensions: (u32, u32),
         config: EncoderConfig,
-    ) -> Result<Self, String> {
+    ) -> Result<Self> {
+        config.container.validate(config.codec)?;
+        config.quality.validate(config.codec)?;
+        for track in &config.audio {
+            config.container.validate_audio(track.codec)?;
+        }
+        let pix_fmt = config.quality.chroma.pix_fmt();
+        let color_range = config.quality.color_range.av_range();
+        let output_path_c = path_to_cstring(&output_path)?;
+        // Everything allocated so far is released by `drop` when a step fails
+        let mut encoder = Self {
+            fmt_ctx: ptr::null_mut(),
+            codec_ctx: ptr::null_mut(),
+            stream: ptr::null_mut(),
+            sws_ctx: ptr::null_mut(),
+            frame: ptr::null_mut(),
+            packet: ptr::null_mut(),
+            time_base: TIME_BASE,
+            last_pts: None,
+            audio: Vec::with_capacity(config.audio.len()),
+        };
         unsafe {
-            let mut fmt_ctx = ptr::null_mut();
-            let output_path_c = path_to_cstring(&output_path);
-
             // 1. Allocate format context
             if avformat_alloc_output_context2(
-                &mut fmt_ctx,
-                ptr::null(),
+                &mut encoder.fmt_ctx,
                 ptr::null(),
+                config.container.format_name().as_ptr(),
                 output_path_c.as_ptr(),
             ) < 0
             {
-                return Err("Failed to create output context".into());
+                return Err(XlabError::Encode("Failed to create output context".into()));
             }
+            let fmt_ctx = encoder.fmt_ctx;
 
             // 2. Open output file
-            let mut avio_ctx = ptr::null_mut();
-            if avio_open(&mut avio_ctx, output_path_c.as_ptr(), AVIO_FLAG_WRITE) < 0 {
-                return Err("Failed to open output file".into());
+            if avio_open(&mut (*fmt_ctx).pb, output_path_c.as_ptr(), AVIO_FLAG_WRITE) < 0 {
+                return Err(XlabError::Encode("Failed to open output file".into()));
             }
-            (*fmt_ctx).pb = avio_ctx;
 
             // 3. Create stream
-            let stream = avformat_new_stream(fmt_ctx, ptr::null());
-            if stream.is_null() {
-                return Err("Failed to create stream".into());
+            encoder.stream = avformat_new_stream(fmt_ctx, ptr::null());
+            if encoder.stream.is_null() {
+                return Err(XlabError::Encode("Failed to create stream".into()));
             }
+            let stream = encoder.stream;
 
             // 4. Initialize codec context
-            let codec = avcodec_find_encoder(AV_CODEC_ID_H264);
-            if codec.is_null() {
-                return Err("H.264 encoder not found".into());
-            }
+            let codec = config.codec.find_encoder_for(&config.quality)?;
 
-            let codec_ctx = avcodec_alloc_context3(codec);
-            if codec_ctx.is_null() {
-                return Err("Failed to allocate codec context".into());
+            encoder.codec_ctx = avcodec_alloc_context3(codec);
+            if encoder.codec_ctx.is_null() {
+                return Err(XlabError::Encode("Failed to allocate codec context".into()));
             }
+            let codec_ctx = encoder.codec_ctx;
 
             // 5. Configure codec parameters
-            (*codec_ctx).codec_id = AV_CODEC_ID_H264;
+            (*codec_ctx).codec_id = config.codec.codec_id();
             (*codec_ctx).width = dimensions.0 as i32;
             (*codec_ctx).height = dimensions.1 as i32;
-            (*codec_ctx).time_base = AVRational {
-                num: 1,
-                den: fps as i32,
+            (*codec_ctx).time_base = TIME_BASE;
+            (*codec_ctx).framerate = AVRational {
+                num: fps as i32,
+                den: 1,
             };
-            (*codec_ctx).pix_fmt = config.pix_fmt;
+            (*codec_ctx).pix_fmt = pix_fmt;
+            // Screens are sRGB, which shares its primaries with BT.709. Without these
+            // tags players guess the colorspace from the resolution and may pick BT.601.
+            (*codec_ctx).color_primaries = AVColorPrimaries::AVCOL_PRI_BT709;
+            (*codec_ctx).color_trc = AVColorTransferCharacteristic::AVCOL_TRC_BT709;
+            (*codec_ctx).colorspace = AVColorSpace::AVCOL_SPC_BT709;
+            (*codec_ctx).color_range = color_range;
             (*codec_ctx).thread_count = config.thread_count;
             (*codec_ctx).thread_type = FF_THREAD_FRAME;
-
-            // 6. Copy parameters to stream
-            if avcodec_parameters_from_context((*stream).codecpar, codec_ctx) < 0 {
-                return Err("Failed to copy codec parameters".into());
+            // Matroska and MOV expect codec headers in the stream parameters
+            if (*(*fmt_ctx).oformat).flags & AVFMT_GLOBALHEADER != 0 {
+                (*codec_ctx).flags |= AV_CODEC_FLAG_GLOBAL_HEADER as i32;
             }
 
-            // 7. Set encoder options
-            let crf = CString::new("crf").unwrap();
-            let preset = CString::new(config.preset).unwrap();
-            av_opt_set_int((*codec_ctx).priv_data, crf.as_ptr(), config.crf as i64, 0);
-            av_opt_set(
-                (*codec_ctx).priv_data,
-                CStr::from_bytes_with_nul(b"preset\0").unwrap().as_ptr(),
-                preset.as_ptr(),
-                0,
-            );
+            // 6. Set encoder options
+            apply_quality(codec_ctx, config.codec, &config.quality)?;
 
-            // 8. Open codec
+            // 7. Open codec
             if avcodec_open2(codec_ctx, codec, ptr::null_mut()) < 0 {
-                return Err("Failed to open codec".into());
+                return Err(XlabError::Encode("Failed to open codec".into()));
+            }
+            encoder.time_base = (*codec_ctx).time_base;
+
+            // 8. Copy parameters to stream, including the headers written by the encoder
+            (*stream).time_base = TIME_BASE;
+            if avcodec_parameters_from_context((*stream).codecpar, codec_ctx) < 0 {
+                return Err(XlabError::Encode("Failed to copy codec parameters".into()));
             }
 
             // 9. Create scaling context for format conversion
-            let sws_ctx = sws_getContext(
+            encoder.sws_ctx = sws_getContext(
                 dimensions.0 as i32,
                 dimensions.1 as i32,
                 AV_PIX_FMT_RGBA,
                 dimensions.0 as i32,
                 dimensions.1 as i32,
-                config.pix_fmt,
+                pix_fmt,
                 SWS_BILINEAR,
                 ptr::null_mut(),
                 ptr::null_mut(),
                 ptr::null_mut(),
             );
+            if encoder.sws_ctx.is_null() {
+                return Err(XlabError::Encode("Failed to create scaling context".into()));
+            }
+            // The conversion must use the BT.709 matrix the video is tagged with,
+            // rather than the BT.601 default of swscale
+            let coefficients = sws_getCoefficients(SWS_CS_ITU709);
+            let full_range = (color_range == AVColorRange::AVCOL_RANGE_JPEG) as i32;
+            if sws_setColorspaceDetails(
+                encoder.sws_ctx,
+                coefficients,
+                1,
+                coefficients,
+                full_range,
+                0,
+                1 << 16,
+                1 << 16,
+            ) < 0
+            {
+                return Err(XlabError::Encode(
+                    "Failed to set the conversion colorspace".into(),
+                ));
+            }
 
             // 10. Allocate frame
-            let frame = av_frame_alloc();
+            encoder.frame = av_frame_alloc();
+            let frame = encoder.frame;
+            if frame.is_null() {
+                return Err(XlabError::Encode("Failed to allocate frame".into()));
+            }
             (*frame).width = dimensions.0 as i32;
             (*frame).height = dimensions.1 as i32;
-            (*frame).format = config.pix_fmt as i32;
+            (*frame).format = pix_fmt as i32;
+            (*frame).color_primaries = (*codec_ctx).color_primaries;
+            (*frame).color_trc = (*codec_ctx).color_trc;
+            (*frame).colorspace = (*codec_ctx).colorspace;
+            (*frame).color_range = color_range;
             if av_frame_get_buffer(frame, 0) < 0 {
-                return Err("Failed to allocate frame buffers".into());
+                return Err(XlabError::Encode("Failed to allocate frame buffers".into()));
             }
 
             // 11. Allocate packet
-            let packet = av_packet_alloc();
+            encoder.packet = av_packet_alloc();
+            if encoder.packet.is_null() {
+                return Err(XlabError::Encode("Failed to allocate packet".into()));
+            }
 
-            // 12. Write header
-            if avformat_write_header(fmt_ctx, ptr::null_mut()) < 0 {
-                return Err("Failed to write header".into());
+            // 12. Add the audio streams, which must exist before the header is written
+            for track in &config.audio {
+                encoder.audio.push(AudioEncoder::new(fmt_ctx, track)?);
             }
 
-            Ok(Self {
-                fmt_ctx,
-                codec_ctx,
-                stream,
-                sws_ctx,
-                frame,
-                packet,
-                time_base: (*codec_ctx).time_base,
-            })
+            // 13. Write header
+            if avformat_write_header(fmt_ctx, ptr::null_mut()) < 0 {
+                return Err(XlabError::Encode("Failed to write header".into()));
+            }
         }
+        Ok(encoder)
     }
 
-    /// Appends an RGBA image to the video stream. Images should be pre-resized to the
-    /// target dimensions; this method only performs format conversion (RGBA to YUV420P).
-    pub fn append_image(&mut self, image: RgbaImage, index: u64) -> Result<(), String> {
+    /// Appends an RGBA image captured `timestamp` after the recording started.
+    /// Images should be pre-resized to the target dimensions; this method only performs
+    /// format conversion (RGBA to the YUV format of the encoder).
+    pub fn append_image(&mut self, image: RgbaImage, timestamp: Duration) -> Result<()> {
         let (width, height) = image.dimensions();
         let rgba_data = image.into_raw();
-        self.add_frame(&rgba_data, width, height, index)?;
+        // Encoders reject frames that don't strictly follow the previous one
+        let pts = timestamp.as_micros() as i64;
+        let pts = match self.last_pts {
+            Some(last_pts) => pts.max(last_pts + 1),
+            None => pts,
+        };
+        self.add_frame(&rgba_data, width, height, pts)?;
+        self.last_pts = Some(pts);
         Ok(())
     }
 
-    fn add_frame(
+    /// Appends interleaved audio samples of `track` captured `timestamp` after the
+    /// recording started. Does nothing if the encoder was created without the track.
+    pub fn append_audio(
         &mut self,
-        rgba_data: &[u8],
-        width: u32,
-        height: u32,
-        frame_index: u64,
-    ) -> Result<(), String> {
+        track: usize,
+        samples: &[f32],
+        timestamp: Duration,
+    ) -> Result<()> {
+        match self.audio.get_mut(track) {
+            Some(audio) => unsafe { audio.append(self.fmt_ctx, timestamp, samples) },
+            None => Ok(()),
+        }
+    }
+
+    fn add_frame(&mut self, rgba_data: &[u8], width: u32, height: u32, pts: i64) -> Result<()> {
         unsafe {
             // Format conversion from RGBA to YUV420P
             let src_slice = [rgba_data.as_ptr()];
@@ -189,16 +275,16 @@ impl VideoEncoder {
             );
 
             if result < 0 {
-                return Err("Failed to convert image format".into());
+                return Err(XlabError::Encode("Failed to convert image format".into()));
             }
 
             // Set frame properties
-            (*self.frame).pts = frame_index as i64;
+            (*self.frame).pts = pts;
 
             // Send frame to encoder
             let send_result = avcodec_send_frame(self.codec_ctx, self.frame);
             if send_result < 0 {
-                return Err("Failed to send frame to encoder".into());
+                return Err(XlabError::Encode("Failed to send frame to encoder".into()));
             }
 
             // Process packets
@@ -208,7 +294,7 @@ impl VideoEncoder {
 
                 let write_result = av_interleaved_write_frame(self.fmt_ctx, self.packet);
                 if write_result < 0 {
-                    return Err("Failed to write frame".into());
+                    return Err(XlabError::Encode("Failed to write frame".into()));
                 }
 
                 av_packet_unref(self.packet);
@@ -218,7 +304,7 @@ impl VideoEncoder {
         }
     }
 
-    pub fn finalize(self) -> Result<(), String> {
+    pub fn finalize(mut self) -> Result<()> {
         unsafe {
             // Flush encoder
             avcodec_send_frame(self.codec_ctx, ptr::null_mut());
@@ -228,6 +314,9 @@ impl VideoEncoder {
                 av_interleaved_write_frame(self.fmt_ctx, self.packet);
                 av_packet_unref(self.packet);
             }
+            for audio in &mut self.audio {
+                audio.finish(self.fmt_ctx)?;
+            }
 
             av_write_trailer(self.fmt_ctx);
         }
@@ -237,20 +326,93 @@ impl VideoEncoder {
 
 impl Drop for VideoEncoder {
     fn drop(&mut self) {
+        // The audio encoders go first, their streams belong to the format context
+        self.audio.clear();
         unsafe {
             // Free all allocated resources in reverse allocation order
             av_packet_free(&mut self.packet);
             av_frame_free(&mut self.frame);
             sws_freeContext(self.sws_ctx);
             avcodec_free_context(&mut self.codec_ctx);
-            if !(*self.fmt_ctx).pb.is_null() {
+            if !self.fmt_ctx.is_null() {
                 avio_closep(&mut (*self.fmt_ctx).pb);
+                avformat_free_context(self.fmt_ctx);
             }
-            avformat_free_context(self.fmt_ctx);
         }
     }
 }
 
-fn path_to_cstring(path: &PathBuf) -> CString {
-    CString::new(path.to_str().unwrap()).expect("Invalid path")
+pub(crate) fn path_to_cstring(path: &Path) -> Result<CString> {
+    path.to_str()
+        .and_then(|path| CString::new(path).ok())
+        .ok_or_else(|| XlabError::Config(format!("invalid file path {}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use xcap::image::Rgba;
+
+    use super::*;
+
+    /// Presentation times of the packets of the video at `path` from the first one, in
+    /// order, along with the duration of a tick of its time base
+    fn packet_times(path: &Path) -> (Vec<Duration>, Duration) {
+        let path_c = path_to_cstring(path).unwrap();
+        unsafe {
+            let mut fmt_ctx = ptr::null_mut();
+            let opened =
+                avformat_open_input(&mut fmt_ctx, path_c.as_ptr(), ptr::null(), ptr::null_mut());
+            assert!(opened >= 0);
+            assert!(avformat_find_stream_info(fmt_ctx, ptr::null_mut()) >= 0);
+            let time_base = (**(*fmt_ctx).streams).time_base;
+            let mut packet = av_packet_alloc();
+            let mut pts = Vec::new();
+            while av_read_frame(fmt_ctx, packet) >= 0 {
+                pts.push((*packet).pts);
+                av_packet_unref(packet);
+            }
+            av_packet_free(&mut packet);
+            avformat_close_input(&mut fmt_ctx);
+
+            // Packets are stored in decoding order
+            pts.sort_unstable();
+            let to_micros = |pts: i64| av_rescale_q(pts, time_base, TIME_BASE) as u64;
+            let times = pts
+                .iter()
+                .map(|&time| Duration::from_micros(to_micros(time - pts[0])))
+                .collect();
+            (times, Duration::from_micros(to_micros(1).max(1)))
+        }
+    }
+
+    #[test]
+    fn frames_keep_their_timestamps() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("uneven_timestamps.mp4");
+        let timestamps = [0, 33_000, 41_500, 120_250, 121_000].map(Duration::from_micros);
+
+        let mut encoder =
+            VideoEncoder::new(path.clone(), 30, (64, 36), EncoderConfig::default()).unwrap();
+        for (i, &timestamp) in timestamps.iter().enumerate() {
+            let image = RgbaImage::from_pixel(64, 36, Rgba([i as u8 * 50, 0, 0, 255]));
+            encoder.append_image(image, timestamp).unwrap();
+        }
+        assert_eq!(encoder.last_pts, Some(121_000));
+        // A frame with the timestamp of the previous one still gets a later pts
+        let image = RgbaImage::from_pixel(64, 36, Rgba([0, 0, 255, 255]));
+        encoder.append_image(image, timestamps[4]).unwrap();
+        assert_eq!(encoder.last_pts, Some(121_001));
+        encoder.finalize().unwrap();
+
+        let (times, tick) = packet_times(&path);
+        assert_eq!(times.len(), timestamps.len() + 1);
+        for (time, expected) in times.iter().zip(timestamps) {
+            assert!(
+                time.abs_diff(expected) <= tick,
+                "{time:?} is more than a tick of {tick:?} from {expected:?}"
+            );
+        }
+        assert!(times.windows(2).all(|pair| pair[0] < pair[1]));
+    }
 }
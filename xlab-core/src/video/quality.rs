@@ -0,0 +1,379 @@
+use ffmpeg_sys_next::{AVColorRange, AVPixelFormat};
+
+use super::VideoCodec;
+use crate::{Result, XlabError};
+
+/// x264 preset names from fastest to slowest; other encoders map them onto their own
+/// speed settings by position
+pub(crate) const PRESETS: [&str; 10] = [
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+    "placebo",
+];
+
+/// Highest CRF of x264 and x265, the scale of [`QualitySettings::crf`]
+pub(crate) const MAX_X264_CRF: u8 = 51;
+
+/// Starting points for the quality settings, for users who don't want to tune them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum QualityPreset {
+    /// Visibly compressed, for sharing where size matters
+    Small,
+    /// Hard to tell apart from the screen at a moderate size
+    #[default]
+    Balanced,
+    /// Keeps every pixel the encoder receives, at a very large size
+    Lossless,
+}
+
+impl QualityPreset {
+    pub const ALL: [QualityPreset; 3] = [Self::Small, Self::Balanced, Self::Lossless];
+
+    pub fn settings(self) -> QualitySettings {
+        let crf = match self {
+            Self::Small => 28,
+            Self::Balanced => 18,
+            Self::Lossless => 0,
+        };
+        QualitySettings {
+            crf,
+            ..Default::default()
+        }
+    }
+}
+
+/// Content the encoder is tuned for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Tune {
+    /// Slides and mostly static screens
+    StillImage,
+    /// Flat colored content such as user interfaces and cartoons
+    Animation,
+}
+
+impl std::fmt::Display for Tune {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::StillImage => "stillimage",
+            Self::Animation => "animation",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How much color detail the video keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ChromaSubsampling {
+    /// Color is stored for every 2x2 block of pixels, which every player supports
+    #[default]
+    Yuv420,
+    /// Color is stored for every pixel, so colored text and thin lines stay sharp
+    Yuv444,
+}
+
+impl ChromaSubsampling {
+    pub(crate) fn pix_fmt(self) -> AVPixelFormat {
+        match self {
+            Self::Yuv420 => AVPixelFormat::AV_PIX_FMT_YUV420P,
+            Self::Yuv444 => AVPixelFormat::AV_PIX_FMT_YUV444P,
+        }
+    }
+}
+
+impl std::fmt::Display for ChromaSubsampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Yuv420 => "4:2:0",
+            Self::Yuv444 => "4:4:4",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Range of the encoded luma and chroma values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ColorRange {
+    /// 16 to 235, what players expect unless told otherwise
+    #[default]
+    Limited,
+    /// 0 to 255, keeps every shade of the screen but some players show it washed out
+    Full,
+}
+
+impl ColorRange {
+    pub(crate) fn av_range(self) -> AVColorRange {
+        match self {
+            Self::Limited => AVColorRange::AVCOL_RANGE_MPEG,
+            Self::Full => AVColorRange::AVCOL_RANGE_JPEG,
+        }
+    }
+}
+
+/// Encoder quality settings. CRF, preset and tune follow x264 and are mapped onto the
+/// settings of the other encoders.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct QualitySettings {
+    /// Constant rate factor from 0, lossless, to 51, smallest. Unused when `bitrate` is set.
+    pub crf: u8,
+    /// Name of an x264 preset, slower presets make smaller files
+    pub preset: String,
+    /// Constant bitrate in kbit/s, replacing the constant rate factor
+    pub bitrate: Option<u32>,
+    /// Bitrate in kbit/s the video never goes above
+    pub max_bitrate: Option<u32>,
+    /// Most frames between two keyframes, `None` leaves it to the encoder
+    pub keyframe_interval: Option<u32>,
+    pub tune: Option<Tune>,
+    /// Codec profile, such as "high" for H.264 or "0" for VP9
+    pub profile: Option<String>,
+    /// Codec level, such as "4.1"
+    pub level: Option<String>,
+    pub chroma: ChromaSubsampling,
+    pub color_range: ColorRange,
+}
+
+impl Default for QualitySettings {
+    fn default() -> Self {
+        Self {
+            crf: 18,
+            preset: "medium".to_owned(),
+            bitrate: None,
+            max_bitrate: None,
+            keyframe_interval: None,
+            tune: None,
+            profile: None,
+            level: None,
+            chroma: ChromaSubsampling::default(),
+            color_range: ColorRange::default(),
+        }
+    }
+}
+
+impl QualitySettings {
+    /// Returns the preset these settings match, if any
+    pub fn preset(&self) -> Option<QualityPreset> {
+        QualityPreset::ALL
+            .into_iter()
+            .find(|preset| preset.settings() == *self)
+    }
+
+    /// Fails unless every setting is valid and supported by `codec`
+    pub fn validate(&self, codec: VideoCodec) -> Result<()> {
+        if self.crf > MAX_X264_CRF {
+            return Err(XlabError::Config(format!(
+                "CRF must be between 0 and {MAX_X264_CRF}"
+            )));
+        }
+        preset_speed(&self.preset)?;
+        if self.bitrate == Some(0) || self.max_bitrate == Some(0) {
+            return Err(XlabError::Config("bitrates must be positive".into()));
+        }
+        if let (Some(bitrate), Some(max_bitrate)) = (self.bitrate, self.max_bitrate) {
+            if max_bitrate < bitrate {
+                return Err(XlabError::Config(
+                    "the maximum bitrate can't be below the bitrate".into(),
+                ));
+            }
+        }
+        if self.keyframe_interval == Some(0) {
+            return Err(XlabError::Config(
+                "the keyframe interval must be positive".into(),
+            ));
+        }
+        if let Some(tune) = self.tune {
+            let supported = match codec {
+                VideoCodec::H264 => true,
+                VideoCodec::H265 => tune == Tune::Animation,
+                VideoCodec::Vp9 | VideoCodec::Av1 => false,
+            };
+            if !supported {
+                return Err(XlabError::Config(format!(
+                    "{codec} encoders can't be tuned for {tune}"
+                )));
+            }
+        }
+        if let Some(profile) = &self.profile {
+            if !profiles(codec).contains(&profile.as_str()) {
+                return Err(XlabError::Config(format!(
+                    "{profile} is not a {codec} profile"
+                )));
+            }
+            if !profile_supports(codec, profile, self.chroma) {
+                return Err(XlabError::Config(format!(
+                    "the {profile} profile of {codec} can't encode {} video",
+                    self.chroma
+                )));
+            }
+            // x264 only encodes lossless video in its 4:4:4 profile
+            if codec == VideoCodec::H264 && self.is_lossless() && profile != "high444" {
+                return Err(XlabError::Config(
+                    "lossless H.264 needs the high444 profile".into(),
+                ));
+            }
+        }
+        if let Some(level) = &self.level {
+            level_number(codec, level)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the settings ask for lossless video
+    pub(crate) fn is_lossless(&self) -> bool {
+        self.crf == 0 && self.bitrate.is_none()
+    }
+}
+
+/// Position of `preset` in [`PRESETS`]
+pub(crate) fn preset_speed(preset: &str) -> Result<usize> {
+    PRESETS
+        .iter()
+        .position(|name| *name == preset)
+        .ok_or_else(|| XlabError::Config(format!("unknown encoder preset {preset}")))
+}
+
+/// Profiles that can be requested for `codec`
+fn profiles(codec: VideoCodec) -> &'static [&'static str] {
+    match codec {
+        VideoCodec::H264 => &["baseline", "main", "high", "high10", "high422", "high444"],
+        VideoCodec::H265 => &[
+            "main",
+            "main10",
+            "mainstillpicture",
+            "main422-10",
+            "main444-8",
+            "main444-10",
+        ],
+        VideoCodec::Vp9 => &["0", "1", "2", "3"],
+        VideoCodec::Av1 => &["main", "high", "professional"],
+    }
+}
+
+/// Whether `profile` of `codec` can encode 8-bit video with `chroma` subsampling
+fn profile_supports(codec: VideoCodec, profile: &str, chroma: ChromaSubsampling) -> bool {
+    match (codec, chroma) {
+        (VideoCodec::H264, ChromaSubsampling::Yuv420) => true,
+        (VideoCodec::H264, ChromaSubsampling::Yuv444) => profile == "high444",
+        (VideoCodec::H265, ChromaSubsampling::Yuv420) => {
+            !profile.contains("444") && !profile.contains("422")
+        }
+        (VideoCodec::H265, ChromaSubsampling::Yuv444) => profile.starts_with("main444"),
+        // VP9 profiles 2 and 3 are the high bit depth versions of 0 and 1
+        (VideoCodec::Vp9, ChromaSubsampling::Yuv420) => profile == "0",
+        (VideoCodec::Vp9, ChromaSubsampling::Yuv444) => profile == "1",
+        (VideoCodec::Av1, ChromaSubsampling::Yuv420) => true,
+        (VideoCodec::Av1, ChromaSubsampling::Yuv444) => profile != "main",
+    }
+}
+
+/// Profile number ffmpeg uses for `profile`, for encoders that take profiles as numbers
+pub(crate) fn profile_number(codec: VideoCodec, profile: &str) -> Option<i32> {
+    match codec {
+        VideoCodec::H264 | VideoCodec::H265 => None,
+        VideoCodec::Vp9 | VideoCodec::Av1 => profiles(codec)
+            .iter()
+            .position(|name| *name == profile)
+            .map(|position| position as i32),
+    }
+}
+
+/// Parses a level such as "4.1" into the number the codec identifies it with
+pub(crate) fn level_number(codec: VideoCodec, level: &str) -> Result<i32> {
+    let invalid = || XlabError::Config(format!("{level} is not a {codec} level"));
+    // Level 1b sits between 1 and 1.1, and is identified as 9
+    if codec == VideoCodec::H264 && level == "1b" {
+        return Ok(9);
+    }
+    let (major, minor) = level.split_once('.').unwrap_or((level, "0"));
+    let major: i32 = major.parse().map_err(|_| invalid())?;
+    let minor: i32 = minor.parse().map_err(|_| invalid())?;
+    let (number, valid) = match codec {
+        // H.264 and H.265 number their levels ten times the level
+        VideoCodec::H264 => (
+            major * 10 + minor,
+            matches!((major, minor), (1, 0..=3) | (2..=6, 0..=2)),
+        ),
+        // H.265 has no minor levels below 2.1, and only from 5 up is there an x.2
+        VideoCodec::H265 => (
+            major * 10 + minor,
+            matches!((major, minor), (1, 0) | (2..=4, 0..=1) | (5..=6, 0..=2)),
+        ),
+        // AV1 numbers its levels from 2.0 with four minor levels each
+        VideoCodec::Av1 => (
+            (major - 2) * 4 + minor,
+            (2..=7).contains(&major) && minor <= 3,
+        ),
+        VideoCodec::Vp9 => return Err(XlabError::Config("VP9 encoders don't take a level".into())),
+    };
+    if !valid || minor < 0 {
+        return Err(invalid());
+    }
+    Ok(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_settings_validation() {
+        for preset in QualityPreset::ALL {
+            assert_eq!(preset.settings().preset(), Some(preset));
+            for codec in VideoCodec::ALL {
+                preset.settings().validate(codec).unwrap();
+            }
+        }
+        let quality = QualitySettings {
+            bitrate: Some(4000),
+            max_bitrate: Some(2000),
+            ..Default::default()
+        };
+        assert!(quality.validate(VideoCodec::H264).is_err());
+        let quality = QualitySettings {
+            tune: Some(Tune::StillImage),
+            level: Some("4.1".to_owned()),
+            ..Default::default()
+        };
+        quality.validate(VideoCodec::H264).unwrap();
+        assert!(quality.validate(VideoCodec::H265).is_err());
+        let quality = QualitySettings {
+            profile: Some("high".to_owned()),
+            ..QualityPreset::Lossless.settings()
+        };
+        assert!(quality.validate(VideoCodec::H264).is_err());
+        assert!(quality.validate(VideoCodec::Vp9).is_err());
+        let quality = QualitySettings {
+            profile: Some("high444".to_owned()),
+            chroma: ChromaSubsampling::Yuv444,
+            ..Default::default()
+        };
+        quality.validate(VideoCodec::H264).unwrap();
+        assert!(quality.validate(VideoCodec::H265).is_err());
+    }
+
+    #[test]
+    fn levels() {
+        for (level, number) in [("1", 10), ("1b", 9), ("1.3", 13), ("5.2", 52), ("6.2", 62)] {
+            assert_eq!(level_number(VideoCodec::H264, level).unwrap(), number);
+        }
+        for level in ["0.9", "1.4", "2.3", "6.3", "7", "1c", "-1", "4.-1"] {
+            assert!(level_number(VideoCodec::H264, level).is_err(), "{level}");
+        }
+        for (level, valid) in [("1", true), ("1.1", false), ("4.1", true), ("4.2", false)] {
+            let quality = QualitySettings {
+                level: Some(level.to_owned()),
+                ..Default::default()
+            };
+            assert_eq!(quality.validate(VideoCodec::H265).is_ok(), valid, "{level}");
+        }
+        assert_eq!(level_number(VideoCodec::Av1, "5.1").unwrap(), 13);
+        assert!(level_number(VideoCodec::Vp9, "1").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! Zooming in on the cursor, so that small text stays readable in recordings that are
+//! scaled down. The view zooms in when the cursor rests or clicks, follows it while
+//! zoomed in and zooms out again once it moves on.
+
+use std::time::Duration;
+
+use crate::{capture::CaptureRegion, Result, XlabError};
+
+/// Largest zoom factor of [`ZoomSettings::scale`]
+pub const MAX_ZOOM: f32 = 8.0;
+
+/// Share of the frame width the cursor may move within while still resting
+const REST_DISTANCE: f32 = 0.01;
+
+/// How the view zooms in on the cursor
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ZoomSettings {
+    /// Factor the view zooms in by, 2.0 shows a quarter of the frame
+    pub scale: f32,
+    /// How long the cursor has to rest before the view zooms in
+    pub dwell: Duration,
+    /// How long the view stays zoomed in after the cursor last rested or clicked
+    pub hold: Duration,
+    /// How long zooming in or out takes
+    pub transition: Duration,
+    /// Whether clicks zoom in right away
+    pub on_click: bool,
+}
+
+impl Default for ZoomSettings {
+    fn default() -> Self {
+        Self {
+            scale: 2.0,
+            dwell: Duration::from_secs(1),
+            hold: Duration::from_secs(2),
+            transition: Duration::from_millis(400),
+            on_click: true,
+        }
+    }
+}
+
+impl ZoomSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.scale > 1.0 && self.scale <= MAX_ZOOM) {
+            return Err(XlabError::Config(format!(
+                "zoom must be above 1 and at most {MAX_ZOOM}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Works out the part of each frame that is shown from the cursor in it
+pub(crate) struct AutoZoom {
+    settings: ZoomSettings,
+    dimensions: (f32, f32),
+    /// Where the cursor started resting, and when
+    rest: Option<((f32, f32), Duration)>,
+    /// Time the view zooms out again at
+    zoomed_until: Duration,
+    /// How far zooming in has got, from 0 to 1
+    progress: f32,
+    /// Center of the view, which trails the cursor
+    center: (f32, f32),
+    last_update: Option<Duration>,
+}
+
+impl AutoZoom {
+    pub(crate) fn new(settings: ZoomSettings, (width, height): (u32, u32)) -> Self {
+        let dimensions = (width as f32, height as f32);
+        Self {
+            settings,
+            dimensions,
+            rest: None,
+            zoomed_until: Duration::ZERO,
+            progress: 0.0,
+            center: (dimensions.0 / 2.0, dimensions.1 / 2.0),
+            last_update: None,
+        }
+    }
+
+    /// Returns the part of the frame at `timestamp` to show, given the cursor in it.
+    /// Frames must be passed in order.
+    pub(crate) fn update(
+        &mut self,
+        timestamp: Duration,
+        position: Option<(u32, u32)>,
+        pressed: bool,
+    ) -> CaptureRegion {
+        let elapsed = self
+            .last_update
+            .map_or(Duration::ZERO, |last| timestamp.saturating_sub(last));
+        self.last_update = Some(timestamp);
+        let position = position.map(|(x, y)| (x as f32, y as f32));
+
+        // 1. Zoom in on clicks and on a cursor that rests long enough
+        match (position, self.rest) {
+            (Some(position), Some((anchor, since)))
+                if distance(position, anchor) <= REST_DISTANCE * self.dimensions.0 =>
+            {
+                if timestamp.saturating_sub(since) >= self.settings.dwell {
+                    self.zoomed_until = timestamp + self.settings.hold;
+                }
+            }
+            (Some(position), _) => self.rest = Some((position, timestamp)),
+            (None, _) => self.rest = None,
+        }
+        if pressed && self.settings.on_click && position.is_some() {
+            self.zoomed_until = timestamp + self.settings.hold;
+        }
+
+        // 2. Move towards the target zoom at a steady pace, eased when applied
+        let step = match self.settings.transition.is_zero() {
+            true => 1.0,
+            false => elapsed.as_secs_f32() / self.settings.transition.as_secs_f32(),
+        };
+        self.progress = match timestamp < self.zoomed_until {
+            true => (self.progress + step).min(1.0),
+            false => (self.progress - step).max(0.0),
+        };
+        let eased = self.progress * self.progress * (3.0 - 2.0 * self.progress);
+        let zoom = 1.0 + (self.settings.scale - 1.0) * eased;
+
+        // 3. Trail the cursor, catching up within about one transition
+        if let Some(position) = position {
+            let follow = match self.settings.transition.is_zero() {
+                true => 1.0,
+                false => 1.0 - (-step * 3.0).exp(),
+            };
+            self.center.0 += (position.0 - self.center.0) * follow;
+            self.center.1 += (position.1 - self.center.1) * follow;
+        }
+        self.view(zoom)
+    }
+
+    /// The part of the frame around the center that is `zoom` times smaller than it
+    fn view(&self, zoom: f32) -> CaptureRegion {
+        let (frame_width, frame_height) = self.dimensions;
+        let width = (frame_width / zoom).round().clamp(1.0, frame_width);
+        let height = (frame_height / zoom).round().clamp(1.0, frame_height);
+        let x = (self.center.0 - width / 2.0).clamp(0.0, frame_width - width);
+        let y = (self.center.1 - height / 2.0).clamp(0.0, frame_height - height);
+        CaptureRegion::new(x as u32, y as u32, width as u32, height as u32)
+    }
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_zoom_follows_cursor() {
+        let settings = ZoomSettings::default();
+        let frame = Duration::from_millis(100);
+        let mut zoom = AutoZoom::new(settings, (1600, 900));
+        // A moving cursor shows the whole frame
+        for i in 0..10 {
+            let view = zoom.update(frame * i, Some((100 + i * 100, 450)), false);
+            assert_eq!(view.dimensions(), (1600, 900));
+        }
+        // A resting cursor is zoomed in on once it rested long enough
+        let rested = frame * 10 + settings.dwell + settings.transition;
+        let mut time = frame * 10;
+        let mut view = zoom.update(time, Some((1200, 300)), false);
+        while time < rested {
+            time += frame;
+            view = zoom.update(time, Some((1200, 300)), false);
+        }
+        assert_eq!(view.dimensions(), (800, 450));
+        assert!(view.translate((1200, 300)).is_some());
+
+        // Clicks zoom in right away, and the view zooms out once the cursor moves on
+        let mut zoom = AutoZoom::new(settings, (1600, 900));
+        zoom.update(Duration::ZERO, Some((10, 10)), true);
+        view = zoom.update(settings.transition, Some((10, 10)), false);
+        assert_eq!(view.dimensions(), (800, 450));
+        let zoomed_out = settings.hold + settings.transition;
+        let mut time = settings.transition;
+        let mut position = 10;
+        while time <= zoomed_out {
+            time += frame;
+            position += 50;
+            view = zoom.update(time, Some((position, 10)), false);
+        }
+        assert_eq!(view.dimensions(), (1600, 900));
+        assert!(ZoomSettings {
+            scale: 1.0,
+            ..settings
+        }
+        .validate()
+        .is_err());
+    }
+}